@@ -0,0 +1,159 @@
+//! Handshake and throughput benchmarks, run with `cargo bench --features bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures_util::future;
+use openssl::ssl::{Ssl, SslAcceptor, SslConnector, SslFiletype, SslMethod, SslVersion};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::runtime::Runtime;
+use tokio_openssl::SslStream;
+
+const CHUNK_LEN: usize = 16 * 1024;
+const BULK_LEN: usize = 100 * 1024 * 1024;
+const INTERLEAVED_LEN: usize = 16 * 1024 * 1024;
+
+// The protocol version and cipher suite are fixed so that results are comparable across OpenSSL
+// versions and their defaults.
+fn contexts() -> (SslConnector, SslAcceptor) {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor
+        .set_private_key_file("tests/key.pem", SslFiletype::PEM)
+        .unwrap();
+    acceptor
+        .set_certificate_chain_file("tests/cert.pem")
+        .unwrap();
+    acceptor
+        .set_min_proto_version(Some(SslVersion::TLS1_3))
+        .unwrap();
+    acceptor.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_ca_file("tests/cert.pem").unwrap();
+    connector
+        .set_min_proto_version(Some(SslVersion::TLS1_3))
+        .unwrap();
+    connector
+        .set_ciphersuites("TLS_AES_128_GCM_SHA256")
+        .unwrap();
+
+    (connector.build(), acceptor.build())
+}
+
+async fn handshake(
+    connector: &SslConnector,
+    acceptor: &SslAcceptor,
+) -> (SslStream<DuplexStream>, SslStream<DuplexStream>) {
+    let client = connector
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    let server = Ssl::new(acceptor.context()).unwrap();
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+    let mut client = SslStream::new(client, client_stream).unwrap();
+    let mut server = SslStream::new(server, server_stream).unwrap();
+
+    let (r1, r2) = future::join(
+        Pin::new(&mut client).connect(),
+        Pin::new(&mut server).accept(),
+    )
+    .await;
+    r1.unwrap();
+    r2.unwrap();
+    (client, server)
+}
+
+async fn bulk_write(client: &mut SslStream<DuplexStream>, server: &mut SslStream<DuplexStream>) {
+    let chunk = vec![0; CHUNK_LEN];
+
+    let write = async {
+        for _ in 0..BULK_LEN / CHUNK_LEN {
+            client.write_all(&chunk).await.unwrap();
+        }
+        client.flush().await.unwrap();
+    };
+    let read = async {
+        let mut buf = vec![0; CHUNK_LEN];
+        let mut remaining = BULK_LEN;
+        while remaining > 0 {
+            let n = server.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0);
+            remaining -= n;
+        }
+    };
+
+    future::join(write, read).await;
+}
+
+async fn interleaved(client: &mut SslStream<DuplexStream>, server: &mut SslStream<DuplexStream>) {
+    let echo = async {
+        let mut buf = vec![0; CHUNK_LEN];
+        for _ in 0..INTERLEAVED_LEN / CHUNK_LEN {
+            server.read_exact(&mut buf).await.unwrap();
+            server.write_all(&buf).await.unwrap();
+        }
+    };
+    let ping = async {
+        let chunk = vec![0; CHUNK_LEN];
+        let mut buf = vec![0; CHUNK_LEN];
+        for _ in 0..INTERLEAVED_LEN / CHUNK_LEN {
+            client.write_all(&chunk).await.unwrap();
+            client.read_exact(&mut buf).await.unwrap();
+        }
+    };
+
+    future::join(echo, ping).await;
+}
+
+fn benches(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (connector, acceptor) = contexts();
+
+    c.bench_function("handshake", |b| {
+        b.to_async(&rt).iter(|| handshake(&connector, &acceptor))
+    });
+
+    let mut group = c.benchmark_group("throughput");
+    group.sample_size(10);
+
+    // only the transfer is measured, not the handshake
+    group.throughput(Throughput::Bytes(BULK_LEN as u64));
+    group.bench_function("bulk_write", |b| {
+        b.to_async(&rt).iter_custom(|iters| {
+            let (connector, acceptor) = (&connector, &acceptor);
+            async move {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let (mut client, mut server) = handshake(connector, acceptor).await;
+                    let start = Instant::now();
+                    bulk_write(&mut client, &mut server).await;
+                    total += start.elapsed();
+                }
+                total
+            }
+        })
+    });
+
+    group.throughput(Throughput::Bytes(2 * INTERLEAVED_LEN as u64));
+    group.bench_function("interleaved", |b| {
+        b.to_async(&rt).iter_custom(|iters| {
+            let (connector, acceptor) = (&connector, &acceptor);
+            async move {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let (mut client, mut server) = handshake(connector, acceptor).await;
+                    let start = Instant::now();
+                    interleaved(&mut client, &mut server).await;
+                    total += start.elapsed();
+                }
+                total
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(ssl_stream, benches);
+criterion_main!(ssl_stream);
@@ -0,0 +1,32 @@
+//! Feeds fuzzed bytes to the server side of a handshake as if they were sent by a client.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use openssl::pkey::PKey;
+use openssl::ssl::{Ssl, SslAcceptor, SslMethod};
+use openssl::x509::X509;
+use std::pin::Pin;
+use tokio::io::AsyncWriteExt;
+use tokio_openssl::SslStream;
+
+fuzz_target!(|data: &[u8]| {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    let key = PKey::private_key_from_pem(include_bytes!("../../tests/key.pem")).unwrap();
+    let cert = X509::from_pem(include_bytes!("../../tests/cert.pem")).unwrap();
+    acceptor.set_private_key(&key).unwrap();
+    acceptor.set_certificate(&cert).unwrap();
+    let acceptor = acceptor.build();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let (stream, mut peer) = tokio::io::duplex(data.len() + 64 * 1024);
+        peer.write_all(data).await.unwrap();
+        peer.shutdown().await.unwrap();
+
+        let ssl = Ssl::new(acceptor.context()).unwrap();
+        let mut stream = SslStream::new(ssl, stream).unwrap();
+        let _ = Pin::new(&mut stream).accept().await;
+    });
+});
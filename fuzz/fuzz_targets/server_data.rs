@@ -0,0 +1,32 @@
+//! Feeds fuzzed bytes to the client side of a handshake as if they were sent by a server.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use openssl::ssl::{SslConnector, SslMethod};
+use std::pin::Pin;
+use tokio::io::AsyncWriteExt;
+use tokio_openssl::SslStream;
+
+fuzz_target!(|data: &[u8]| {
+    let ssl = SslConnector::builder(SslMethod::tls())
+        .unwrap()
+        .build()
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        // the peer is kept alive so the ClientHello can be written to it
+        let (stream, mut peer) = tokio::io::duplex(data.len() + 64 * 1024);
+        peer.write_all(data).await.unwrap();
+        peer.shutdown().await.unwrap();
+
+        let mut stream = SslStream::new(ssl, stream).unwrap();
+        let _ = Pin::new(&mut stream).connect().await;
+        drop(peer);
+    });
+});
@@ -0,0 +1,300 @@
+#[cfg(ossl111)]
+use crate::client_hello::ClientHello;
+use crate::verify::VerifyCallback;
+use crate::{Error, HandshakeError, SslStream, VerifyFuture};
+use openssl::error::ErrorStack;
+#[cfg(ossl111)]
+use openssl::ssl::{self, ErrorCode, SslContext};
+use openssl::ssl::{Ssl, SslAcceptor, SslAcceptorBuilder, SslVerifyMode};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509Name, X509};
+#[cfg(ossl111)]
+use std::future::Future;
+#[cfg(ossl111)]
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The future returned by an asynchronous SNI callback.
+#[cfg(ossl111)]
+pub type SniFuture = Pin<Box<dyn Future<Output = Result<SslContext, ssl::Error>> + Send>>;
+
+// returns `None` to continue the handshake with the acceptor's own context
+#[cfg(ossl111)]
+type SniCallback = dyn Fn(ClientHello) -> Option<SniFuture> + Send + Sync;
+
+/// A builder for [`SslStreamAcceptor`]s.
+///
+/// This dereferences to the wrapped [`SslAcceptorBuilder`], so it can be configured in the same
+/// way.
+pub struct SslStreamAcceptorBuilder {
+    builder: SslAcceptorBuilder,
+    #[cfg(ossl111)]
+    sni: Option<Arc<SniCallback>>,
+    post_handshake_verify: Option<Arc<VerifyCallback>>,
+}
+
+impl SslStreamAcceptorBuilder {
+    /// Creates a new builder wrapping an [`SslAcceptorBuilder`].
+    pub fn new(builder: SslAcceptorBuilder) -> SslStreamAcceptorBuilder {
+        SslStreamAcceptorBuilder {
+            builder,
+            #[cfg(ossl111)]
+            sni: None,
+            post_handshake_verify: None,
+        }
+    }
+
+    /// Requires clients to present a certificate issued by one of the CAs in the PEM file at
+    /// `ca_file`.
+    ///
+    /// The CAs are trusted for client certificate verification and their names are sent to
+    /// clients in the certificate request. The verify mode is set to
+    /// [`PEER`](SslVerifyMode::PEER) and [`FAIL_IF_NO_PEER_CERT`](SslVerifyMode::FAIL_IF_NO_PEER_CERT),
+    /// so handshakes with clients which present no certificate or one which fails verification
+    /// are aborted. The maximum length of client certificate chains can be adjusted with
+    /// [`set_verify_depth`](openssl::ssl::SslContextBuilder::set_verify_depth).
+    ///
+    /// Once the handshake completes, the validated certificate is available from
+    /// [`SslStream::peer_certificate`].
+    pub fn require_client_certificates<P>(&mut self, ca_file: P) -> Result<(), ErrorStack>
+    where
+        P: AsRef<Path>,
+    {
+        let ca_file = ca_file.as_ref();
+        self.builder.set_ca_file(ca_file)?;
+        self.builder
+            .set_client_ca_list(X509Name::load_client_ca_file(ca_file)?);
+        self.builder
+            .set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        Ok(())
+    }
+
+    /// Like [`require_client_certificates`](Self::require_client_certificates), but trusts the
+    /// CA certificates `cas` instead of those of a file.
+    ///
+    /// Unlike `require_client_certificates`, the CAs are used exclusively for client
+    /// certificate verification, separately from the certificate store of the context.
+    pub fn require_client_certificates_from(&mut self, cas: &[X509]) -> Result<(), ErrorStack> {
+        let mut store = X509StoreBuilder::new()?;
+        for ca in cas {
+            store.add_cert(ca.clone())?;
+            self.builder.add_client_ca(ca)?;
+        }
+        self.builder.set_verify_cert_store(store.build())?;
+        self.builder
+            .set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        Ok(())
+    }
+
+    /// Sets a callback used to asynchronously select the [`SslContext`] for a connection based on
+    /// the server name indication sent by the client.
+    ///
+    /// The handshake is paused in OpenSSL's client hello callback, and the future returned by the
+    /// callback is awaited by [`SslStreamAcceptor::accept`] without blocking the runtime. The
+    /// resolved context's certificate, key and verification settings are then used for the rest
+    /// of the handshake. If
+    /// the client does not send a host name, the callback is not invoked and the acceptor's own
+    /// context is used.
+    ///
+    /// This replaces any client hello callback previously configured on the builder.
+    ///
+    /// Requires OpenSSL 1.1.1 or newer.
+    #[cfg(ossl111)]
+    pub fn set_async_sni_callback<F>(&mut self, callback: F) -> Result<(), ErrorStack>
+    where
+        F: Fn(String) -> SniFuture + 'static + Send + Sync,
+    {
+        self.set_sni_callback(move |hello: ClientHello| {
+            hello.server_name().map(|name| callback(name.to_string()))
+        })
+    }
+
+    /// Like [`set_async_sni_callback`](Self::set_async_sni_callback), but the callback receives
+    /// the parsed [`ClientHello`], including the ALPN protocols offered by the client, and is
+    /// invoked whether or not the client sent a host name.
+    ///
+    /// This replaces any client hello callback previously configured on the builder.
+    ///
+    /// Requires OpenSSL 1.1.1 or newer.
+    #[cfg(ossl111)]
+    pub fn set_async_client_hello_callback<F>(&mut self, callback: F) -> Result<(), ErrorStack>
+    where
+        F: Fn(ClientHello) -> SniFuture + 'static + Send + Sync,
+    {
+        self.set_sni_callback(move |hello| Some(callback(hello)))
+    }
+
+    #[cfg(ossl111)]
+    fn set_sni_callback<F>(&mut self, callback: F) -> Result<(), ErrorStack>
+    where
+        F: Fn(ClientHello) -> Option<SniFuture> + 'static + Send + Sync,
+    {
+        crate::client_hello::pause_on_client_hello(&mut self.builder)?;
+        self.sni = Some(Arc::new(callback));
+        Ok(())
+    }
+
+    /// Appends the secrets of every connection to the file at `path`; see [`crate::keylog`].
+    #[cfg(ossl111)]
+    pub fn set_keylog_file<P>(&mut self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        crate::keylog::set_keylog_file(&mut self.builder, path.as_ref())
+    }
+
+    /// Like [`set_keylog_file`](Self::set_keylog_file), but writes to the file named by
+    /// `SSLKEYLOGFILE`; see [`keylog::set_keylog_from_env`](crate::keylog::set_keylog_from_env).
+    #[cfg(ossl111)]
+    pub fn set_keylog_from_env(&mut self) -> io::Result<()> {
+        crate::keylog::set_keylog_from_env(&mut self.builder)
+    }
+
+    /// Configures the server to select the application protocol via ALPN from a preference list.
+    ///
+    /// The first of `protocols` which the client also offers is selected, so the server's order of
+    /// preference wins. If the client offers none of them, the handshake continues without an
+    /// application protocol.
+    ///
+    /// This replaces any ALPN selection callback previously configured on the builder.
+    pub fn set_alpn_preferences<'a, I>(&mut self, protocols: I)
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let protocols = protocols.into_iter().map(|p| p.to_vec()).collect();
+        crate::alpn::set_alpn_preferences(&mut self.builder, protocols);
+    }
+
+    /// Sets a callback used to verify client certificate chains asynchronously once the
+    /// handshake has completed.
+    ///
+    /// The callback receives the verified chain, leaf first, and can consult external services
+    /// such as an OCSP responder or an allow list without blocking the runtime. OpenSSL can't
+    /// suspend a server's handshake during client certificate verification, so this is not part
+    /// of the handshake: the client sees a successful handshake and is sent no alert, and under
+    /// TLS 1.3 the server may already have sent it session tickets. If the callback returns an
+    /// error, [`SslStreamAcceptor::accept`] fails with [`Error::Verify`] and the stream is
+    /// dropped before any application data is exchanged. Clients which don't present a
+    /// certificate and resumed sessions are not checked.
+    pub fn set_post_handshake_verify_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(Vec<X509>) -> VerifyFuture + 'static + Send + Sync,
+    {
+        self.post_handshake_verify = Some(Arc::new(callback));
+    }
+
+    /// Consumes the builder, returning an [`SslStreamAcceptor`].
+    pub fn build(self) -> SslStreamAcceptor {
+        SslStreamAcceptor {
+            acceptor: Arc::new(self.builder.build()),
+            #[cfg(ossl111)]
+            sni: self.sni,
+            post_handshake_verify: self.post_handshake_verify,
+        }
+    }
+}
+
+impl Deref for SslStreamAcceptorBuilder {
+    type Target = SslAcceptorBuilder;
+
+    fn deref(&self) -> &SslAcceptorBuilder {
+        &self.builder
+    }
+}
+
+impl DerefMut for SslStreamAcceptorBuilder {
+    fn deref_mut(&mut self) -> &mut SslAcceptorBuilder {
+        &mut self.builder
+    }
+}
+
+/// A type which accepts TLS connections from clients, producing [`SslStream`]s.
+///
+/// This is a thin wrapper around an [`SslAcceptor`]. It is cheap to clone, so a single acceptor
+/// can be shared by all of the tasks of a server.
+#[derive(Clone)]
+pub struct SslStreamAcceptor {
+    acceptor: Arc<SslAcceptor>,
+    #[cfg(ossl111)]
+    sni: Option<Arc<SniCallback>>,
+    post_handshake_verify: Option<Arc<VerifyCallback>>,
+}
+
+impl SslStreamAcceptor {
+    /// Creates a new acceptor from an [`SslAcceptor`].
+    pub fn from_acceptor(acceptor: SslAcceptor) -> SslStreamAcceptor {
+        SslStreamAcceptor {
+            acceptor: Arc::new(acceptor),
+            #[cfg(ossl111)]
+            sni: None,
+            post_handshake_verify: None,
+        }
+    }
+
+    /// Creates a new builder wrapping an [`SslAcceptorBuilder`].
+    pub fn builder(builder: SslAcceptorBuilder) -> SslStreamAcceptorBuilder {
+        SslStreamAcceptorBuilder::new(builder)
+    }
+
+    /// Returns a shared reference to the inner [`SslAcceptor`].
+    pub fn acceptor(&self) -> &SslAcceptor {
+        &self.acceptor
+    }
+
+    /// Performs a server-side TLS handshake over `stream`.
+    pub async fn accept<S>(&self, stream: S) -> Result<SslStream<S>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let ssl = Ssl::new(self.acceptor.context())?;
+        let mut stream = SslStream::new(ssl, stream)?;
+
+        loop {
+            match Pin::new(&mut stream).accept().await {
+                Ok(()) => break,
+                #[cfg(ossl111)]
+                Err(ref e) if e.code() == ErrorCode::WANT_CLIENT_HELLO_CB && self.sni.is_some() => {
+                    self.resolve_sni(&mut stream).await?
+                }
+                Err(e) => return Err(HandshakeError::new(stream.ssl(), e).into()),
+            }
+        }
+
+        if let Some(callback) = &self.post_handshake_verify {
+            crate::verify::verify_after_handshake(callback, &stream).await?;
+        }
+        Ok(stream)
+    }
+
+    #[cfg(ossl111)]
+    async fn resolve_sni<S>(&self, stream: &mut SslStream<S>) -> Result<(), ssl::Error> {
+        let sni = match &self.sni {
+            Some(sni) => sni,
+            None => return Ok(()),
+        };
+
+        let hello = {
+            let ssl = stream.inner.ssl_mut();
+            let hello = crate::client_hello::take(ssl);
+            crate::client_hello::resume(ssl);
+            hello
+        };
+
+        if let Some(future) = hello.and_then(|hello| sni(hello)) {
+            let context = future.await?;
+            crate::switch_context(stream.inner.ssl_mut(), &context)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<SslAcceptor> for SslStreamAcceptor {
+    fn from(acceptor: SslAcceptor) -> SslStreamAcceptor {
+        SslStreamAcceptor::from_acceptor(acceptor)
+    }
+}
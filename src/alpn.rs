@@ -0,0 +1,132 @@
+//! Server-side selection of application protocols, and dispatch on the negotiated one.
+
+use crate::{Error, SslStream, SslStreamAcceptor, SslStreamAcceptorBuilder};
+use openssl::ssl::{AlpnError, SslContextBuilder};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub(crate) fn set_alpn_preferences(builder: &mut SslContextBuilder, protocols: Vec<Vec<u8>>) {
+    builder.set_alpn_select_callback(move |_, client| {
+        protocols
+            .iter()
+            .find_map(|protocol| find_protocol(client, protocol))
+            .ok_or(AlpnError::NOACK)
+    });
+}
+
+/// Returns the entry of the wire-format protocol list `list` equal to `protocol`.
+fn find_protocol<'a>(mut list: &'a [u8], protocol: &[u8]) -> Option<&'a [u8]> {
+    while let Some((&len, rest)) = list.split_first() {
+        let len = usize::from(len);
+        if rest.len() < len {
+            return None;
+        }
+        let (candidate, rest) = rest.split_at(len);
+        if candidate == protocol {
+            return Some(candidate);
+        }
+        list = rest;
+    }
+    None
+}
+
+/// Maps the application protocols negotiated via ALPN to values of a user-chosen type `P`.
+///
+/// This lets a server speaking several protocols on one port accept a connection and then match
+/// on a typed decision, rather than on the raw protocol name.
+///
+/// ```no_run
+/// use tokio_openssl::{AlpnDispatcher, SslStreamAcceptor};
+/// # use openssl::ssl::{SslAcceptor, SslMethod};
+///
+/// #[derive(Clone)]
+/// enum Protocol {
+///     Http1,
+///     Custom,
+/// }
+///
+/// # async fn run(stream: tokio::net::TcpStream) -> Result<(), tokio_openssl::Error> {
+/// let mut dispatcher = AlpnDispatcher::new();
+/// dispatcher.add_protocol(b"http/1.1", Protocol::Http1);
+/// dispatcher.add_protocol(b"custom/1", Protocol::Custom);
+///
+/// let mut builder =
+///     SslStreamAcceptor::builder(SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?);
+/// dispatcher.configure(&mut builder);
+/// let acceptor = builder.build();
+///
+/// match dispatcher.accept(&acceptor, stream).await? {
+///     (stream, Some(Protocol::Http1)) => { /* ... */ }
+///     (stream, Some(Protocol::Custom)) => { /* ... */ }
+///     (stream, None) => { /* the client didn't offer a supported protocol */ }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AlpnDispatcher<P> {
+    protocols: Vec<(Vec<u8>, P)>,
+}
+
+impl<P> Default for AlpnDispatcher<P> {
+    fn default() -> AlpnDispatcher<P> {
+        AlpnDispatcher::new()
+    }
+}
+
+impl<P> AlpnDispatcher<P> {
+    /// Creates a dispatcher with no protocols.
+    pub fn new() -> AlpnDispatcher<P> {
+        AlpnDispatcher { protocols: vec![] }
+    }
+
+    /// Adds a protocol, mapped to `value`.
+    ///
+    /// Protocols are preferred in the order they are added. Adding a protocol again replaces its
+    /// value but keeps its position.
+    pub fn add_protocol(&mut self, protocol: &[u8], value: P) {
+        match self.protocols.iter_mut().find(|(p, _)| p == protocol) {
+            Some(entry) => entry.1 = value,
+            None => self.protocols.push((protocol.to_vec(), value)),
+        }
+    }
+
+    /// Configures `builder` to select the most preferred of this dispatcher's protocols offered by
+    /// the client.
+    ///
+    /// If the client offers none of them, the handshake continues without an application
+    /// protocol.
+    ///
+    /// This replaces any ALPN selection callback previously configured on the builder.
+    pub fn configure(&self, builder: &mut SslStreamAcceptorBuilder) {
+        builder.set_alpn_preferences(self.protocols.iter().map(|(p, _)| &p[..]));
+    }
+
+    /// Returns the value mapped to the protocol negotiated on `stream`, if any.
+    pub fn dispatch<S>(&self, stream: &SslStream<S>) -> Option<&P> {
+        let protocol = stream.alpn_protocol()?;
+        self.protocols
+            .iter()
+            .find(|(p, _)| p == protocol)
+            .map(|(_, value)| value)
+    }
+}
+
+impl<P: Clone> AlpnDispatcher<P> {
+    /// Performs a server-side TLS handshake over `stream` with `acceptor`, returning the stream
+    /// along with the value mapped to the negotiated protocol.
+    ///
+    /// The value is `None` if no protocol was negotiated, or if the negotiated protocol is not one
+    /// of this dispatcher's.
+    pub async fn accept<S>(
+        &self,
+        acceptor: &SslStreamAcceptor,
+        stream: S,
+    ) -> Result<(SslStream<S>, Option<P>), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let stream = acceptor.accept(stream).await?;
+        let value = self.dispatch(&stream).cloned();
+        Ok((stream, value))
+    }
+}
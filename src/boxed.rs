@@ -0,0 +1,81 @@
+use crate::SslStream;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// A heap-pinned [`SslStream`] which is [`Unpin`] regardless of the underlying stream.
+///
+/// This allows streams over `!Unpin` transports to be used with APIs that require
+/// `AsyncRead + AsyncWrite + Unpin`, such as `tokio_util::codec::Framed`.
+#[derive(Debug)]
+pub struct BoxedSslStream<S>(Pin<Box<SslStream<S>>>);
+
+impl<S> BoxedSslStream<S> {
+    /// Returns a shared reference to the wrapped stream.
+    pub fn get_ref(&self) -> &SslStream<S> {
+        &self.0
+    }
+
+    /// Returns a pinned mutable reference to the wrapped stream.
+    pub fn get_pin_mut(&mut self) -> Pin<&mut SslStream<S>> {
+        self.0.as_mut()
+    }
+
+    /// Consumes the wrapper, returning the pinned stream.
+    pub fn into_inner(self) -> Pin<Box<SslStream<S>>> {
+        self.0
+    }
+}
+
+impl<S> From<Pin<Box<SslStream<S>>>> for BoxedSslStream<S> {
+    fn from(stream: Pin<Box<SslStream<S>>>) -> BoxedSslStream<S> {
+        BoxedSslStream(stream)
+    }
+}
+
+impl<S> AsyncRead for BoxedSslStream<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.0.as_mut().poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for BoxedSslStream<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.as_mut().poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.0.as_mut().poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored()
+    }
+}
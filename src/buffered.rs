@@ -0,0 +1,155 @@
+use crate::SslStream;
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Small writes are coalesced into chunks of up to this size, the maximum TLS record payload.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// An [`SslStream`] which queues writes in memory until it is flushed.
+///
+/// [`poll_write`](AsyncWrite::poll_write) never returns `Pending`: every write is accepted in full
+/// and appended to an internal queue, with small writes coalesced into chunks of up to 16 KiB.
+/// The queue is handed to OpenSSL by [`poll_flush`](AsyncWrite::poll_flush) and
+/// [`poll_shutdown`](AsyncWrite::poll_shutdown).
+///
+/// The queue is unbounded, so writers must flush periodically to apply backpressure.
+#[derive(Debug)]
+pub struct BufferedSslStream<S> {
+    inner: SslStream<S>,
+    queue: VecDeque<Vec<u8>>,
+    /// The number of bytes of the front chunk already written.
+    pos: usize,
+}
+
+impl<S> BufferedSslStream<S> {
+    /// Wraps a stream.
+    pub fn new(inner: SslStream<S>) -> BufferedSslStream<S> {
+        BufferedSslStream {
+            inner,
+            queue: VecDeque::new(),
+            pos: 0,
+        }
+    }
+
+    /// Returns a shared reference to the wrapped stream.
+    pub fn get_ref(&self) -> &SslStream<S> {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped stream.
+    pub fn get_mut(&mut self) -> &mut SslStream<S> {
+        &mut self.inner
+    }
+
+    /// Returns a pinned mutable reference to the wrapped stream.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut SslStream<S>> {
+        unsafe { Pin::new_unchecked(&mut self.get_unchecked_mut().inner) }
+    }
+
+    /// Returns the number of bytes queued but not yet written to the wrapped stream.
+    pub fn buffered_len(&self) -> usize {
+        self.queue.iter().map(Vec::len).sum::<usize>() - self.pos
+    }
+
+    /// Consumes the wrapper, returning the wrapped stream.
+    ///
+    /// Any queued data which has not been flushed is discarded.
+    pub fn into_inner(self) -> SslStream<S> {
+        self.inner
+    }
+
+    fn parts(self: Pin<&mut Self>) -> (Pin<&mut SslStream<S>>, &mut VecDeque<Vec<u8>>, &mut usize) {
+        unsafe {
+            let this = self.get_unchecked_mut();
+            (
+                Pin::new_unchecked(&mut this.inner),
+                &mut this.queue,
+                &mut this.pos,
+            )
+        }
+    }
+}
+
+impl<S> BufferedSslStream<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    fn poll_drain(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let (mut inner, queue, pos) = self.parts();
+
+        while let Some(chunk) = queue.front() {
+            match inner.as_mut().poll_write(cx, &chunk[*pos..]) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write buffered data",
+                    )));
+                }
+                Poll::Ready(Ok(n)) => {
+                    *pos += n;
+                    if *pos == chunk.len() {
+                        queue.pop_front();
+                        *pos = 0;
+                    }
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncRead for BufferedSslStream<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_pin_mut().poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for BufferedSslStream<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let (_, queue, _) = self.parts();
+
+        let mut rest = buf;
+        if let Some(back) = queue.back_mut() {
+            let n = rest.len().min(CHUNK_SIZE.saturating_sub(back.len()));
+            back.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+        }
+        queue.extend(rest.chunks(CHUNK_SIZE).map(<[u8]>::to_vec));
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.as_mut().poll_drain(cx) {
+            Poll::Ready(Ok(())) => self.get_pin_mut().poll_flush(cx),
+            r => r,
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.as_mut().poll_drain(cx) {
+            Poll::Ready(Ok(())) => self.get_pin_mut().poll_shutdown(cx),
+            r => r,
+        }
+    }
+}
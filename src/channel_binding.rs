@@ -0,0 +1,66 @@
+//! Channel bindings, which tie application-level authentication to a TLS connection.
+
+use crate::{Error, SslStream};
+use openssl::ssl::SslVersion;
+use std::io;
+
+/// The label of the `tls-exporter` channel binding, from RFC 9266.
+const EXPORTER_LABEL: &str = "EXPORTER-Channel-Binding";
+/// The length of the `tls-exporter` channel binding, from RFC 9266.
+const EXPORTER_LEN: usize = 32;
+
+/// A type of channel binding, as used by authentication mechanisms such as SCRAM-PLUS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelBinding {
+    /// The `tls-unique` binding from RFC 5929: the first Finished message of the handshake.
+    ///
+    /// This is not defined for TLS 1.3.
+    TlsUnique,
+    /// The `tls-exporter` binding from RFC 9266: 32 bytes of keying material exported with the
+    /// label `EXPORTER-Channel-Binding`.
+    ///
+    /// Under TLS 1.2, this is only secure if the extended master secret extension was
+    /// negotiated.
+    TlsExporter,
+}
+
+impl<S> SslStream<S> {
+    /// Returns the channel binding data of the given `kind` for this connection.
+    ///
+    /// Both ends of a connection derive the same data. Returns an
+    /// [`InvalidInput`](io::ErrorKind::InvalidInput) error if the handshake has not completed, or
+    /// if `tls-unique` is requested for a TLS 1.3 connection.
+    ///
+    /// Requires OpenSSL 1.1.1 or newer.
+    pub fn channel_binding(&self, kind: ChannelBinding) -> Result<Vec<u8>, Error> {
+        if !self.is_handshake_done() {
+            return Err(invalid_input("the handshake has not completed"));
+        }
+
+        match kind {
+            ChannelBinding::TlsUnique => {
+                if self.protocol_version() == Some(SslVersion::TLS1_3) {
+                    return Err(invalid_input("tls-unique is not defined for TLS 1.3"));
+                }
+
+                // the client sends the first Finished message of a full handshake, and the server
+                // the first of an abbreviated one
+                let ssl = self.ssl();
+                let mut buf = [0; 64];
+                let len = if ssl.is_server() == ssl.session_reused() {
+                    ssl.finished(&mut buf)
+                } else {
+                    ssl.peer_finished(&mut buf)
+                };
+                Ok(buf[..len.min(buf.len())].to_vec())
+            }
+            ChannelBinding::TlsExporter => {
+                Ok(self.export_keying_material(EXPORTER_LEN, EXPORTER_LABEL, Some(&[]))?)
+            }
+        }
+    }
+}
+
+fn invalid_input(msg: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
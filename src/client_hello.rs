@@ -0,0 +1,195 @@
+//! Parsing of ClientHello extensions from within the client hello callback.
+
+use crate::ex_data::ExIndex;
+use foreign_types::ForeignTypeRef;
+use openssl::error::ErrorStack;
+use openssl::ssl::{ClientHelloResponse, Ssl, SslContextBuilder, SslRef};
+use std::os::raw::c_uint;
+use std::ptr;
+use std::slice;
+use std::str;
+
+const TLSEXT_TYPE_SERVER_NAME: c_uint = 0;
+const TLSEXT_TYPE_APPLICATION_LAYER_PROTOCOL_NEGOTIATION: c_uint = 16;
+const TLSEXT_NAMETYPE_HOST_NAME: u8 = 0;
+
+/// The parts of a ClientHello passed to
+/// [`SslStreamAcceptorBuilder::set_async_client_hello_callback`](crate::SslStreamAcceptorBuilder::set_async_client_hello_callback)
+/// and returned by [`StartHandshake::client_hello`](crate::StartHandshake::client_hello).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientHello {
+    server_name: Option<String>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl ClientHello {
+    /// Parses the ClientHello being processed.
+    ///
+    /// Only meaningful from within the client hello callback.
+    pub(crate) fn new(ssl: &SslRef) -> ClientHello {
+        ClientHello {
+            server_name: server_name(ssl),
+            alpn_protocols: alpn_protocols(ssl),
+        }
+    }
+
+    /// Returns the host name sent by the client in the server name indication extension.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Returns the protocols offered by the client in the ALPN extension, in order of the
+    /// client's preference.
+    ///
+    /// The list is empty if the client did not send the extension.
+    pub fn alpn_protocols(&self) -> &[Vec<u8>] {
+        &self.alpn_protocols
+    }
+}
+
+/// Whether the handshake of a connection is paused at its ClientHello.
+enum ClientHelloState {
+    /// Paused, holding the ClientHello until it is taken.
+    Paused(Option<ClientHello>),
+    Resumed,
+}
+
+static STATE: ExIndex<Ssl, ClientHelloState> = ExIndex::new();
+
+/// Configures `builder` to pause every handshake once the ClientHello has been read.
+///
+/// The handshake then fails with `WANT_CLIENT_HELLO_CB` until [`resume`] is called, which lets
+/// the acceptors do asynchronous work, such as choosing a context, between polls of the
+/// handshake.
+///
+/// This replaces any client hello callback previously configured on the builder.
+pub(crate) fn pause_on_client_hello(builder: &mut SslContextBuilder) -> Result<(), ErrorStack> {
+    let index = STATE.index()?;
+    builder.set_client_hello_callback(move |ssl, _| {
+        if let Some(ClientHelloState::Resumed) = ssl.ex_data(index) {
+            return Ok(ClientHelloResponse::SUCCESS);
+        }
+
+        let hello = ClientHello::new(ssl);
+        ssl.set_ex_data(index, ClientHelloState::Paused(Some(hello)));
+        Ok(ClientHelloResponse::RETRY)
+    });
+    Ok(())
+}
+
+/// Returns `true` if the handshake of `ssl` was paused by [`pause_on_client_hello`] and has not
+/// been resumed.
+pub(crate) fn is_paused(ssl: &SslRef) -> bool {
+    let state = STATE.get().and_then(|index| ssl.ex_data(index));
+    matches!(state, Some(ClientHelloState::Paused(_)))
+}
+
+/// Takes the ClientHello at which the handshake of `ssl` is paused.
+///
+/// Returns `None` if the handshake isn't paused, or if the ClientHello has already been taken.
+pub(crate) fn take(ssl: &mut SslRef) -> Option<ClientHello> {
+    match ssl.ex_data_mut(STATE.get()?) {
+        Some(ClientHelloState::Paused(hello)) => hello.take(),
+        _ => None,
+    }
+}
+
+/// Lets the handshake of `ssl` continue past the ClientHello when it is next polled.
+pub(crate) fn resume(ssl: &mut SslRef) {
+    if let Some(index) = STATE.get() {
+        ssl.set_ex_data(index, ClientHelloState::Resumed);
+    }
+}
+
+/// Returns the raw contents of an extension of the ClientHello being processed.
+///
+/// Only meaningful from within the client hello callback.
+fn extension(ssl: &SslRef, ty: c_uint) -> Option<&[u8]> {
+    unsafe {
+        let mut out = ptr::null();
+        let mut outlen = 0;
+        if openssl_sys::SSL_client_hello_get0_ext(ssl.as_ptr(), ty, &mut out, &mut outlen) == 1 {
+            Some(slice::from_raw_parts(out, outlen))
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the host name from the server_name extension of the ClientHello being processed.
+fn server_name(ssl: &SslRef) -> Option<String> {
+    let ext = extension(ssl, TLSEXT_TYPE_SERVER_NAME)?;
+    parse_server_name(ext).map(str::to_owned)
+}
+
+/// Returns the protocols from the ALPN extension of the ClientHello being processed.
+fn alpn_protocols(ssl: &SslRef) -> Vec<Vec<u8>> {
+    extension(ssl, TLSEXT_TYPE_APPLICATION_LAYER_PROTOCOL_NEGOTIATION)
+        .and_then(parse_alpn_protocols)
+        .unwrap_or_default()
+}
+
+fn read_u8(buf: &mut &[u8]) -> Option<u8> {
+    let (&b, rest) = buf.split_first()?;
+    *buf = rest;
+    Some(b)
+}
+
+fn read_u16(buf: &mut &[u8]) -> Option<usize> {
+    let hi = read_u8(buf)?;
+    let lo = read_u8(buf)?;
+    Some((usize::from(hi) << 8) | usize::from(lo))
+}
+
+fn read_bytes<'a>(buf: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if buf.len() < len {
+        return None;
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(bytes)
+}
+
+// struct {
+//     NameType name_type;
+//     select (name_type) {
+//         case host_name: HostName;
+//     } name;
+// } ServerName;
+//
+// struct {
+//     ServerName server_name_list<1..2^16-1>
+// } ServerNameList;
+fn parse_server_name(mut ext: &[u8]) -> Option<&str> {
+    let len = read_u16(&mut ext)?;
+    let mut list = read_bytes(&mut ext, len)?;
+
+    while !list.is_empty() {
+        let ty = read_u8(&mut list)?;
+        let len = read_u16(&mut list)?;
+        let name = read_bytes(&mut list, len)?;
+        if ty == TLSEXT_NAMETYPE_HOST_NAME {
+            return str::from_utf8(name).ok();
+        }
+    }
+
+    None
+}
+
+// opaque ProtocolName<1..2^8-1>;
+//
+// struct {
+//     ProtocolName protocol_name_list<2..2^16-1>
+// } ProtocolNameList;
+fn parse_alpn_protocols(mut ext: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let len = read_u16(&mut ext)?;
+    let mut list = read_bytes(&mut ext, len)?;
+
+    let mut protocols = vec![];
+    while !list.is_empty() {
+        let len = usize::from(read_u8(&mut list)?);
+        protocols.push(read_bytes(&mut list, len)?.to_vec());
+    }
+
+    Some(protocols)
+}
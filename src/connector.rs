@@ -0,0 +1,537 @@
+use crate::ex_data::ExIndex;
+#[cfg(feature = "rt")]
+use crate::session_cache::SessionCache;
+use crate::session_cache::{LocalSessionCache, SessionKey};
+use crate::verify::VerifyCallback;
+use crate::{Error, HandshakeError, OcspStaplingPolicy, PinMode, SslStream, VerifyFuture};
+use foreign_types::ForeignTypeRef;
+use openssl::error::ErrorStack;
+use openssl::ex_data::Index;
+use openssl::ssl::{
+    Ssl, SslConnector, SslConnectorBuilder, SslSession, SslSessionCacheMode, SslSessionRef,
+    StatusType,
+};
+use openssl::x509::X509;
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
+#[cfg(ossl111)]
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "rt")]
+use tokio::sync::mpsc;
+
+/// The number of new sessions queued for an asynchronous cache before further ones are dropped.
+#[cfg(feature = "rt")]
+const SESSION_QUEUE_LEN: usize = 1024;
+
+static SESSION_KEY: ExIndex<Ssl, SessionKey> = ExIndex::new();
+
+#[derive(Clone)]
+enum CacheStore {
+    Local(Arc<LocalSessionCache>),
+    #[cfg(feature = "rt")]
+    Async(Arc<SessionWriter>),
+}
+
+/// Stores the sessions reported by OpenSSL's synchronous new session callback in an
+/// asynchronous cache.
+///
+/// The callback queues sessions, and a single task spawned by the first connection stores them.
+#[cfg(feature = "rt")]
+struct SessionWriter {
+    cache: Arc<dyn SessionCache>,
+    queue: Mutex<Option<mpsc::Receiver<(SessionKey, Vec<u8>)>>>,
+}
+
+#[cfg(feature = "rt")]
+impl SessionWriter {
+    /// Spawns the task storing queued sessions, unless it already has been.
+    fn start(&self) {
+        let queue = self.queue.lock().unwrap().take();
+        if let Some(mut queue) = queue {
+            let cache = self.cache.clone();
+            // the task exits once the connector's context, and with it the sender, is dropped
+            tokio::spawn(async move {
+                while let Some((key, session)) = queue.recv().await {
+                    cache.put(key, session).await;
+                }
+            });
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Cache {
+    store: CacheStore,
+    index: Index<Ssl, SessionKey>,
+}
+
+impl Cache {
+    async fn get(&self, key: &SessionKey) -> Option<SslSession> {
+        match &self.store {
+            CacheStore::Local(sessions) => sessions.get(key),
+            #[cfg(feature = "rt")]
+            CacheStore::Async(writer) => {
+                let der = writer.cache.get(key).await?;
+                SslSession::from_der(&der).ok()
+            }
+        }
+    }
+}
+
+/// A builder for [`SslStreamConnector`]s.
+///
+/// This dereferences to the wrapped [`SslConnectorBuilder`], so it can be configured in the same
+/// way.
+pub struct SslStreamConnectorBuilder {
+    builder: SslConnectorBuilder,
+    cache: Option<Cache>,
+    pinned: bool,
+    ocsp: bool,
+    #[cfg(ossl300)]
+    verify: Option<Arc<VerifyCallback>>,
+    post_handshake_verify: Option<Arc<VerifyCallback>>,
+}
+
+impl SslStreamConnectorBuilder {
+    /// Creates a new builder wrapping an [`SslConnectorBuilder`].
+    pub fn new(builder: SslConnectorBuilder) -> SslStreamConnectorBuilder {
+        SslStreamConnectorBuilder {
+            builder,
+            cache: None,
+            pinned: false,
+            ocsp: false,
+            #[cfg(ossl300)]
+            verify: None,
+            post_handshake_verify: None,
+        }
+    }
+
+    /// Enables an in-memory cache of client sessions, which
+    /// [`SslStreamConnector::connect`] uses to resume sessions automatically.
+    ///
+    /// Sessions are cached per server, identified by the domain and, when connecting with
+    /// [`connect_with_port`](SslStreamConnector::connect_with_port), the port. Up to `capacity`
+    /// servers are tracked, and sessions are discarded `ttl` after they were issued. Several
+    /// sessions are retained per server, as TLS 1.3 tickets can only be used once.
+    ///
+    /// This replaces any new session callback previously configured on the builder.
+    pub fn set_session_cache(&mut self, capacity: usize, ttl: Duration) -> Result<(), ErrorStack> {
+        let index = SESSION_KEY.index()?;
+        let sessions = Arc::new(LocalSessionCache::new(capacity, ttl));
+
+        self.builder.set_session_cache_mode(
+            SslSessionCacheMode::CLIENT | SslSessionCacheMode::NO_INTERNAL_STORE,
+        );
+        self.builder.set_new_session_callback({
+            let sessions = sessions.clone();
+            move |ssl, session| {
+                if crate::pin::rejected(ssl) {
+                    return;
+                }
+                if let Some(key) = ssl.ex_data(index) {
+                    sessions.insert(key, session);
+                }
+            }
+        });
+        self.cache = Some(Cache {
+            store: CacheStore::Local(sessions),
+            index,
+        });
+        Ok(())
+    }
+
+    /// Like [`set_session_cache`](Self::set_session_cache), but stores sessions in an external,
+    /// asynchronous `cache`.
+    ///
+    /// OpenSSL reports new sessions synchronously, so they are queued and stored in order by a
+    /// task which the first connection spawns onto its Tokio runtime. If the cache falls behind,
+    /// further sessions are dropped. [`SslStreamConnector::connect`] waits for the cache lookup
+    /// before starting the handshake.
+    ///
+    /// This replaces any new session callback previously configured on the builder.
+    ///
+    /// Requires the `rt` feature.
+    #[cfg(feature = "rt")]
+    pub fn set_async_session_cache(
+        &mut self,
+        cache: Arc<dyn SessionCache>,
+    ) -> Result<(), ErrorStack> {
+        let index = SESSION_KEY.index()?;
+        let (tx, rx) = mpsc::channel(SESSION_QUEUE_LEN);
+
+        self.builder.set_session_cache_mode(
+            SslSessionCacheMode::CLIENT | SslSessionCacheMode::NO_INTERNAL_STORE,
+        );
+        self.builder.set_new_session_callback(move |ssl, session| {
+            if crate::pin::rejected(ssl) {
+                return;
+            }
+            let key = match ssl.ex_data(index) {
+                Some(key) => key.clone(),
+                None => return,
+            };
+            let der = match session.to_der() {
+                Ok(der) => der,
+                Err(_) => return,
+            };
+            // drop sessions rather than blocking the handshake if the cache falls behind
+            let _ = tx.try_send((key, der));
+        });
+        self.cache = Some(Cache {
+            store: CacheStore::Async(Arc::new(SessionWriter {
+                cache,
+                queue: Mutex::new(Some(rx)),
+            })),
+            index,
+        });
+        Ok(())
+    }
+
+    /// Appends the secrets of every connection to the file at `path`; see [`crate::keylog`].
+    #[cfg(ossl111)]
+    pub fn set_keylog_file<P>(&mut self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        crate::keylog::set_keylog_file(&mut self.builder, path.as_ref())
+    }
+
+    /// Like [`set_keylog_file`](Self::set_keylog_file), but writes to the file named by
+    /// `SSLKEYLOGFILE`; see [`keylog::set_keylog_from_env`](crate::keylog::set_keylog_from_env).
+    #[cfg(ossl111)]
+    pub fn set_keylog_from_env(&mut self) -> io::Result<()> {
+        crate::keylog::set_keylog_from_env(&mut self.builder)
+    }
+
+    /// Requires the server's certificate chain to contain a public key whose SHA-256 hash, taken
+    /// over its DER-encoded SubjectPublicKeyInfo, is one of `pins`.
+    ///
+    /// Pins are checked in addition to the usual certificate and hostname verification, against
+    /// every certificate of the verified chain, so both the server's own key and that of a CA can
+    /// be pinned. `mode` determines whether a mismatch is reported once the handshake completes
+    /// or aborts the handshake. Resumed sessions are not checked again, and sessions of
+    /// connections which failed the check are not cached.
+    ///
+    /// This replaces any verify callback previously configured on the builder, and sets the
+    /// verify mode to [`SslVerifyMode::PEER`](openssl::ssl::SslVerifyMode::PEER).
+    pub fn set_spki_pins(&mut self, pins: Vec<[u8; 32]>, mode: PinMode) -> Result<(), ErrorStack> {
+        crate::pin::set_spki_pins(&mut self.builder, pins, mode)?;
+        self.pinned = true;
+        Ok(())
+    }
+
+    /// Sets how OCSP responses stapled by servers are requested and verified.
+    ///
+    /// Unless `policy` is [`OcspStaplingPolicy::Off`], servers are asked to staple a response,
+    /// which is verified against the server's certificate chain and the context's trust store.
+    /// Its signature, thisUpdate and nextUpdate times, and the certificate's status are checked.
+    /// If the response is invalid, or is missing when `policy` requires one,
+    /// [`SslStreamConnector::connect`] fails with [`Error::Ocsp`]. Resumed sessions are not
+    /// checked again.
+    ///
+    /// This replaces any status callback previously configured on the builder.
+    pub fn set_ocsp_stapling(&mut self, policy: OcspStaplingPolicy) -> Result<(), ErrorStack> {
+        self.ocsp = policy != OcspStaplingPolicy::Off;
+        if self.ocsp {
+            crate::ocsp::set_stapling_policy(&mut self.builder, policy)?;
+        }
+        Ok(())
+    }
+
+    /// Sets a callback used to verify the server's certificate chain asynchronously, in addition
+    /// to OpenSSL's own verification.
+    ///
+    /// The callback receives the verified chain, leaf first, and can consult external services
+    /// such as an OCSP responder or an allow list without blocking the runtime. The handshake is
+    /// suspended while the callback runs. If it returns an error, the handshake is aborted with a
+    /// certificate alert and [`SslStreamConnector::connect`] fails with [`Error::Verify`].
+    /// Resumed sessions are not verified again.
+    ///
+    /// This replaces any certificate verification callback previously configured on the context
+    /// with `SSL_CTX_set_cert_verify_callback`.
+    ///
+    /// Requires OpenSSL 3.0 or newer. For older versions, see
+    /// [`set_post_handshake_verify_callback`](Self::set_post_handshake_verify_callback).
+    #[cfg(ossl300)]
+    pub fn set_async_verify_callback<F>(&mut self, callback: F) -> Result<(), ErrorStack>
+    where
+        F: Fn(Vec<X509>) -> VerifyFuture + 'static + Send + Sync,
+    {
+        crate::verify::retry::install(&mut self.builder)?;
+        self.verify = Some(Arc::new(callback));
+        Ok(())
+    }
+
+    /// Sets a callback used to verify the server's certificate chain asynchronously once the
+    /// handshake has completed.
+    ///
+    /// The callback receives the verified chain, leaf first. Unlike
+    /// [`set_async_verify_callback`](Self::set_async_verify_callback), the handshake is not
+    /// suspended: it completes before the callback runs, so the server sees a successful
+    /// handshake and is sent no alert. If the callback returns an error,
+    /// [`SslStreamConnector::connect`] fails with [`Error::Verify`] and the stream is dropped
+    /// before any data is exchanged. Resumed sessions are not verified again.
+    pub fn set_post_handshake_verify_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(Vec<X509>) -> VerifyFuture + 'static + Send + Sync,
+    {
+        self.post_handshake_verify = Some(Arc::new(callback));
+    }
+
+    /// Consumes the builder, returning an [`SslStreamConnector`].
+    pub fn build(self) -> SslStreamConnector {
+        SslStreamConnector {
+            connector: Arc::new(self.builder.build()),
+            cache: self.cache,
+            pinned: self.pinned,
+            ocsp: self.ocsp,
+            #[cfg(ossl300)]
+            verify: self.verify,
+            post_handshake_verify: self.post_handshake_verify,
+        }
+    }
+}
+
+impl Deref for SslStreamConnectorBuilder {
+    type Target = SslConnectorBuilder;
+
+    fn deref(&self) -> &SslConnectorBuilder {
+        &self.builder
+    }
+}
+
+impl DerefMut for SslStreamConnectorBuilder {
+    fn deref_mut(&mut self) -> &mut SslConnectorBuilder {
+        &mut self.builder
+    }
+}
+
+/// A type which establishes TLS connections to servers, producing [`SslStream`]s.
+///
+/// This is a thin wrapper around an [`SslConnector`]. It is cheap to clone, so a single connector
+/// can be shared by all of the tasks of a client.
+#[derive(Clone)]
+pub struct SslStreamConnector {
+    connector: Arc<SslConnector>,
+    cache: Option<Cache>,
+    pinned: bool,
+    ocsp: bool,
+    #[cfg(ossl300)]
+    verify: Option<Arc<VerifyCallback>>,
+    post_handshake_verify: Option<Arc<VerifyCallback>>,
+}
+
+impl SslStreamConnector {
+    /// Creates a new connector from an [`SslConnector`].
+    pub fn from_connector(connector: SslConnector) -> SslStreamConnector {
+        SslStreamConnector {
+            connector: Arc::new(connector),
+            cache: None,
+            pinned: false,
+            ocsp: false,
+            #[cfg(ossl300)]
+            verify: None,
+            post_handshake_verify: None,
+        }
+    }
+
+    /// Creates a new builder wrapping an [`SslConnectorBuilder`].
+    pub fn builder(builder: SslConnectorBuilder) -> SslStreamConnectorBuilder {
+        SslStreamConnectorBuilder::new(builder)
+    }
+
+    /// Returns a shared reference to the inner [`SslConnector`].
+    pub fn connector(&self) -> &SslConnector {
+        &self.connector
+    }
+
+    /// Performs a client-side TLS handshake with the server `domain` over `stream`.
+    ///
+    /// The domain is used for SNI and hostname verification. If the
+    /// [session cache](SslStreamConnectorBuilder::set_session_cache) is enabled, a cached
+    /// session for the domain is resumed when available.
+    pub async fn connect<S>(&self, domain: &str, stream: S) -> Result<SslStream<S>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.connect_inner(domain, None, stream, None).await
+    }
+
+    /// Like [`connect`](Self::connect), but cached sessions are keyed by `port` as well as the
+    /// domain.
+    ///
+    /// This keeps the sessions of different servers on the same host apart.
+    pub async fn connect_with_port<S>(
+        &self,
+        domain: &str,
+        port: u16,
+        stream: S,
+    ) -> Result<SslStream<S>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.connect_inner(domain, Some(port), stream, None).await
+    }
+
+    /// Like [`connect`](Self::connect), but attempts to resume `session` instead of a cached
+    /// session.
+    ///
+    /// If the server declines to resume the session, a full handshake is performed instead; this
+    /// can be checked with [`SslStream::session_reused`]. The session to resume on a later
+    /// connection can be retrieved with [`session`](Self::session).
+    ///
+    /// Returns an [`InvalidInput`](io::ErrorKind::InvalidInput) error if `session` was not
+    /// obtained from this connector.
+    pub async fn connect_with_session<S>(
+        &self,
+        domain: &str,
+        stream: S,
+        session: &ConnectorSession,
+    ) -> Result<SslStream<S>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.connect_inner(domain, None, stream, Some(session))
+            .await
+    }
+
+    /// Returns the session negotiated by `stream`, so that it can be resumed by
+    /// [`connect_with_session`](Self::connect_with_session).
+    ///
+    /// Returns `None` if `stream` has no session, or if it was not created by this connector.
+    /// Under TLS 1.3, the session is only available once the stream has read the session tickets
+    /// the server sends after the handshake.
+    pub fn session<S>(&self, stream: &SslStream<S>) -> Option<ConnectorSession> {
+        if stream.ssl().ssl_context().as_ptr() != self.connector.context().as_ptr() {
+            return None;
+        }
+
+        let session = stream.ssl().session()?;
+        Some(ConnectorSession {
+            session: session.to_owned(),
+            connector: self.connector.clone(),
+        })
+    }
+
+    async fn connect_inner<S>(
+        &self,
+        domain: &str,
+        port: Option<u16>,
+        stream: S,
+        session: Option<&ConnectorSession>,
+    ) -> Result<SslStream<S>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut ssl = self.connector.configure()?.into_ssl(domain)?;
+        if let Some(session) = session {
+            if !Arc::ptr_eq(&session.connector, &self.connector) {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "session was not created by this connector",
+                )));
+            }
+            // SAFETY: the session was negotiated by a stream using this connector's context.
+            unsafe { ssl.set_session(&session.session)? };
+        }
+
+        if let Some(cache) = &self.cache {
+            #[cfg(feature = "rt")]
+            if let CacheStore::Async(writer) = &cache.store {
+                writer.start();
+            }
+            let key = SessionKey::new(domain, port);
+            if session.is_none() {
+                if let Some(session) = cache.get(&key).await {
+                    // SAFETY: cached sessions either come from this connector's new session
+                    // callback or were deserialized, and so aren't associated with any context.
+                    unsafe { ssl.set_session(&session)? };
+                }
+            }
+            ssl.set_ex_data(cache.index, key);
+        }
+
+        if self.pinned {
+            crate::pin::init(&mut ssl)?;
+        }
+        if self.ocsp {
+            ssl.set_status_type(StatusType::OCSP)?;
+        }
+        #[cfg(ossl300)]
+        if self.verify.is_some() {
+            crate::verify::retry::init(&mut ssl)?;
+        }
+
+        let mut stream = SslStream::new(ssl, stream)?;
+        loop {
+            match Pin::new(&mut stream).connect().await {
+                Ok(()) => break,
+                #[cfg(ossl300)]
+                Err(ref e) if crate::verify::retry::is_paused(e) && self.verify.is_some() => {
+                    self.resume_verify(&stream).await?
+                }
+                Err(e) => {
+                    #[cfg(ossl300)]
+                    if let Some(e) = crate::verify::retry::take_rejection(stream.ssl()) {
+                        return Err(e);
+                    }
+                    if let Some(e) = crate::ocsp::failure(stream.ssl()) {
+                        return Err(e);
+                    }
+                    return Err(HandshakeError::new(stream.ssl(), e).into());
+                }
+            }
+        }
+
+        if crate::pin::rejected(stream.ssl()) {
+            return Err(Error::PinMismatch);
+        }
+        if let Some(callback) = &self.post_handshake_verify {
+            crate::verify::verify_after_handshake(callback, &stream).await?;
+        }
+        Ok(stream)
+    }
+
+    #[cfg(ossl300)]
+    async fn resume_verify<S>(&self, stream: &SslStream<S>) -> Result<(), Error> {
+        match &self.verify {
+            Some(callback) => crate::verify::retry::resume(callback, stream.ssl()).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<SslConnector> for SslStreamConnector {
+    fn from(connector: SslConnector) -> SslStreamConnector {
+        SslStreamConnector::from_connector(connector)
+    }
+}
+
+/// A TLS session negotiated through an [`SslStreamConnector`], which can be resumed by later
+/// connections from the same connector.
+#[derive(Clone)]
+pub struct ConnectorSession {
+    session: SslSession,
+    // keeps the connector alive so that its identity can't be reused
+    connector: Arc<SslConnector>,
+}
+
+impl ConnectorSession {
+    /// Returns a shared reference to the session.
+    pub fn session(&self) -> &SslSessionRef {
+        &self.session
+    }
+}
+
+impl fmt::Debug for ConnectorSession {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ConnectorSession")
+            .field("id", &self.session.id())
+            .finish()
+    }
+}
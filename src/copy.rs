@@ -0,0 +1,233 @@
+use crate::SslStream;
+use futures_util::future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+pub(crate) const DEFAULT_BUF_SIZE: usize = 64 * 1024;
+
+enum TransferState {
+    Running(CopyBuffer),
+    ShuttingDown(u64),
+    Done(u64),
+}
+
+struct CopyBuffer {
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+    amt: u64,
+    read_done: bool,
+    need_flush: bool,
+}
+
+impl CopyBuffer {
+    fn new(size: usize) -> CopyBuffer {
+        CopyBuffer {
+            buf: vec![0; size].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            amt: 0,
+            read_done: false,
+            need_flush: false,
+        }
+    }
+
+    fn poll_fill<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut SslStream<R>>,
+    ) -> Poll<io::Result<()>>
+    where
+        R: AsyncRead + AsyncWrite,
+    {
+        self.pos = 0;
+        self.cap = 0;
+
+        // Keep reading while OpenSSL already holds decrypted data so that a full buffer is handed
+        // to the writer rather than one record at a time.
+        loop {
+            let mut buf = ReadBuf::new(&mut self.buf[self.cap..]);
+            match reader.as_mut().poll_read(cx, &mut buf) {
+                Poll::Ready(Ok(())) => {
+                    let n = buf.filled().len();
+                    if n == 0 {
+                        self.read_done = true;
+                        return Poll::Ready(Ok(()));
+                    }
+                    self.cap += n;
+                    if self.cap == self.buf.len() || reader.ssl().pending() == 0 {
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending if self.cap > 0 => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn poll_copy<R, W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut SslStream<R>>,
+        mut writer: Pin<&mut SslStream<W>>,
+    ) -> Poll<io::Result<u64>>
+    where
+        R: AsyncRead + AsyncWrite,
+        W: AsyncRead + AsyncWrite,
+    {
+        loop {
+            if self.pos == self.cap && !self.read_done {
+                match self.poll_fill(cx, reader.as_mut()) {
+                    Poll::Ready(Ok(())) => {}
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => {
+                        // nothing to read right now, so push out what we've already written
+                        if self.need_flush {
+                            match writer.as_mut().poll_flush(cx) {
+                                Poll::Ready(Ok(())) => self.need_flush = false,
+                                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                                Poll::Pending => {}
+                            }
+                        }
+                        return Poll::Pending;
+                    }
+                }
+            }
+
+            while self.pos < self.cap {
+                match writer
+                    .as_mut()
+                    .poll_write(cx, &self.buf[self.pos..self.cap])
+                {
+                    Poll::Ready(Ok(0)) => {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "write zero byte into writer",
+                        )));
+                    }
+                    Poll::Ready(Ok(n)) => {
+                        self.pos += n;
+                        self.amt += n as u64;
+                        self.need_flush = true;
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            if self.pos == self.cap && self.read_done {
+                match writer.as_mut().poll_flush(cx) {
+                    Poll::Ready(Ok(())) => return Poll::Ready(Ok(self.amt)),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+    }
+}
+
+fn transfer_one_direction<R, W>(
+    cx: &mut Context<'_>,
+    state: &mut TransferState,
+    mut reader: Pin<&mut SslStream<R>>,
+    mut writer: Pin<&mut SslStream<W>>,
+) -> Poll<io::Result<u64>>
+where
+    R: AsyncRead + AsyncWrite,
+    W: AsyncRead + AsyncWrite,
+{
+    loop {
+        match state {
+            TransferState::Running(buf) => {
+                match buf.poll_copy(cx, reader.as_mut(), writer.as_mut()) {
+                    Poll::Ready(Ok(amt)) => *state = TransferState::ShuttingDown(amt),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            TransferState::ShuttingDown(amt) => {
+                // Only send our close_notify - waiting for the peer's would consume data flowing
+                // in the other direction.
+                match writer.as_mut().poll_shutdown_tls(cx) {
+                    Poll::Ready(Ok(_)) => *state = TransferState::Done(*amt),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(crate::to_io_error(e))),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            TransferState::Done(amt) => return Poll::Ready(Ok(*amt)),
+        }
+    }
+}
+
+/// Copies data in both directions between two TLS streams.
+///
+/// This is like [`tokio::io::copy_bidirectional`], but tuned for proxying between TLS streams: it
+/// uses 64 KiB buffers and drains all of the plaintext OpenSSL has already decrypted before
+/// writing it to the other side.
+///
+/// When one direction reaches EOF, a close_notify alert is sent on the other stream to propagate
+/// the half-close. The peer's close_notify is not awaited, so the opposite direction can continue.
+///
+/// Returns the number of bytes copied from `a` to `b` and from `b` to `a` once both directions
+/// have finished.
+pub async fn copy_bidirectional_tls<A, B>(
+    a: &mut SslStream<A>,
+    b: &mut SslStream<B>,
+) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    copy_bidirectional_tls_with_sizes(a, b, DEFAULT_BUF_SIZE, DEFAULT_BUF_SIZE).await
+}
+
+/// Like [`copy_bidirectional_tls`], but with explicit buffer sizes for each direction.
+///
+/// # Panics
+///
+/// Panics if either buffer size is 0.
+pub async fn copy_bidirectional_tls_with_sizes<A, B>(
+    a: &mut SslStream<A>,
+    b: &mut SslStream<B>,
+    a_to_b_buf_size: usize,
+    b_to_a_buf_size: usize,
+) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    assert!(
+        a_to_b_buf_size > 0 && b_to_a_buf_size > 0,
+        "buffer sizes must be nonzero"
+    );
+
+    copy_bidirectional_pinned(Pin::new(a), Pin::new(b), a_to_b_buf_size, b_to_a_buf_size).await
+}
+
+pub(crate) async fn copy_bidirectional_pinned<A, B>(
+    mut a: Pin<&mut SslStream<A>>,
+    mut b: Pin<&mut SslStream<B>>,
+    a_to_b_buf_size: usize,
+    b_to_a_buf_size: usize,
+) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite,
+    B: AsyncRead + AsyncWrite,
+{
+    let mut a_to_b = TransferState::Running(CopyBuffer::new(a_to_b_buf_size));
+    let mut b_to_a = TransferState::Running(CopyBuffer::new(b_to_a_buf_size));
+
+    future::poll_fn(|cx| {
+        let a_to_b = transfer_one_direction(cx, &mut a_to_b, a.as_mut(), b.as_mut())?;
+        let b_to_a = transfer_one_direction(cx, &mut b_to_a, b.as_mut(), a.as_mut())?;
+
+        match (a_to_b, b_to_a) {
+            (Poll::Ready(a_to_b), Poll::Ready(b_to_a)) => Poll::Ready(Ok((a_to_b, b_to_a))),
+            _ => Poll::Pending,
+        }
+    })
+    .await
+}
@@ -0,0 +1,264 @@
+use openssl::error::ErrorStack;
+use openssl::ssl::{self, ErrorCode, SslRef};
+use openssl::x509::{X509VerifyResult, X509};
+use std::error;
+use std::fmt;
+use std::io;
+
+/// An error returned by the high-level APIs of this crate.
+///
+/// Errors surfaced through [`AsyncRead`](tokio::io::AsyncRead) and
+/// [`AsyncWrite`](tokio::io::AsyncWrite), which must be [`io::Error`]s, wrap this type unless they
+/// originate from the underlying stream. The OpenSSL details can be recovered with
+/// `error.get_ref().and_then(|e| e.downcast_ref::<tokio_openssl::Error>())`.
+///
+/// Since both [`ssl::Error`] and [`ErrorStack`] convert into this type, mapping errors with
+/// `Error::from` makes them usable wherever an `Into<io::Error>` bound is required, such as the
+/// items of a stream passed to `tokio_util::io::StreamReader`.
+#[derive(Debug)]
+pub enum Error {
+    /// An error from the underlying stream.
+    Io(io::Error),
+    /// An error from an OpenSSL TLS operation.
+    Ssl(ssl::Error),
+    /// An error from configuring an OpenSSL object.
+    Stack(ErrorStack),
+    /// A failed TLS handshake.
+    Handshake(HandshakeError),
+    /// The server's certificate chain did not match any of the public key pins configured with
+    /// [`SslStreamConnectorBuilder::set_spki_pins`](crate::SslStreamConnectorBuilder::set_spki_pins).
+    PinMismatch,
+    /// The peer's certificate chain was rejected by an asynchronous verification callback.
+    Verify(Box<dyn error::Error + Send + Sync>),
+    /// The server's stapled OCSP response was missing or invalid under the policy configured
+    /// with
+    /// [`SslStreamConnectorBuilder::set_ocsp_stapling`](crate::SslStreamConnectorBuilder::set_ocsp_stapling).
+    Ocsp(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(fmt, "I/O error: {}", e),
+            // ssl::Error renders the OpenSSL error stack, which is the useful part of the message
+            Error::Ssl(e) => write!(fmt, "TLS error: {}", e),
+            Error::Stack(e) => write!(fmt, "OpenSSL error: {}", e),
+            Error::Handshake(e) => fmt::Display::fmt(e, fmt),
+            Error::PinMismatch => fmt.write_str("server certificate did not match any pinned key"),
+            Error::Verify(e) => write!(fmt, "certificate verification failed: {}", e),
+            Error::Ocsp(e) => write!(fmt, "OCSP stapling verification failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Ssl(e) => Some(e),
+            Error::Stack(e) => Some(e),
+            Error::Handshake(e) => Some(e),
+            Error::PinMismatch => None,
+            Error::Verify(e) => Some(&**e),
+            Error::Ocsp(_) => None,
+        }
+    }
+}
+
+impl Error {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(_) => ErrorKind::Io,
+            Error::Ssl(_) => ErrorKind::Ssl,
+            Error::Stack(_) => ErrorKind::Stack,
+            Error::Handshake(_) => ErrorKind::Handshake,
+            Error::PinMismatch => ErrorKind::PinMismatch,
+            Error::Verify(_) => ErrorKind::Verify,
+            Error::Ocsp(_) => ErrorKind::Ocsp,
+        }
+    }
+
+    /// Returns an owned summary of this error, consisting of its kind and message.
+    pub fn info(&self) -> ErrorInfo {
+        ErrorInfo {
+            kind: self.kind(),
+            message: self.to_string(),
+        }
+    }
+}
+
+/// Serializes the error as its [`ErrorInfo`].
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.info().serialize(serializer)
+    }
+}
+
+/// The category of an [`Error`], corresponding to its variant.
+///
+/// With the `serde` feature, this is represented by its name in `snake_case`, such as
+/// `"pin_mismatch"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ErrorKind {
+    /// [`Error::Io`].
+    Io,
+    /// [`Error::Ssl`].
+    Ssl,
+    /// [`Error::Stack`].
+    Stack,
+    /// [`Error::Handshake`].
+    Handshake,
+    /// [`Error::PinMismatch`].
+    PinMismatch,
+    /// [`Error::Verify`].
+    Verify,
+    /// [`Error::Ocsp`].
+    Ocsp,
+}
+
+/// An owned summary of an [`Error`], returned by [`Error::info`].
+///
+/// The OpenSSL error stack and the underlying errors can't be reconstructed, so this records only
+/// the kind of the error and its rendered message. With the `serde` feature, this implements
+/// `Serialize` and `Deserialize`, and is also how `Error` itself is serialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorInfo {
+    /// The category of the error.
+    pub kind: ErrorKind,
+    /// The error's `Display` output.
+    pub message: String,
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<ssl::Error> for Error {
+    fn from(e: ssl::Error) -> Error {
+        Error::Ssl(e)
+    }
+}
+
+impl From<ErrorStack> for Error {
+    fn from(e: ErrorStack) -> Error {
+        Error::Stack(e)
+    }
+}
+
+impl From<HandshakeError> for Error {
+    fn from(e: HandshakeError) -> Error {
+        Error::Handshake(e)
+    }
+}
+
+/// Converts into an [`io::Error`] wrapping the `Error`, unless it originates from the underlying
+/// stream, in which case the stream's error is returned.
+///
+/// TLS protocol failures, including rejected certificates, have the kind
+/// [`InvalidData`](io::ErrorKind::InvalidData), and other OpenSSL failures the kind
+/// [`Other`](io::ErrorKind::Other).
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            Error::Ssl(e) => crate::to_io_error(e),
+            Error::Handshake(e) if e.error.io_error().is_some() => crate::to_io_error(e.error),
+            e => {
+                let kind = match &e {
+                    Error::Handshake(e) => ssl_error_kind(&e.error),
+                    Error::PinMismatch | Error::Verify(_) | Error::Ocsp(_) => {
+                        io::ErrorKind::InvalidData
+                    }
+                    _ => io::ErrorKind::Other,
+                };
+                io::Error::new(kind, e)
+            }
+        }
+    }
+}
+
+/// Returns the kind of the [`io::Error`] reporting an OpenSSL error which didn't originate from
+/// the underlying stream.
+pub(crate) fn ssl_error_kind(e: &ssl::Error) -> io::ErrorKind {
+    if e.code() == ErrorCode::SSL {
+        io::ErrorKind::InvalidData
+    } else {
+        io::ErrorKind::Other
+    }
+}
+
+/// A failed TLS handshake, along with the peer's certificate verification state.
+#[derive(Debug)]
+pub struct HandshakeError {
+    error: ssl::Error,
+    verify_result: X509VerifyResult,
+    peer_certificates: Vec<X509>,
+}
+
+impl HandshakeError {
+    /// Creates a new `HandshakeError`, capturing the verification state of `ssl` at the time of
+    /// the failure.
+    pub fn new(ssl: &SslRef, error: ssl::Error) -> HandshakeError {
+        HandshakeError {
+            error,
+            verify_result: ssl.verify_result(),
+            peer_certificates: ssl.peer_cert_chain().map_or_else(Vec::new, |chain| {
+                chain.iter().map(|c| c.to_owned()).collect()
+            }),
+        }
+    }
+
+    /// Returns the underlying OpenSSL error.
+    pub fn error(&self) -> &ssl::Error {
+        &self.error
+    }
+
+    /// Consumes the `HandshakeError`, returning the underlying OpenSSL error.
+    pub fn into_error(self) -> ssl::Error {
+        self.error
+    }
+
+    /// Returns the result of verifying the peer's certificate.
+    pub fn verify_result(&self) -> X509VerifyResult {
+        self.verify_result
+    }
+
+    /// Returns the certificate chain presented by the peer.
+    ///
+    /// OpenSSL versions before 3.0 discard the chain when it fails verification, in which case
+    /// this is empty.
+    pub fn peer_certificates(&self) -> &[X509] {
+        &self.peer_certificates
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.verify_result == X509VerifyResult::OK {
+            write!(fmt, "TLS handshake failed: {}", self.error)
+        } else {
+            write!(
+                fmt,
+                "TLS handshake failed: certificate verify failed: {}: {}",
+                self.verify_result.error_string(),
+                self.error
+            )
+        }
+    }
+}
+
+impl error::Error for HandshakeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
@@ -0,0 +1,59 @@
+//! Process-wide ex_data indexes, allocated on first use.
+
+use openssl::error::ErrorStack;
+use openssl::ex_data::Index;
+use openssl::ssl::{Ssl, SslContext};
+use std::sync::OnceLock;
+
+/// An ex_data index of `O` holding a `T`, shared by every object of the process.
+///
+/// OpenSSL never frees ex_data indexes, so each one is allocated once and kept in a static.
+pub(crate) struct ExIndex<O, T>(OnceLock<Index<O, T>>);
+
+impl<O, T> ExIndex<O, T> {
+    pub(crate) const fn new() -> ExIndex<O, T> {
+        ExIndex(OnceLock::new())
+    }
+
+    /// Returns the index if it has been allocated.
+    ///
+    /// No object can hold data at an index which hasn't been allocated yet, so lookups can use
+    /// this rather than allocating.
+    pub(crate) fn get(&self) -> Option<Index<O, T>> {
+        self.0.get().copied()
+    }
+
+    fn get_or_try_init<F>(&self, new: F) -> Result<Index<O, T>, ErrorStack>
+    where
+        F: FnOnce() -> Result<Index<O, T>, ErrorStack>,
+    {
+        match self.get() {
+            Some(index) => Ok(index),
+            None => {
+                // a racing thread may allocate an index too, in which case ours goes unused
+                let index = new()?;
+                Ok(*self.0.get_or_init(|| index))
+            }
+        }
+    }
+}
+
+impl<T> ExIndex<Ssl, T>
+where
+    T: 'static + Sync + Send,
+{
+    /// Returns the index, allocating it if necessary.
+    pub(crate) fn index(&self) -> Result<Index<Ssl, T>, ErrorStack> {
+        self.get_or_try_init(Ssl::new_ex_index)
+    }
+}
+
+impl<T> ExIndex<SslContext, T>
+where
+    T: 'static + Sync + Send,
+{
+    /// Returns the index, allocating it if necessary.
+    pub(crate) fn index(&self) -> Result<Index<SslContext, T>, ErrorStack> {
+        self.get_or_try_init(SslContext::new_ex_index)
+    }
+}
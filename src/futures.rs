@@ -0,0 +1,191 @@
+use crate::SslStream;
+use openssl::ssl;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Future returned by [`SslStream::connect`](crate::SslStream::connect) and
+/// [`SslStreamExt::connect`](crate::SslStreamExt::connect).
+///
+/// Dropping this future leaves the handshake suspended; it can be resumed by polling a new one.
+#[must_use = "handshake futures must be awaited"]
+#[derive(Debug)]
+pub struct Connect<'a, S> {
+    stream: Pin<&'a mut SslStream<S>>,
+}
+
+impl<'a, S> Connect<'a, S> {
+    pub(crate) fn new(stream: Pin<&'a mut SslStream<S>>) -> Self {
+        Connect { stream }
+    }
+}
+
+impl<S> Future for Connect<'_, S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    type Output = Result<(), ssl::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.stream.as_mut().poll_connect(cx)
+    }
+}
+
+/// Future returned by [`SslStream::accept`](crate::SslStream::accept) and
+/// [`SslStreamExt::accept`](crate::SslStreamExt::accept).
+///
+/// Dropping this future leaves the handshake suspended; it can be resumed by polling a new one.
+#[must_use = "handshake futures must be awaited"]
+#[derive(Debug)]
+pub struct Accept<'a, S> {
+    stream: Pin<&'a mut SslStream<S>>,
+}
+
+impl<'a, S> Accept<'a, S> {
+    pub(crate) fn new(stream: Pin<&'a mut SslStream<S>>) -> Self {
+        Accept { stream }
+    }
+}
+
+impl<S> Future for Accept<'_, S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    type Output = Result<(), ssl::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.stream.as_mut().poll_accept(cx)
+    }
+}
+
+/// Future returned by [`SslStream::do_handshake`](crate::SslStream::do_handshake) and
+/// [`SslStreamExt::do_handshake`](crate::SslStreamExt::do_handshake).
+///
+/// Dropping this future leaves the handshake suspended; it can be resumed by polling a new one.
+#[must_use = "handshake futures must be awaited"]
+#[derive(Debug)]
+pub struct DoHandshake<'a, S> {
+    stream: Pin<&'a mut SslStream<S>>,
+}
+
+impl<'a, S> DoHandshake<'a, S> {
+    pub(crate) fn new(stream: Pin<&'a mut SslStream<S>>) -> Self {
+        DoHandshake { stream }
+    }
+}
+
+impl<S> Future for DoHandshake<'_, S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    type Output = Result<(), ssl::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.stream.as_mut().poll_do_handshake(cx)
+    }
+}
+
+/// Future returned by [`SslStream::into_connect`](crate::SslStream::into_connect).
+pub type ConnectFuture<S> = HandshakeFuture<S>;
+
+/// Future returned by [`SslStream::into_accept`](crate::SslStream::into_accept).
+pub type AcceptFuture<S> = HandshakeFuture<S>;
+
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    Connect,
+    Accept,
+    Either,
+}
+
+/// Future returned by [`SslStream::into_connect`](crate::SslStream::into_connect),
+/// [`SslStream::into_accept`](crate::SslStream::into_accept) and
+/// [`SslStream::into_handshake`](crate::SslStream::into_handshake).
+///
+/// Unlike [`Connect`], [`Accept`] and [`DoHandshake`], this owns the stream, so it can be stored
+/// or spawned independently. The stream is returned once the handshake finishes. On failure it is
+/// returned alongside the error rather than dropped, so the peer's certificates and verification
+/// result can still be inspected.
+///
+/// The stream is moved between polls, so `S` must be [`Unpin`]. Streams which aren't can be
+/// wrapped in [`Box::pin`] first.
+#[must_use = "handshake futures must be awaited"]
+#[derive(Debug)]
+pub struct HandshakeFuture<S> {
+    stream: Option<SslStream<S>>,
+    mode: Mode,
+}
+
+impl<S> HandshakeFuture<S> {
+    pub(crate) fn connect(stream: SslStream<S>) -> Self {
+        HandshakeFuture::new(stream, Mode::Connect)
+    }
+
+    pub(crate) fn accept(stream: SslStream<S>) -> Self {
+        HandshakeFuture::new(stream, Mode::Accept)
+    }
+
+    pub(crate) fn handshake(stream: SslStream<S>) -> Self {
+        HandshakeFuture::new(stream, Mode::Either)
+    }
+
+    fn new(stream: SslStream<S>, mode: Mode) -> Self {
+        HandshakeFuture {
+            stream: Some(stream),
+            mode,
+        }
+    }
+}
+
+impl<S> Future for HandshakeFuture<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Output = Result<SslStream<S>, (ssl::Error, SslStream<S>)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mode = self.mode;
+        let stream = self
+            .stream
+            .as_mut()
+            .expect("HandshakeFuture polled after completion");
+        let stream = Pin::new(stream);
+        let r = match mode {
+            Mode::Connect => stream.poll_connect(cx),
+            Mode::Accept => stream.poll_accept(cx),
+            Mode::Either => stream.poll_do_handshake(cx),
+        };
+        match r {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(self.stream.take().unwrap())),
+            Poll::Ready(Err(e)) => Poll::Ready(Err((e, self.stream.take().unwrap()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Future returned by [`SslStreamExt::ssl_read`](crate::SslStreamExt::ssl_read).
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+#[derive(Debug)]
+pub struct SslRead<'a, S> {
+    stream: Pin<&'a mut SslStream<S>>,
+    buf: &'a mut [u8],
+}
+
+impl<'a, S> SslRead<'a, S> {
+    pub(crate) fn new(stream: Pin<&'a mut SslStream<S>>, buf: &'a mut [u8]) -> Self {
+        SslRead { stream, buf }
+    }
+}
+
+impl<S> Future for SslRead<'_, S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    type Output = Result<usize, ssl::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.stream.as_mut().poll_ssl_read(cx, this.buf)
+    }
+}
@@ -0,0 +1,249 @@
+use crate::SslStream;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// A hook observing the plaintext passing through a [`HookedSslStream`].
+///
+/// The methods are called synchronously from within the stream's polls, so they should be cheap
+/// and must not block.
+pub trait IoHook {
+    /// Called with the plaintext returned by each successful read, after decryption.
+    fn on_read(&self, bytes: &[u8]);
+
+    /// Called with the plaintext accepted by each successful write, before encryption.
+    fn on_write(&self, bytes: &[u8]);
+}
+
+impl<H> IoHook for &H
+where
+    H: IoHook + ?Sized,
+{
+    fn on_read(&self, bytes: &[u8]) {
+        (**self).on_read(bytes)
+    }
+
+    fn on_write(&self, bytes: &[u8]) {
+        (**self).on_write(bytes)
+    }
+}
+
+impl<H> IoHook for Arc<H>
+where
+    H: IoHook + ?Sized,
+{
+    fn on_read(&self, bytes: &[u8]) {
+        (**self).on_read(bytes)
+    }
+
+    fn on_write(&self, bytes: &[u8]) {
+        (**self).on_write(bytes)
+    }
+}
+
+/// An [`SslStream`] which reports the plaintext it reads and writes to an [`IoHook`], for
+/// debugging protocol issues.
+///
+/// Only the bytes actually returned by a read or accepted by a write are reported, so a write
+/// which returns `Pending` or fails is not reported, and a partial write reports only the part
+/// that was written.
+#[derive(Debug)]
+pub struct HookedSslStream<S, H> {
+    inner: SslStream<S>,
+    hook: H,
+}
+
+impl<S, H> HookedSslStream<S, H> {
+    /// Wraps a stream.
+    pub fn new(inner: SslStream<S>, hook: H) -> HookedSslStream<S, H> {
+        HookedSslStream { inner, hook }
+    }
+
+    /// Returns a shared reference to the wrapped stream.
+    pub fn get_ref(&self) -> &SslStream<S> {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped stream.
+    pub fn get_mut(&mut self) -> &mut SslStream<S> {
+        &mut self.inner
+    }
+
+    /// Returns a pinned mutable reference to the wrapped stream.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut SslStream<S>> {
+        self.parts().0
+    }
+
+    /// Returns a shared reference to the hook.
+    pub fn hook(&self) -> &H {
+        &self.hook
+    }
+
+    /// Consumes the wrapper, returning the wrapped stream and the hook.
+    pub fn into_inner(self) -> (SslStream<S>, H) {
+        (self.inner, self.hook)
+    }
+
+    fn parts(self: Pin<&mut Self>) -> (Pin<&mut SslStream<S>>, &H) {
+        unsafe {
+            let this = self.get_unchecked_mut();
+            (Pin::new_unchecked(&mut this.inner), &this.hook)
+        }
+    }
+}
+
+impl<S, H> AsyncRead for HookedSslStream<S, H>
+where
+    S: AsyncRead + AsyncWrite,
+    H: IoHook,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let (inner, hook) = self.parts();
+        let filled = buf.filled().len();
+        let r = inner.poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = r {
+            let bytes = &buf.filled()[filled..];
+            if !bytes.is_empty() {
+                hook.on_read(bytes);
+            }
+        }
+        r
+    }
+}
+
+impl<S, H> AsyncWrite for HookedSslStream<S, H>
+where
+    S: AsyncRead + AsyncWrite,
+    H: IoHook,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let (inner, hook) = self.parts();
+        let r = inner.poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = r {
+            if n > 0 {
+                hook.on_write(&buf[..n]);
+            }
+        }
+        r
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_pin_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_pin_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "hooks")]
+pub use self::file::FileIoHook;
+
+#[cfg(feature = "hooks")]
+mod file {
+    use super::IoHook;
+    use std::fmt::Write as _;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tokio::fs::File;
+    use tokio::io::AsyncWriteExt;
+    use tokio::sync::mpsc;
+
+    /// The number of dumps queued for the writer task before further dumps are dropped.
+    const QUEUE_LEN: usize = 1024;
+
+    /// An [`IoHook`] which writes hex dumps of the plaintext to a file.
+    ///
+    /// Each line holds up to 16 bytes, prefixed by the direction and the offset within the
+    /// stream's reads or writes:
+    ///
+    /// ```text
+    /// read  00000000  48 54 54 50 2f 31 2e 31 20 32 30 30 20 4f 4b 0d  |HTTP/1.1 200 OK.|
+    /// ```
+    ///
+    /// Dumps are written by a background task, so polls never wait for file I/O. If the task
+    /// falls behind, further dumps are dropped. The file contains the connection's plaintext, so
+    /// this should only be used for debugging.
+    ///
+    /// Requires the `hooks` Cargo feature.
+    pub struct FileIoHook {
+        tx: mpsc::Sender<String>,
+        read: AtomicU64,
+        written: AtomicU64,
+    }
+
+    impl FileIoHook {
+        /// Creates a hook which appends dumps to `file`.
+        ///
+        /// # Panics
+        ///
+        /// Panics if called outside of a Tokio runtime.
+        pub fn new(mut file: File) -> FileIoHook {
+            let (tx, mut rx) = mpsc::channel::<String>(QUEUE_LEN);
+
+            // the task exits once the hook, and with it the sender, is dropped
+            tokio::spawn(async move {
+                while let Some(dump) = rx.recv().await {
+                    if file.write_all(dump.as_bytes()).await.is_err() || file.flush().await.is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+
+            FileIoHook {
+                tx,
+                read: AtomicU64::new(0),
+                written: AtomicU64::new(0),
+            }
+        }
+
+        fn dump(&self, direction: &str, offset: &AtomicU64, bytes: &[u8]) {
+            let mut offset = offset.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            let mut dump = String::new();
+            for chunk in bytes.chunks(16) {
+                let _ = write!(dump, "{:<5} {:08x} ", direction, offset);
+                for i in 0..16 {
+                    match chunk.get(i) {
+                        Some(b) => {
+                            let _ = write!(dump, " {:02x}", b);
+                        }
+                        None => dump.push_str("   "),
+                    }
+                }
+                dump.push_str("  |");
+                dump.extend(chunk.iter().map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                }));
+                dump.push_str("|\n");
+                offset += chunk.len() as u64;
+            }
+
+            // drop dumps rather than blocking the stream if the writer falls behind
+            let _ = self.tx.try_send(dump);
+        }
+    }
+
+    impl IoHook for FileIoHook {
+        fn on_read(&self, bytes: &[u8]) {
+            self.dump("read", &self.read, bytes);
+        }
+
+        fn on_write(&self, bytes: &[u8]) {
+            self.dump("write", &self.written, bytes);
+        }
+    }
+}
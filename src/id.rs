@@ -0,0 +1,9 @@
+/// An opaque identifier for an [`SslStream`](crate::SslStream), returned by
+/// [`SslStream::id`](crate::SslStream::id).
+///
+/// IDs are cheap to copy and hash, making them suitable as keys in connection tables. An ID is
+/// only unique while its stream is alive: once the stream is dropped, a new stream may be assigned
+/// the same ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SslStreamId(pub(crate) usize);
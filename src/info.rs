@@ -0,0 +1,124 @@
+use openssl::ssl::{NameType, SslRef, SslVersion};
+use openssl::x509::X509;
+
+/// An owned summary of an established TLS session, returned by
+/// [`SslStream::connection_info`](crate::SslStream::connection_info).
+///
+/// With the `serde` feature, this implements `Serialize` and `Deserialize`. The version is
+/// represented by its name, such as `"TLSv1.3"`, and the peer certificate by its DER encoding.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionInfo {
+    /// The negotiated protocol version.
+    #[cfg_attr(feature = "serde", serde(with = "version"))]
+    pub version: SslVersion,
+    /// The name of the negotiated cipher.
+    pub cipher: String,
+    /// The protocol selected via Application Layer Protocol Negotiation (ALPN), if any.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub alpn: Option<Vec<u8>>,
+    /// The host name sent by the client via Server Name Indication (SNI), if any.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub sni: Option<String>,
+    /// Whether the session was resumed rather than negotiated from scratch.
+    pub session_reused: bool,
+    /// The peer's certificate, if present.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none", with = "certificate")
+    )]
+    pub peer_certificate: Option<X509>,
+}
+
+impl ConnectionInfo {
+    /// Returns `None` if the handshake has not completed.
+    pub(crate) fn new(ssl: &SslRef) -> Option<ConnectionInfo> {
+        if !ssl.is_init_finished() {
+            return None;
+        }
+
+        Some(ConnectionInfo {
+            version: ssl.version2()?,
+            cipher: ssl.current_cipher()?.name().to_string(),
+            alpn: ssl.selected_alpn_protocol().map(|p| p.to_vec()),
+            sni: ssl.servername(NameType::HOST_NAME).map(|s| s.to_string()),
+            session_reused: ssl.session_reused(),
+            peer_certificate: ssl.peer_certificate(),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+mod version {
+    use openssl::ssl::SslVersion;
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::ser::{self, Serializer};
+
+    const VERSIONS: &[(SslVersion, &str)] = &[
+        (SslVersion::SSL3, "SSLv3"),
+        (SslVersion::TLS1, "TLSv1"),
+        (SslVersion::TLS1_1, "TLSv1.1"),
+        (SslVersion::TLS1_2, "TLSv1.2"),
+        #[cfg(ossl111)]
+        (SslVersion::TLS1_3, "TLSv1.3"),
+    ];
+
+    pub(super) fn serialize<S>(version: &SslVersion, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let name = VERSIONS
+            .iter()
+            .find(|(v, _)| v == version)
+            .map(|(_, name)| *name)
+            .ok_or_else(|| ser::Error::custom("unknown protocol version"))?;
+        serializer.serialize_str(name)
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<SslVersion, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+        VERSIONS
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(version, _)| *version)
+            .ok_or_else(|| de::Error::custom(format!("unknown protocol version `{}`", name)))
+    }
+}
+
+#[cfg(feature = "serde")]
+mod certificate {
+    use openssl::x509::X509;
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::ser::{self, Serializer};
+
+    pub(super) fn serialize<S>(cert: &Option<X509>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match cert {
+            Some(cert) => {
+                let der = cert.to_der().map_err(ser::Error::custom)?;
+                serializer.serialize_some(&der)
+            }
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Option<X509>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<Vec<u8>>::deserialize(deserializer)?
+            .map(|der| X509::from_der(&der).map_err(de::Error::custom))
+            .transpose()
+    }
+}
@@ -0,0 +1,55 @@
+//! NSS key log output, for decrypting captured traffic with tools such as Wireshark.
+//!
+//! The secrets of every connection made with a configured context are appended to a file in
+//! the NSS key log format. Lines are written by a background thread, so handshakes never wait for
+//! file I/O. If the thread falls behind, further lines are dropped. The secrets allow anyone with
+//! access to the file to decrypt the connections, so this should only be used for debugging.
+//!
+//! Configuring key logging replaces any key log callback previously set on the context.
+//!
+//! Requires OpenSSL 1.1.1 or newer.
+
+use openssl::ssl::SslContextBuilder;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+/// The number of lines queued for the writer thread before further lines are dropped.
+const QUEUE_LEN: usize = 1024;
+
+/// Appends the secrets of every connection made with `builder` to the file at `path`.
+pub fn set_keylog_file(builder: &mut SslContextBuilder, path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_LEN);
+
+    // the thread exits once the context, and with it the sender, is dropped
+    thread::Builder::new()
+        .name("tokio-openssl-keylog".to_string())
+        .spawn(move || {
+            for line in rx {
+                if file.write_all(line.as_bytes()).is_err() {
+                    break;
+                }
+            }
+        })?;
+
+    builder.set_keylog_callback(move |_, line| {
+        // drop lines rather than blocking the handshake if the writer falls behind
+        let _ = tx.try_send(format!("{}\n", line));
+    });
+    Ok(())
+}
+
+/// Like [`set_keylog_file`], but writes to the file named by the `SSLKEYLOGFILE` environment
+/// variable.
+///
+/// Does nothing if the variable is not set.
+pub fn set_keylog_from_env(builder: &mut SslContextBuilder) -> io::Result<()> {
+    match env::var_os("SSLKEYLOGFILE") {
+        Some(path) if !path.is_empty() => set_keylog_file(builder, Path::new(&path)),
+        _ => Ok(()),
+    }
+}
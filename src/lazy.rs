@@ -0,0 +1,122 @@
+//! Deferred selection of a server's configuration until the ClientHello has been read.
+
+use crate::client_hello::{self, ClientHello};
+use crate::{Error, HandshakeError, SslStream};
+use openssl::error::ErrorStack;
+use openssl::ssl::{ErrorCode, Ssl, SslAcceptorBuilder, SslContext, SslContextRef};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The configuration every handshake of a [`LazyConfigAcceptor`] starts with.
+///
+/// The base context supplies the protocol versions, cipher suites and options of the
+/// handshakes, while the context chosen once the ClientHello has been read supplies the
+/// certificate. It is cheap to clone, so a single base can be shared by all of the tasks of a
+/// server.
+///
+/// Requires OpenSSL 1.1.1 or newer.
+#[derive(Clone)]
+pub struct LazyAcceptor {
+    context: SslContext,
+}
+
+impl LazyAcceptor {
+    /// Creates a new base from an [`SslAcceptorBuilder`].
+    ///
+    /// This replaces any client hello callback previously configured on the builder.
+    pub fn new(mut builder: SslAcceptorBuilder) -> Result<LazyAcceptor, ErrorStack> {
+        client_hello::pause_on_client_hello(&mut builder)?;
+        Ok(LazyAcceptor {
+            context: builder.build().into_context(),
+        })
+    }
+
+    /// Returns a [`LazyConfigAcceptor`] which reads the ClientHello from `stream`.
+    pub fn accept<S>(&self, stream: S) -> Result<LazyConfigAcceptor<S>, ErrorStack>
+    where
+        S: AsyncRead + AsyncWrite,
+    {
+        let ssl = Ssl::new(&self.context)?;
+        Ok(LazyConfigAcceptor {
+            stream: Some(SslStream::new(ssl, stream)?),
+        })
+    }
+}
+
+/// A future which reads the ClientHello of a server-side connection, so that the
+/// [`SslContext`] used for the handshake can be chosen based on it.
+///
+/// The future is created by [`LazyAcceptor::accept`], and resolves to a [`StartHandshake`] once
+/// the ClientHello has arrived, which either finishes the handshake with the chosen context, or
+/// is dropped to close the connection without handshaking. It fails with a [`HandshakeError`]
+/// if the ClientHello is malformed. A client which never sends a ClientHello leaves the future
+/// pending forever, so callers should impose a timeout.
+pub struct LazyConfigAcceptor<S> {
+    stream: Option<SslStream<S>>,
+}
+
+impl<S> Future for LazyConfigAcceptor<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Output = Result<StartHandshake<S>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let stream = self
+            .stream
+            .as_mut()
+            .expect("LazyConfigAcceptor polled after completion");
+        let r = match Pin::new(stream).poll_accept(cx) {
+            Poll::Ready(r) => r,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let mut stream = self.stream.take().unwrap();
+        let e = match r {
+            Ok(()) => unreachable!("the handshake can't complete before the client hello callback"),
+            Err(e) => e,
+        };
+        if e.code() != ErrorCode::WANT_CLIENT_HELLO_CB {
+            return Poll::Ready(Err(HandshakeError::new(stream.ssl(), e).into()));
+        }
+
+        let hello = client_hello::take(stream.inner.ssl_mut()).expect("client hello missing");
+        Poll::Ready(Ok(StartHandshake { stream, hello }))
+    }
+}
+
+/// A server-side connection whose ClientHello has been read, returned by
+/// [`LazyConfigAcceptor`].
+pub struct StartHandshake<S> {
+    stream: SslStream<S>,
+    hello: ClientHello,
+}
+
+impl<S> StartHandshake<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Returns the ClientHello sent by the client.
+    pub fn client_hello(&self) -> &ClientHello {
+        &self.hello
+    }
+
+    /// Completes the handshake using `context`.
+    ///
+    /// The certificate, private key, verification settings and callbacks OpenSSL looks up
+    /// through the context, such as ALPN selection, are taken from `context`. Protocol versions,
+    /// cipher suites and options were fixed by the [`LazyAcceptor`] when the connection was
+    /// created, and are not changed.
+    pub async fn into_stream(mut self, context: &SslContextRef) -> Result<SslStream<S>, Error> {
+        let ssl = self.stream.inner.ssl_mut();
+        client_hello::resume(ssl);
+        crate::switch_context(ssl, context)?;
+
+        match Pin::new(&mut self.stream).accept().await {
+            Ok(()) => Ok(self.stream),
+            Err(e) => Err(HandshakeError::new(self.stream.ssl(), e).into()),
+        }
+    }
+}
@@ -3,54 +3,241 @@
 //! This crate provides a wrapper around the [`openssl`] crate's [`SslStream`](ssl::SslStream) type
 //! that works with with [`tokio`]'s [`AsyncRead`] and [`AsyncWrite`] traits rather than std's
 //! blocking [`Read`] and [`Write`] traits.
+//!
+//! When the underlying stream is [`Unpin`], the methods of [`SslStreamExt`] can be used to drive
+//! the handshake without pinning the stream manually:
+//!
+//! ```no_run
+//! use openssl::ssl::{SslConnector, SslMethod};
+//! use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//! use tokio::net::TcpStream;
+//! use tokio_openssl::{SslStream, SslStreamExt};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let connector = SslConnector::builder(SslMethod::tls())?.build();
+//! let ssl = connector.configure()?.into_ssl("google.com")?;
+//! let stream = TcpStream::connect("google.com:443").await?;
+//! let mut stream = SslStream::new(ssl, stream)?;
+//!
+//! stream.connect().await?;
+//! stream.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+//!
+//! let mut response = vec![];
+//! stream.read_to_end(&mut response).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! When the `tracing` feature is enabled, handshake progress and I/O are reported as `tracing`
+//! events with the `tokio_openssl` target.
+//!
+//! The `metrics` feature enables process-wide counters, which are exposed by the `metrics`
+//! module.
+//!
+//! The `net` feature enables [`SslListener`] and the integrations with Tokio's sockets, and the
+//! `rt` feature enables the helpers which spawn tasks onto the Tokio runtime: asynchronous
+//! session caches, OCSP response refreshing and ticket key rotation.
 #![warn(missing_docs)]
 
+use crate::shutdown::{
+    _ShouldKeepPollSslRead, cvt_shutdown_ssl_read_ossl, Shutdown, ShutdownState,
+    SHUTDOWN_DRAIN_ITERATIONS_PER_POLL,
+};
+use crate::timeout::Timeout;
+use foreign_types::ForeignTypeRef;
 use futures_util::future;
 use openssl::error::ErrorStack;
-use openssl::ssl::{self, ErrorCode, ShutdownResult, Ssl, SslRef};
+use openssl::hash::{DigestBytes, MessageDigest};
+#[cfg(ossl110)]
+use openssl::ssl::SslVersion;
+use openssl::ssl::{
+    self, ErrorCode, NameType, ShutdownResult, Ssl, SslCipherRef, SslContextRef, SslRef,
+    SslSessionRef,
+};
+#[cfg(ossl110)]
+use openssl::stack::Stack;
+use openssl::stack::StackRef;
+use openssl::x509::{X509VerifyResult, X509};
 use std::fmt;
+use std::future::Future;
 use std::io::{self, Read, Write};
+use std::mem;
+use std::os::raw::c_int;
 use std::pin::Pin;
-use std::slice;
+use std::ptr::NonNull;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
+mod acceptor;
+mod alpn;
+mod boxed;
+mod buffered;
+#[cfg(ossl111)]
+mod channel_binding;
+#[cfg(ossl111)]
+mod client_hello;
+mod connector;
+mod copy;
+mod error;
+mod ex_data;
+mod futures;
+mod hook;
+mod id;
+#[cfg(ossl110)]
+mod info;
+#[cfg(ossl111)]
+pub mod keylog;
+#[cfg(ossl111)]
+mod lazy;
+#[cfg(feature = "net")]
+mod listener;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod ocsp;
+mod os;
+mod pin;
+#[cfg(feature = "tower")]
+mod service;
+mod session_cache;
+mod shutdown;
+mod sni;
+mod sync;
 #[cfg(test)]
 mod test;
+#[cfg(test)]
+mod test_utils;
+mod ticket_keys;
+mod timeout;
+mod verify;
+mod want;
+
+#[cfg(ossl111)]
+pub use crate::acceptor::SniFuture;
+pub use crate::acceptor::{SslStreamAcceptor, SslStreamAcceptorBuilder};
+pub use crate::alpn::AlpnDispatcher;
+pub use crate::boxed::BoxedSslStream;
+pub use crate::buffered::BufferedSslStream;
+#[cfg(ossl111)]
+pub use crate::channel_binding::ChannelBinding;
+#[cfg(ossl111)]
+pub use crate::client_hello::ClientHello;
+pub use crate::connector::{ConnectorSession, SslStreamConnector, SslStreamConnectorBuilder};
+pub use crate::copy::{copy_bidirectional_tls, copy_bidirectional_tls_with_sizes};
+pub use crate::error::{Error, ErrorInfo, ErrorKind, HandshakeError};
+pub use crate::futures::{
+    Accept, AcceptFuture, Connect, ConnectFuture, DoHandshake, HandshakeFuture, SslRead,
+};
+#[cfg(feature = "hooks")]
+pub use crate::hook::FileIoHook;
+pub use crate::hook::{HookedSslStream, IoHook};
+pub use crate::id::SslStreamId;
+#[cfg(ossl110)]
+pub use crate::info::ConnectionInfo;
+#[cfg(ossl111)]
+pub use crate::lazy::{LazyAcceptor, LazyConfigAcceptor, StartHandshake};
+#[cfg(feature = "net")]
+pub use crate::listener::{Listener, SslListener};
+pub use crate::ocsp::{OcspFetchFuture, OcspStapler, OcspStaplingPolicy};
+pub use crate::os::TakeError;
+pub use crate::pin::PinMode;
+#[cfg(feature = "tower")]
+pub use crate::service::{ConnectorService, ConnectorServiceFuture};
+pub use crate::session_cache::{MemorySessionCache, SessionCache, SessionCacheFuture, SessionKey};
+pub use crate::shutdown::ShutdownMode;
+pub use crate::sni::{SniAcceptor, SniAcceptorBuilder};
+pub use crate::sync::SyncSslStream;
+pub use crate::ticket_keys::TicketKeyManager;
+pub use crate::verify::VerifyFuture;
 
+/// Adapts an async stream to the blocking `Read` and `Write` traits OpenSSL's BIO expects.
+///
+/// The task context is only available while [`SslStream::with_context`] runs, so it is stashed in
+/// the wrapper for the duration of that call and cleared afterwards. I/O attempted at any other
+/// time fails rather than touching a stale context.
 struct StreamWrapper<S> {
-    stream: S,
-    context: usize,
+    /// Only `None` while [`SslStream::into_std`] is moving the stream out.
+    stream: Option<S>,
+    /// Points to the `Context` of the poll in progress, if any. The `'static` lifetime is a lie;
+    /// the pointer is only dereferenced while the real `Context` is borrowed by `with_context`.
+    context: Option<NonNull<Context<'static>>>,
 }
 
+// SAFETY: the context pointer is only set while `with_context` holds a unique borrow of the
+// wrapper and is cleared before that borrow ends, so it is never observed from another thread.
+unsafe impl<S: Send> Send for StreamWrapper<S> {}
+unsafe impl<S: Sync> Sync for StreamWrapper<S> {}
+
 impl<S> fmt::Debug for StreamWrapper<S>
 where
     S: fmt::Debug,
 {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(&self.stream, fmt)
+        fmt::Debug::fmt(self.stream(), fmt)
     }
 }
 
 impl<S> StreamWrapper<S> {
+    fn new(stream: S) -> Self {
+        StreamWrapper {
+            stream: Some(stream),
+            context: None,
+        }
+    }
+
+    fn stream(&self) -> &S {
+        self.stream.as_ref().expect("stream taken")
+    }
+
+    fn stream_mut(&mut self) -> &mut S {
+        self.stream.as_mut().expect("stream taken")
+    }
+
+    /// Makes `cx` available to I/O performed through the wrapper.
+    ///
+    /// # Safety
+    ///
+    /// `cx` must stay alive until [`clear_context`](Self::clear_context) is called.
+    unsafe fn set_context(&mut self, cx: &mut Context<'_>) {
+        debug_assert!(self.context.is_none(), "reentrant poll of an SslStream");
+        self.context = Some(NonNull::from(cx).cast());
+    }
+
+    fn clear_context(&mut self) {
+        self.context = None;
+    }
+
+    /// Returns the pinned stream and the context of the poll in progress, or `None` if no poll is
+    /// in progress.
+    ///
     /// # Safety
     ///
-    /// Must be called with `context` set to a valid pointer to a live `Context` object, and the
-    /// wrapper must be pinned in memory.
-    unsafe fn parts(&mut self) -> (Pin<&mut S>, &mut Context<'_>) {
-        debug_assert_ne!(self.context, 0);
-        let stream = Pin::new_unchecked(&mut self.stream);
-        let context = &mut *(self.context as *mut _);
-        (stream, context)
+    /// The wrapper must be pinned in memory.
+    unsafe fn parts(&mut self) -> Option<(Pin<&mut S>, &mut Context<'_>)> {
+        let context = self.context?;
+        // SAFETY: `set_context` guarantees the pointee is alive until the pointer is cleared, and
+        // the returned borrow can't outlive the unique borrow of `self` that clearing requires.
+        let context = &mut *context.as_ptr().cast::<Context<'_>>();
+        // SAFETY: the caller guarantees that the wrapper, and so the stream, is pinned.
+        let stream = Pin::new_unchecked(self.stream_mut());
+        Some((stream, context))
     }
 }
 
+fn no_context() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "I/O attempted on an SslStream outside of a poll",
+    )
+}
+
 impl<S> Read for StreamWrapper<S>
 where
     S: AsyncRead,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let (stream, cx) = unsafe { self.parts() };
+        // SAFETY: the wrapper is owned by an `SslStream`, which is pinned while polled.
+        let (stream, cx) = unsafe { self.parts() }.ok_or_else(no_context)?;
         let mut buf = ReadBuf::new(buf);
         match stream.poll_read(cx, &mut buf)? {
             Poll::Ready(()) => Ok(buf.filled().len()),
@@ -64,15 +251,22 @@ where
     S: AsyncWrite,
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let (stream, cx) = unsafe { self.parts() };
+        // SAFETY: the wrapper is owned by an `SslStream`, which is pinned while polled.
+        let (stream, cx) = unsafe { self.parts() }.ok_or_else(no_context)?;
         match stream.poll_write(cx, buf) {
+            // OpenSSL would otherwise retry the write or report an unhelpful error
+            Poll::Ready(Ok(0)) if !buf.is_empty() => Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "underlying stream accepted zero bytes",
+            )),
             Poll::Ready(r) => r,
             Poll::Pending => Err(io::Error::from(io::ErrorKind::WouldBlock)),
         }
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        let (stream, cx) = unsafe { self.parts() };
+        // SAFETY: the wrapper is owned by an `SslStream`, which is pinned while polled.
+        let (stream, cx) = unsafe { self.parts() }.ok_or_else(no_context)?;
         match stream.poll_flush(cx) {
             Poll::Ready(r) => r,
             Poll::Pending => Err(io::Error::from(io::ErrorKind::WouldBlock)),
@@ -80,7 +274,8 @@ where
     }
 
     fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        let (stream, cx) = unsafe { self.parts() };
+        // SAFETY: the wrapper is owned by an `SslStream`, which is pinned while polled.
+        let (stream, cx) = unsafe { self.parts() }.ok_or_else(no_context)?;
         match stream.poll_write_vectored(cx, bufs) {
             Poll::Ready(r) => r,
             Poll::Pending => Err(io::Error::from(io::ErrorKind::WouldBlock)),
@@ -88,7 +283,24 @@ where
     }
 }
 
-fn cvt<T>(r: io::Result<T>) -> Poll<io::Result<T>> {
+/// Clears the context pointer of a stream when dropped, so it can't dangle if a poll unwinds.
+struct ContextGuard<'a, S>(&'a mut ssl::SslStream<StreamWrapper<S>>);
+
+impl<S> Drop for ContextGuard<'_, S> {
+    fn drop(&mut self) {
+        self.0.get_mut().clear_context();
+    }
+}
+
+/// The most data a vectored write gathers into a stack buffer, which is one full TLS record.
+const VECTORED_STACK_BUF_LEN: usize = 16 * 1024;
+
+/// Converts the result of an I/O operation on a nonblocking stream into a [`Poll`].
+///
+/// [`io::ErrorKind::WouldBlock`] errors become [`Poll::Pending`]. This is only correct if the
+/// operation registered the current task's waker before reporting `WouldBlock`, as the streams
+/// wrapped by [`SslStream`] do while it is being polled.
+pub fn poll_from_io_result<T>(r: io::Result<T>) -> Poll<io::Result<T>> {
     match r {
         Ok(v) => Poll::Ready(Ok(v)),
         Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
@@ -96,7 +308,69 @@ fn cvt<T>(r: io::Result<T>) -> Poll<io::Result<T>> {
     }
 }
 
-fn cvt_ossl<T>(r: Result<T, ssl::Error>) -> Poll<Result<T, ssl::Error>> {
+pub(crate) fn to_io_error(e: ssl::Error) -> io::Error {
+    e.into_io_error()
+        .unwrap_or_else(|e| io::Error::new(error::ssl_error_kind(&e), Error::Ssl(e)))
+}
+
+/// Like `Read for ssl::SslStream`, but reports a peer closing the transport without sending a
+/// close notify as an [`io::ErrorKind::UnexpectedEof`] error rather than a clean EOF unless
+/// `allow_unclean_eof` is set.
+///
+/// `read` performs a single read from the stream.
+fn ssl_read<S, F>(
+    s: &mut ssl::SslStream<S>,
+    allow_unclean_eof: bool,
+    mut read: F,
+) -> io::Result<usize>
+where
+    S: Read + Write,
+    F: FnMut(&mut ssl::SslStream<S>) -> Result<usize, ssl::Error>,
+{
+    loop {
+        match read(s) {
+            Ok(n) => return Ok(n),
+            Err(ref e) if e.code() == ErrorCode::ZERO_RETURN => return Ok(0),
+            Err(ref e) if e.code() == ErrorCode::WANT_READ && e.io_error().is_none() => {}
+            Err(ref e) if allow_unclean_eof && is_unexpected_eof(e) => return Ok(0),
+            Err(ref e) if is_unexpected_eof(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "peer closed connection without sending TLS close notify",
+                ))
+            }
+            Err(e) => return Err(to_io_error(e)),
+        }
+    }
+}
+
+fn is_unexpected_eof(e: &ssl::Error) -> bool {
+    match e.code() {
+        ErrorCode::SYSCALL => e.io_error().is_none(),
+        // OpenSSL 3 reports a truncated stream as a protocol error
+        #[cfg(ossl300)]
+        ErrorCode::SSL => e.ssl_error().map_or(false, |stack| {
+            stack.errors().iter().any(|e| {
+                openssl_sys::ERR_GET_LIB(e.code()) == openssl_sys::ERR_LIB_SSL
+                    && openssl_sys::ERR_GET_REASON(e.code())
+                        == openssl_sys::SSL_R_UNEXPECTED_EOF_WHILE_READING
+            })
+        }),
+        _ => false,
+    }
+}
+
+/// Converts the result of an OpenSSL operation on a nonblocking stream into a [`Poll`].
+///
+/// Errors with the [`WANT_READ`](ErrorCode::WANT_READ) and [`WANT_WRITE`](ErrorCode::WANT_WRITE)
+/// codes become [`Poll::Pending`]. As with [`poll_from_io_result`], this is only correct if the
+/// underlying stream registered the current task's waker before OpenSSL reported the error.
+///
+/// Retries requested by certificate and client hello callbacks, reported with the
+/// [`WANT_X509_LOOKUP`](ErrorCode::WANT_X509_LOOKUP) and `WANT_CLIENT_HELLO_CB` codes, are
+/// returned as errors, since without a [`Context`] nothing would wake the task to retry. The
+/// methods of [`SslStream`] retry them on the next poll instead.
+pub fn poll_from_ssl_result<T>(r: Result<T, ssl::Error>) -> Poll<Result<T, ssl::Error>> {
     match r {
         Ok(v) => Poll::Ready(Ok(v)),
         Err(e) => match e.code() {
@@ -106,9 +380,92 @@ fn cvt_ossl<T>(r: Result<T, ssl::Error>) -> Poll<Result<T, ssl::Error>> {
     }
 }
 
+/// Like [`poll_from_ssl_result`], but also treats retries requested by certificate and client
+/// hello callbacks as pending.
+///
+/// Those callbacks can't reach the task's waker, so it is woken here, and the operation is
+/// retried on the next poll until the callback lets it complete.
+fn poll_from_ssl_result_cx<T>(
+    cx: &Context<'_>,
+    r: Result<T, ssl::Error>,
+) -> Poll<Result<T, ssl::Error>> {
+    match r {
+        Err(ref e) if is_callback_retry(e) => {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+        r => poll_from_ssl_result(r),
+    }
+}
+
+fn is_callback_retry(e: &ssl::Error) -> bool {
+    match e.code() {
+        ErrorCode::WANT_X509_LOOKUP => true,
+        #[cfg(ossl111)]
+        ErrorCode::WANT_CLIENT_HELLO_CB => true,
+        _ => false,
+    }
+}
+
+extern "C" {
+    fn SSL_CTX_get_verify_callback(
+        ctx: *const openssl_sys::SSL_CTX,
+    ) -> Option<extern "C" fn(c_int, *mut openssl_sys::X509_STORE_CTX) -> c_int>;
+    fn SSL_CTX_get_verify_depth(ctx: *const openssl_sys::SSL_CTX) -> c_int;
+}
+
+/// Switches `ssl` to `context`, along with the context's verification settings.
+///
+/// `SSL_set_SSL_CTX` takes the certificate, private key and the callbacks OpenSSL looks up
+/// through the context, such as ALPN selection, from the new context. The verify mode, callback
+/// and depth are copied into each `SSL` when it is created though, so they are copied here too.
+pub(crate) fn switch_context(ssl: &mut SslRef, context: &SslContextRef) -> Result<(), ErrorStack> {
+    ssl.set_ssl_context(context)?;
+    // SAFETY: both pointers are valid, and a callback set through the openssl crate looks up its
+    // closure through the SSL's current context, which is now `context`.
+    unsafe {
+        let ctx = context.as_ptr();
+        openssl_sys::SSL_set_verify(
+            ssl.as_ptr(),
+            context.verify_mode().bits(),
+            SSL_CTX_get_verify_callback(ctx),
+        );
+        openssl_sys::SSL_set_verify_depth(ssl.as_ptr(), SSL_CTX_get_verify_depth(ctx));
+    }
+    Ok(())
+}
+
+/// The side of the handshake an [`SslStream`] performs, for use with
+/// [`SslStream::new_with_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeMode {
+    /// Act as the client.
+    Client,
+    /// Act as the server.
+    Server,
+}
+
 /// An asynchronous version of [`openssl::ssl::SslStream`].
+///
+/// # Thread safety
+///
+/// `SslStream<S>` is `Send` if `S` is, and `Sync` if `S` is. OpenSSL's `SSL` objects are not
+/// thread-safe, but all I/O and configuration requires a unique reference, so a shared reference
+/// can only be used to inspect the session. To read and write from multiple tasks, wrap the
+/// stream in a [`SyncSslStream`] or split it with `tokio::io::split`.
 #[derive(Debug)]
-pub struct SslStream<S>(ssl::SslStream<StreamWrapper<S>>);
+pub struct SslStream<S> {
+    inner: ssl::SslStream<StreamWrapper<S>>,
+    max_write_chunk: Option<usize>,
+    handshake_start: Option<Instant>,
+    handshake_duration: Option<Duration>,
+    shutdown: Shutdown,
+    allow_unclean_eof: bool,
+    read_timeout: Timeout,
+    write_timeout: Timeout,
+    #[cfg(feature = "dtls")]
+    mtu: Option<u32>,
+}
 
 impl<S> SslStream<S>
 where
@@ -116,43 +473,167 @@ where
 {
     /// Like [`SslStream::new`](ssl::SslStream::new).
     pub fn new(ssl: Ssl, stream: S) -> Result<Self, ErrorStack> {
-        ssl::SslStream::new(ssl, StreamWrapper { stream, context: 0 }).map(SslStream)
+        ssl::SslStream::new(ssl, StreamWrapper::new(stream)).map(|inner| SslStream {
+            inner,
+            max_write_chunk: None,
+            handshake_start: None,
+            handshake_duration: None,
+            shutdown: Shutdown::new(),
+            allow_unclean_eof: false,
+            read_timeout: Timeout::default(),
+            write_timeout: Timeout::default(),
+            #[cfg(feature = "dtls")]
+            mtu: None,
+        })
+    }
+
+    /// Like [`new`](Self::new), but configures `ssl` to act as the client or server ahead of time.
+    ///
+    /// The handshake can then be driven by [`do_handshake`](Self::do_handshake), or implicitly by
+    /// reads and writes, without choosing between [`connect`](Self::connect) and
+    /// [`accept`](Self::accept).
+    pub fn new_with_mode(mut ssl: Ssl, stream: S, mode: HandshakeMode) -> Result<Self, ErrorStack> {
+        match mode {
+            HandshakeMode::Client => ssl.set_connect_state(),
+            HandshakeMode::Server => ssl.set_accept_state(),
+        }
+        SslStream::new(ssl, stream)
     }
 
     /// Like [`SslStream::connect`](ssl::SslStream::connect).
+    #[track_caller]
     pub fn poll_connect(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), ssl::Error>> {
-        self.with_context(cx, |s| cvt_ossl(s.connect()))
+        let r = self.poll_timed_handshake(cx, |s| s.connect());
+        #[cfg(feature = "tracing")]
+        if r.is_pending() {
+            tracing::trace!(target: "tokio_openssl", event = "poll_connect_pending");
+        }
+        r
     }
 
     /// A convenience method wrapping [`poll_connect`](Self::poll_connect).
-    pub async fn connect(mut self: Pin<&mut Self>) -> Result<(), ssl::Error> {
-        future::poll_fn(|cx| self.as_mut().poll_connect(cx)).await
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe. If the returned future is dropped before completion, calling
+    /// this method again resumes the handshake.
+    pub fn connect(self: Pin<&mut Self>) -> Connect<'_, S> {
+        Connect::new(self)
+    }
+
+    /// Like [`connect`](Self::connect), but first attempts to resume `session`, if provided.
+    ///
+    /// The session must be set before the handshake starts, so it is ignored by OpenSSL if the
+    /// handshake is already in progress. If the server declines to resume the session, a full
+    /// handshake is performed instead; this can be checked with
+    /// [`session_reused`](Self::session_reused) once the handshake completes.
+    ///
+    /// OpenSSL takes its own reference to the session, so it only needs to be borrowed for the
+    /// duration of this call.
+    ///
+    /// # Safety
+    ///
+    /// The session must have been negotiated by a connection using the same
+    /// [`SslContext`](ssl::SslContext) as this stream.
+    pub async unsafe fn connect_with_session(
+        mut self: Pin<&mut Self>,
+        session: Option<&SslSessionRef>,
+    ) -> Result<(), ssl::Error> {
+        if let Some(session) = session {
+            // SAFETY: the stream is not moved out of the pinned reference.
+            let this = self.as_mut().get_unchecked_mut();
+            this.inner.ssl_mut().set_session(session)?;
+        }
+        self.connect().await
     }
 
     /// Like [`SslStream::accept`](ssl::SslStream::accept).
+    #[track_caller]
     pub fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), ssl::Error>> {
-        self.with_context(cx, |s| cvt_ossl(s.accept()))
+        self.poll_timed_handshake(cx, |s| s.accept())
     }
 
     /// A convenience method wrapping [`poll_accept`](Self::poll_accept).
-    pub async fn accept(mut self: Pin<&mut Self>) -> Result<(), ssl::Error> {
-        future::poll_fn(|cx| self.as_mut().poll_accept(cx)).await
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe. If the returned future is dropped before completion, calling
+    /// this method again resumes the handshake.
+    pub fn accept(self: Pin<&mut Self>) -> Accept<'_, S> {
+        Accept::new(self)
     }
 
     /// Like [`SslStream::do_handshake`](ssl::SslStream::do_handshake).
+    #[track_caller]
     pub fn poll_do_handshake(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), ssl::Error>> {
-        self.with_context(cx, |s| cvt_ossl(s.do_handshake()))
+        self.poll_timed_handshake(cx, |s| s.do_handshake())
     }
 
     /// A convenience method wrapping [`poll_do_handshake`](Self::poll_do_handshake).
-    pub async fn do_handshake(mut self: Pin<&mut Self>) -> Result<(), ssl::Error> {
-        future::poll_fn(|cx| self.as_mut().poll_do_handshake(cx)).await
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe. If the returned future is dropped before completion, calling
+    /// this method again resumes the handshake.
+    pub fn do_handshake(self: Pin<&mut Self>) -> DoHandshake<'_, S> {
+        DoHandshake::new(self)
+    }
+
+    /// Like [`connect`](Self::connect), but takes ownership of the stream.
+    ///
+    /// The returned future resolves to the stream once the handshake finishes, whether or not it
+    /// succeeded.
+    pub fn into_connect(self) -> ConnectFuture<S>
+    where
+        S: Unpin,
+    {
+        HandshakeFuture::connect(self)
+    }
+
+    /// Like [`accept`](Self::accept), but takes ownership of the stream.
+    ///
+    /// The returned future resolves to the stream once the handshake finishes, whether or not it
+    /// succeeded.
+    pub fn into_accept(self) -> AcceptFuture<S>
+    where
+        S: Unpin,
+    {
+        HandshakeFuture::accept(self)
+    }
+
+    /// Like [`do_handshake`](Self::do_handshake), but takes ownership of the stream.
+    ///
+    /// The returned future resolves to the stream once the handshake finishes, whether or not it
+    /// succeeded.
+    pub fn into_handshake(self) -> HandshakeFuture<S>
+    where
+        S: Unpin,
+    {
+        HandshakeFuture::handshake(self)
+    }
+
+    /// Copies data in both directions between this stream and `other`.
+    ///
+    /// This is a version of [`copy_bidirectional_tls`] for streams which are not [`Unpin`]. When
+    /// one direction reaches EOF, a close notify is sent on the other stream while the opposite
+    /// direction keeps running.
+    ///
+    /// Returns the number of bytes copied from `self` to `other` and from `other` to `self`.
+    pub async fn copy_bidirectional<S2>(
+        self: Pin<&mut Self>,
+        other: Pin<&mut SslStream<S2>>,
+    ) -> io::Result<(u64, u64)>
+    where
+        S2: AsyncRead + AsyncWrite,
+    {
+        copy::copy_bidirectional_pinned(self, other, copy::DEFAULT_BUF_SIZE, copy::DEFAULT_BUF_SIZE)
+            .await
     }
 
     /// Like [`SslStream::read_early_data`](ssl::SslStream::read_early_data).
@@ -162,7 +643,7 @@ where
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize, ssl::Error>> {
-        self.with_context(cx, |s| cvt_ossl(s.read_early_data(buf)))
+        self.poll_ssl(cx, |s| s.read_early_data(buf))
     }
 
     /// A convenience method wrapping [`poll_read_early_data`](Self::poll_read_early_data).
@@ -181,7 +662,7 @@ where
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, ssl::Error>> {
-        self.with_context(cx, |s| cvt_ossl(s.write_early_data(buf)))
+        self.poll_ssl(cx, |s| s.write_early_data(buf))
     }
 
     /// A convenience method wrapping [`poll_write_early_data`](Self::poll_write_early_data).
@@ -193,199 +674,1383 @@ where
         future::poll_fn(|cx| self.as_mut().poll_write_early_data(cx, buf)).await
     }
 
+    /// Like [`poll_write_early_data`](Self::poll_write_early_data), but takes a list of buffers.
+    ///
+    /// OpenSSL has no scatter-gather variant of `SSL_write_early_data`, so the buffers are
+    /// assembled into a single contiguous buffer before being written.
+    #[cfg(ossl111)]
+    pub fn poll_write_early_data_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<Result<usize, ssl::Error>> {
+        let buf = bufs
+            .iter()
+            .flat_map(|b| b.iter())
+            .copied()
+            .collect::<Vec<u8>>();
+        self.poll_write_early_data(cx, &buf)
+    }
+
+    /// A convenience method wrapping
+    /// [`poll_write_early_data_vectored`](Self::poll_write_early_data_vectored).
+    #[cfg(ossl111)]
+    pub async fn write_early_data_vectored(
+        mut self: Pin<&mut Self>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Result<usize, ssl::Error> {
+        future::poll_fn(|cx| self.as_mut().poll_write_early_data_vectored(cx, bufs)).await
+    }
+
+    /// Writes all of `buf` as early data, looping over
+    /// [`write_early_data`](Self::write_early_data) like
+    /// [`AsyncWriteExt::write_all`](tokio::io::AsyncWriteExt::write_all).
+    #[cfg(ossl111)]
+    pub async fn write_early_data_all(
+        mut self: Pin<&mut Self>,
+        mut buf: &[u8],
+    ) -> Result<(), ssl::Error> {
+        while !buf.is_empty() {
+            let n = self.as_mut().write_early_data(buf).await?;
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+
     /// Like [`SslStream::ssl_read`](ssl::SslStream::ssl_read).
     pub fn poll_ssl_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize, ssl::Error>> {
-        self.with_context(cx, |s| cvt_ossl(s.ssl_read(buf)))
+        self.poll_ssl(cx, |s| s.ssl_read(buf))
     }
 
     /// A convenience method wrapping [`poll_ssl_read`](Self::poll_ssl_read).
     pub async fn ssl_read(mut self: Pin<&mut Self>, buf: &mut [u8]) -> Result<usize, ssl::Error> {
         future::poll_fn(|cx| self.as_mut().poll_ssl_read(cx, buf)).await
     }
-}
-
-impl<S> SslStream<S> {
-    /// Returns a shared reference to the `Ssl` object associated with this stream.
-    pub fn ssl(&self) -> &SslRef {
-        self.0.ssl()
-    }
 
-    /// Returns a shared reference to the underlying stream.
-    pub fn get_ref(&self) -> &S {
-        &self.0.get_ref().stream
-    }
+    /// Like [`poll_ssl_read`](Self::poll_ssl_read), but reads into the spare capacity of a
+    /// [`BufMut`](bytes::BufMut), such as a `BytesMut`, and advances it past the data read.
+    ///
+    /// The data is decrypted directly into `buf`, without an intermediate copy. Returns `Ok(0)`
+    /// without reading if `buf` has no remaining capacity.
+    ///
+    /// Requires the `bytes` Cargo feature.
+    #[cfg(feature = "bytes")]
+    pub fn poll_read_to_buf<B>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<Result<usize, ssl::Error>>
+    where
+        B: bytes::BufMut + ?Sized,
+    {
+        if !buf.has_remaining_mut() {
+            return Poll::Ready(Ok(0));
+        }
 
-    /// Returns a mutable reference to the underlying stream.
-    pub fn get_mut(&mut self) -> &mut S {
-        &mut self.0.get_mut().stream
-    }
+        let r = self.poll_ssl(cx, |s| {
+            let chunk = buf.chunk_mut();
+            // SAFETY: OpenSSL only writes to the chunk, so it can't de-initialize any of it.
+            #[cfg(ossl111)]
+            let r = s.ssl_read_uninit(unsafe {
+                std::slice::from_raw_parts_mut(
+                    chunk.as_mut_ptr().cast::<mem::MaybeUninit<u8>>(),
+                    chunk.len(),
+                )
+            });
+            // as in poll_read, SSL_read doesn't actually need an initialized buffer
+            #[cfg(not(ossl111))]
+            let r = s.ssl_read(unsafe {
+                std::slice::from_raw_parts_mut(chunk.as_mut_ptr(), chunk.len())
+            });
+            r
+        });
 
-    /// Returns a pinned mutable reference to the underlying stream.
-    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut S> {
-        unsafe { Pin::new_unchecked(&mut self.get_unchecked_mut().0.get_mut().stream) }
+        if let Poll::Ready(Ok(n)) = r {
+            // SAFETY: OpenSSL initialized the first `n` bytes of the chunk.
+            unsafe { buf.advance_mut(n) };
+        }
+        r
     }
 
-    fn with_context<F, R>(self: Pin<&mut Self>, ctx: &mut Context<'_>, f: F) -> R
+    /// A convenience method wrapping [`poll_read_to_buf`](Self::poll_read_to_buf).
+    ///
+    /// Requires the `bytes` Cargo feature.
+    #[cfg(feature = "bytes")]
+    pub async fn read_buf_ssl<B>(mut self: Pin<&mut Self>, buf: &mut B) -> Result<usize, ssl::Error>
     where
-        F: FnOnce(&mut ssl::SslStream<StreamWrapper<S>>) -> R,
+        B: bytes::BufMut + ?Sized,
     {
-        let this = unsafe { self.get_unchecked_mut() };
-        this.0.get_mut().context = ctx as *mut _ as usize;
-        let r = f(&mut this.0);
-        this.0.get_mut().context = 0;
-        r
+        future::poll_fn(|cx| self.as_mut().poll_read_to_buf(cx, buf)).await
     }
-}
 
-impl<S> AsyncRead for SslStream<S>
-where
-    S: AsyncRead + AsyncWrite,
-{
-    fn poll_read(
-        self: Pin<&mut Self>,
-        ctx: &mut Context<'_>,
-        buf: &mut ReadBuf<'_>,
-    ) -> Poll<io::Result<()>> {
-        self.with_context(ctx, |s| {
-            // This isn't really "proper", but rust-openssl doesn't currently expose a suitable interface even though
-            // OpenSSL itself doesn't require the buffer to be initialized. So this is good enough for now.
-            let slice = unsafe {
-                let buf = buf.unfilled_mut();
-                slice::from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), buf.len())
-            };
-            match cvt(s.read(slice))? {
-                Poll::Ready(nread) => {
-                    unsafe {
-                        buf.assume_init(nread);
+    /// Like [`poll_ssl_read`](Self::poll_ssl_read), but reads into multiple buffers.
+    ///
+    /// OpenSSL has no native scatter read, so this makes one `SSL_read` call per buffer, moving on
+    /// to the next buffer only once the current one has been filled. Returns the total number of
+    /// bytes read. Once some data has been read, a call which would block or fail ends the read
+    /// early instead, and an error will be reported by the next call.
+    pub fn poll_ssl_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [io::IoSliceMut<'_>],
+    ) -> Poll<Result<usize, ssl::Error>> {
+        let mut total = 0;
+        for buf in bufs.iter_mut().filter(|b| !b.is_empty()) {
+            match self.as_mut().poll_ssl_read(cx, buf) {
+                Poll::Ready(Ok(n)) => {
+                    total += n;
+                    if n < buf.len() {
+                        break;
                     }
-                    buf.advance(nread);
-                    Poll::Ready(Ok(()))
                 }
-                Poll::Pending => Poll::Pending,
+                Poll::Ready(Err(_)) | Poll::Pending if total > 0 => break,
+                r => return r,
             }
-        })
-    }
-}
+        }
 
-impl<S> AsyncWrite for SslStream<S>
-where
-    S: AsyncRead + AsyncWrite,
-{
-    fn poll_write(self: Pin<&mut Self>, ctx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
-        self.with_context(ctx, |s| cvt(s.write(buf)))
+        Poll::Ready(Ok(total))
     }
 
-    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<io::Result<()>> {
-        self.with_context(ctx, |s| cvt(s.flush()))
+    /// A convenience method wrapping [`poll_ssl_read_vectored`](Self::poll_ssl_read_vectored).
+    pub async fn ssl_read_vectored(
+        mut self: Pin<&mut Self>,
+        bufs: &mut [io::IoSliceMut<'_>],
+    ) -> Result<usize, ssl::Error> {
+        future::poll_fn(|cx| self.as_mut().poll_ssl_read_vectored(cx, bufs)).await
     }
 
-    fn poll_shutdown(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<io::Result<()>> {
-        let first_shut_ret = match self.as_mut().with_context(ctx, |s| s.shutdown()) {
-            Ok(ShutdownResult::Sent) => {
-                // close notify sent but not received from peer
-                // another try to wait for peer's close notify
-                // We tried call SSL_shutdown() twice previously, we use the recommended SSL_read() now
-                // The OpenSSL manpage suggests SSL_read()
-                // https://github.com/openssl/openssl/blob/OpenSSL_1_1_1-stable/doc/man3/SSL_shutdown.pod
-
-                _ShouldKeepPollSslRead::ShouldStartPollSslRead
-            }
-            Ok(ShutdownResult::Received) => {
-                // close notify sent and received from peer, finished
-                return Poll::Ready(Ok(()));
-            }
-            Err(ref e) if e.code() == ErrorCode::ZERO_RETURN => {
-                // no more read from peer
-                return Poll::Ready(Ok(()));
-            }
-            Err(ref e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => {
-                return Poll::Pending;
+    /// Reads until EOF, appending the data to `buf`, but fails rather than reading more than
+    /// `limit` bytes.
+    ///
+    /// Unlike `AsyncReadExt::read_to_string`, this bounds the memory an untrusted peer can make
+    /// us allocate. If the peer sends more than `limit` bytes or the data is not valid UTF-8, an
+    /// [`InvalidData`](io::ErrorKind::InvalidData) error is returned, `buf` is left unchanged and
+    /// the data read so far is discarded.
+    ///
+    /// Returns the number of bytes appended to `buf`.
+    pub async fn read_to_string_bounded(
+        mut self: Pin<&mut Self>,
+        buf: &mut String,
+        limit: usize,
+    ) -> io::Result<usize> {
+        let mut data = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            // read one byte past the limit to tell whether it was exceeded
+            let max = chunk.len().min((limit - data.len()).saturating_add(1));
+            let mut read_buf = ReadBuf::new(&mut chunk[..max]);
+            future::poll_fn(|cx| self.as_mut().poll_read(cx, &mut read_buf)).await?;
+            let filled = read_buf.filled();
+            if filled.is_empty() {
+                break;
             }
-            Err(ref e) if e.code() == ErrorCode::SYSCALL && e.io_error().is_none() => {
-                // other side closed underlying socket without sending the close notify
-                // we assume it is okay
-                return Poll::Ready(Ok(()));
+            if data.len() + filled.len() > limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream exceeded the read limit",
+                ));
             }
-            Err(e) => {
-                return Poll::Ready(Err(e
-                    .into_io_error()
-                    .unwrap_or_else(|e| io::Error::new(io::ErrorKind::Other, e))));
+            data.extend_from_slice(filled);
+        }
+
+        let data =
+            String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        buf.push_str(&data);
+        Ok(data.len())
+    }
+
+    /// Like [`poll_write`](AsyncWrite::poll_write), but continues until all of `buf` has been
+    /// written and returns an [`ssl::Error`] directly.
+    ///
+    /// `written` tracks progress across calls and must start at 0. When this returns `Pending`,
+    /// it must be called again with the same `buf` and `written` so that `SSL_write` is retried
+    /// with the same data, as OpenSSL requires.
+    pub fn poll_ssl_write_all(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        written: &mut usize,
+    ) -> Poll<Result<(), ssl::Error>> {
+        while *written < buf.len() {
+            let remaining = &buf[*written..];
+            match self.as_mut().poll_ssl(cx, |s| s.ssl_write(remaining)) {
+                Poll::Ready(Ok(n)) => *written += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
             }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// A convenience method wrapping [`poll_ssl_write_all`](Self::poll_ssl_write_all).
+    pub async fn ssl_write_all(mut self: Pin<&mut Self>, buf: &[u8]) -> Result<(), ssl::Error> {
+        let mut written = 0;
+        future::poll_fn(|cx| self.as_mut().poll_ssl_write_all(cx, buf, &mut written)).await
+    }
+
+    /// Sends a close notify alert to the peer without waiting for the peer's.
+    ///
+    /// Only the write side of the session is closed: reads keep returning application data until
+    /// the peer's close notify arrives, after which they return EOF. Calling this again after the
+    /// alert has been sent does nothing.
+    ///
+    /// The alert is written to the underlying stream but not flushed; a later call to
+    /// [`poll_shutdown`](AsyncWrite::poll_shutdown) flushes it and completes the close.
+    pub fn poll_send_close_notify(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), ssl::Error>> {
+        if self.ssl().get_shutdown().contains(ssl::ShutdownState::SENT) {
+            return Poll::Ready(Ok(()));
+        }
+
+        let received = match self.as_mut().poll_shutdown_tls(cx) {
+            Poll::Ready(Ok(r)) => r == ShutdownResult::Received,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
         };
 
-        if let _ShouldKeepPollSslRead::ShouldStartPollSslRead = first_shut_ret {
-            let mut buf = [0u8; 1024];
-            loop {
-                match cvt_shutdown_ssl_read_ossl(self.as_mut().poll_ssl_read(ctx, &mut buf)) {
-                    Poll::Ready(s) => match s {
-                        Ok(keep) => match keep {
-                            _ShouldKeepPollSslRead::ShouldStartPollSslRead => {
-                                return Poll::Ready(Err(io::Error::new(
-                                    io::ErrorKind::Other,
-                                    format!("poll_shutdown: should not go _ShouldKeepPollSslRead::ShouldStartPollSslRead"),
-                                )))
-                            }
-                            _ShouldKeepPollSslRead::KeepPollSslRead => {}
-                            _ShouldKeepPollSslRead::Finished => return Poll::Ready(Ok(())),
-                        },
-                        Err(e) => {
-                            return Poll::Ready(Err(e
-                                .into_io_error()
-                                .unwrap_or_else(|e| io::Error::new(io::ErrorKind::Other, e))));
-                        }
-                    },
-                    Poll::Pending => return Poll::Pending,
-                }
-            }
-        } else {
-            return Poll::Ready(Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("poll_shutdown: should already returned"),
-            )));
+        let shutdown = self.shutdown_mut();
+        if shutdown.state == ShutdownState::NotStarted {
+            shutdown.state = ShutdownState::FlushingCloseNotify { received };
         }
+        Poll::Ready(Ok(()))
     }
 
-    fn poll_write_vectored(
-        self: Pin<&mut Self>,
+    /// A convenience method wrapping [`poll_send_close_notify`](Self::poll_send_close_notify).
+    pub async fn send_close_notify(mut self: Pin<&mut Self>) -> Result<(), ssl::Error> {
+        future::poll_fn(|cx| self.as_mut().poll_send_close_notify(cx)).await
+    }
+
+    /// Closes the write side of the session, like shutting down the write half of a TCP stream.
+    ///
+    /// Sends a close notify alert with [`poll_send_close_notify`](Self::poll_send_close_notify) and
+    /// flushes it to the underlying stream, without waiting for the peer's. The stream remains
+    /// readable until the peer's close notify arrives, while further writes fail. The underlying
+    /// stream itself is not shut down.
+    pub fn poll_write_shutdown(
+        mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-        bufs: &[io::IoSlice<'_>],
-    ) -> Poll<Result<usize, io::Error>> {
-        self.with_context(cx, |s| cvt(s.write_vectored(bufs)))
+    ) -> Poll<io::Result<()>> {
+        match self.as_mut().poll_send_close_notify(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(to_io_error(e))),
+            Poll::Pending => return Poll::Pending,
+        }
+        self.get_pin_mut().poll_flush(cx)
     }
-}
 
-/// A private enum that should not be used
-enum _ShouldKeepPollSslRead {
-    ShouldStartPollSslRead,
-    KeepPollSslRead,
-    Finished,
-}
+    /// A convenience method wrapping [`poll_write_shutdown`](Self::poll_write_shutdown).
+    pub async fn write_shutdown(mut self: Pin<&mut Self>) -> io::Result<()> {
+        future::poll_fn(|cx| self.as_mut().poll_write_shutdown(cx)).await
+    }
 
-/// Convert SSL_read() result to Poll<T> enum when we are doing SSL_shutdown()
-fn cvt_shutdown_ssl_read_ossl(
-    r: Poll<Result<usize, ssl::Error>>,
-) -> Poll<Result<_ShouldKeepPollSslRead, ssl::Error>> {
-    match r {
-        Poll::Ready(r) => match r {
-            Ok(_) => {
-                // We read some data, we should keep reading until we got ZERO_RETURN
-                Poll::Ready(Ok(_ShouldKeepPollSslRead::KeepPollSslRead))
+    /// Performs [`poll_shutdown`](AsyncWrite::poll_shutdown), giving up on the peer's close notify
+    /// once `dur` has elapsed.
+    ///
+    /// Returns `true` if the peer's close notify was received, and `false` if it was not, including
+    /// when the deadline expired. Expiry is treated as a successful best-effort close rather than
+    /// an error. If the deadline expires, calling [`poll_shutdown`](AsyncWrite::poll_shutdown)
+    /// later resumes waiting where this left off.
+    pub async fn shutdown_timeout(mut self: Pin<&mut Self>, dur: Duration) -> io::Result<bool> {
+        let sleep = tokio::time::sleep(dur);
+        futures_util::pin_mut!(sleep);
+
+        future::poll_fn(|cx| {
+            if let Poll::Ready(r) = self.as_mut().poll_shutdown(cx) {
+                let received = self
+                    .ssl()
+                    .get_shutdown()
+                    .contains(ssl::ShutdownState::RECEIVED);
+                return Poll::Ready(r.map(|()| received));
             }
-            Err(e) => match e.code() {
-                ErrorCode::WANT_READ | ErrorCode::WANT_WRITE => Poll::Pending,
-                ErrorCode::ZERO_RETURN => Poll::Ready(Ok(_ShouldKeepPollSslRead::Finished)),
-                // other side closed underlying socket without sending the close notify
-                // we assume it is okay
-                ErrorCode::SYSCALL if e.io_error().is_none() => {
-                    Poll::Ready(Ok(_ShouldKeepPollSslRead::Finished))
-                }
-                _ => Poll::Ready(Err(e)),
-            },
-        },
-        Poll::Pending => Poll::Pending,
+
+            sleep.as_mut().poll(cx).map(|()| Ok(false))
+        })
+        .await
+    }
+
+    /// Performs a single step of the TLS shutdown with `SSL_shutdown`.
+    ///
+    /// The first call sends a close notify alert and returns [`ShutdownResult::Sent`], or
+    /// [`ShutdownResult::Received`] if the peer's close notify has already been read. A later call
+    /// waits for the peer's close notify and returns [`ShutdownResult::Received`]; it fails if
+    /// application data arrives first. The read side remains usable after the close notify has
+    /// been sent, so callers can implement their own close policy.
+    ///
+    /// This does not interact with the state tracked by
+    /// [`poll_shutdown`](AsyncWrite::poll_shutdown).
+    pub fn poll_shutdown_tls(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<ShutdownResult, ssl::Error>> {
+        self.poll_ssl(cx, |s| s.shutdown())
+    }
+
+    /// A convenience method wrapping [`poll_shutdown_tls`](Self::poll_shutdown_tls).
+    pub async fn shutdown_tls(mut self: Pin<&mut Self>) -> Result<ShutdownResult, ssl::Error> {
+        future::poll_fn(|cx| self.as_mut().poll_shutdown_tls(cx)).await
+    }
+
+    /// Completes the TLS phase of [`poll_shutdown`](AsyncWrite::poll_shutdown), moving on to the
+    /// transport if configured to.
+    fn finish_tls_shutdown(
+        mut self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        r: io::Result<()>,
+    ) -> Poll<io::Result<()>> {
+        if r.is_err() || !self.shutdown.transport {
+            return self.finish_shutdown(r);
+        }
+
+        let shutdown = self.as_mut().shutdown_mut();
+        shutdown.state = ShutdownState::ShuttingDownTransport;
+        shutdown.read_buf = None;
+        self.poll_shutdown_transport(ctx)
+    }
+
+    fn poll_shutdown_transport(
+        mut self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.as_mut().get_pin_mut().poll_shutdown(ctx) {
+            Poll::Ready(r) => self.finish_shutdown(r),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Like [`poll_shutdown`](AsyncWrite::poll_shutdown), but with `max_bytes` in place of the
+    /// [drain limit](SslStream::set_shutdown_drain_limit) for this call only.
+    ///
+    /// Fails with a [`ConnectionAborted`](io::ErrorKind::ConnectionAborted) error if the peer
+    /// sends more than `max_bytes` of application data before its close notify.
+    pub fn poll_shutdown_with_drain_limit(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        max_bytes: usize,
+    ) -> Poll<io::Result<()>> {
+        self.poll_shutdown_limited(cx, max_bytes)
+    }
+
+    /// Drives [`poll_shutdown`](AsyncWrite::poll_shutdown), discarding at most `drain_limit`
+    /// bytes of application data while waiting for the peer's close notify.
+    fn poll_shutdown_limited(
+        mut self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        drain_limit: usize,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let state = self.shutdown.state;
+            match state {
+                ShutdownState::NotStarted => {
+                    if shutdown::quiet_shutdown(self.ssl()) {
+                        // the session is marked closed without any alerts crossing the wire, so
+                        // only the transport is left to shut down
+                        if let Poll::Ready(Err(e)) = self.as_mut().poll_shutdown_tls(ctx) {
+                            return self.finish_shutdown(Err(to_io_error(e)));
+                        }
+                        self.as_mut().shutdown_mut().state = ShutdownState::ShuttingDownTransport;
+                        continue;
+                    }
+                    match self.as_mut().poll_shutdown_tls(ctx) {
+                        Poll::Ready(Ok(ShutdownResult::Sent)) => {
+                            // close notify sent but not received from peer
+                            self.as_mut().shutdown_mut().state =
+                                ShutdownState::FlushingCloseNotify { received: false };
+                        }
+                        Poll::Ready(Ok(ShutdownResult::Received)) => {
+                            // close notify sent and received from peer
+                            self.as_mut().shutdown_mut().state =
+                                ShutdownState::FlushingCloseNotify { received: true };
+                        }
+                        Poll::Ready(Err(ref e)) if e.code() == ErrorCode::ZERO_RETURN => {
+                            // no more read from peer
+                            return self.finish_tls_shutdown(ctx, Ok(()));
+                        }
+                        Poll::Ready(Err(ref e))
+                            if e.code() == ErrorCode::SYSCALL && e.io_error().is_none() =>
+                        {
+                            // other side closed underlying socket without sending the close notify
+                            // we assume it is okay
+                            return self.finish_tls_shutdown(ctx, Ok(()));
+                        }
+                        Poll::Ready(Err(e)) => return self.finish_shutdown(Err(to_io_error(e))),
+                        Poll::Pending => {
+                            // the close notify may be stuck in a buffered transport
+                            if let Poll::Ready(Err(e)) = self.as_mut().get_pin_mut().poll_flush(ctx)
+                            {
+                                return self.finish_shutdown(Err(e));
+                            }
+                            return Poll::Pending;
+                        }
+                    }
+                }
+                ShutdownState::FlushingCloseNotify { received } => {
+                    // the peer can't respond to a close notify it hasn't seen
+                    match self.as_mut().get_pin_mut().poll_flush(ctx) {
+                        Poll::Ready(Ok(())) => {}
+                        Poll::Ready(Err(e)) => return self.finish_shutdown(Err(e)),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                    if received || self.shutdown.mode == ShutdownMode::SendOnly {
+                        return self.finish_tls_shutdown(ctx, Ok(()));
+                    }
+                    // another try to wait for peer's close notify
+                    // We tried call SSL_shutdown() twice previously, we use the recommended SSL_read() now
+                    // The OpenSSL manpage suggests SSL_read()
+                    // https://github.com/openssl/openssl/blob/OpenSSL_1_1_1-stable/doc/man3/SSL_shutdown.pod
+                    self.as_mut().shutdown_mut().state = ShutdownState::SentCloseNotify;
+                }
+                ShutdownState::SentCloseNotify => {
+                    let mut buf = self.as_mut().shutdown_mut().take_read_buf();
+                    return match self
+                        .as_mut()
+                        .poll_shutdown_drain(ctx, &mut buf, drain_limit)
+                    {
+                        Poll::Ready(r) => self.finish_tls_shutdown(ctx, r),
+                        Poll::Pending => {
+                            self.as_mut().shutdown_mut().put_read_buf(buf);
+                            Poll::Pending
+                        }
+                    };
+                }
+                ShutdownState::ShuttingDownTransport => return self.poll_shutdown_transport(ctx),
+                ShutdownState::Done => return Poll::Ready(Ok(())),
+                ShutdownState::Failed(kind) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        kind,
+                        "TLS shutdown previously failed",
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Reads and discards application data until the peer's close notify arrives.
+    fn poll_shutdown_drain(
+        mut self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut [u8],
+        drain_limit: usize,
+    ) -> Poll<io::Result<()>> {
+        for _ in 0..SHUTDOWN_DRAIN_ITERATIONS_PER_POLL {
+            match cvt_shutdown_ssl_read_ossl(self.as_mut().poll_ssl_read(ctx, buf)) {
+                Poll::Ready(Ok(_ShouldKeepPollSslRead::KeepPollSslRead(n))) => {
+                    let shutdown = self.as_mut().shutdown_mut();
+                    if shutdown.capture {
+                        let keep = n.min(drain_limit.saturating_sub(shutdown.drained));
+                        shutdown.captured.extend_from_slice(&buf[..keep]);
+                    }
+                    shutdown.drained += n;
+                    if shutdown.drained > drain_limit {
+                        // the peer keeps sending data instead of its close notify
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "peer sent too much data while waiting for close notify",
+                        )));
+                    }
+                }
+                Poll::Ready(Ok(_ShouldKeepPollSslRead::Finished)) => return Poll::Ready(Ok(())),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(to_io_error(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        // don't starve other tasks while the peer keeps sending data
+        ctx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<S> SslStream<S> {
+    /// Moves the stream to the heap, returning a wrapper which is [`Unpin`] even if `S` is not.
+    pub fn into_boxed(self) -> BoxedSslStream<S> {
+        BoxedSslStream::from(Box::pin(self))
+    }
+
+    /// Returns an identifier for this stream.
+    ///
+    /// The ID is derived from the underlying OpenSSL object, so it stays the same if the stream is
+    /// moved.
+    pub fn id(&self) -> SslStreamId {
+        SslStreamId(self.ssl().as_ptr() as usize)
+    }
+
+    /// Returns a shared reference to the `Ssl` object associated with this stream.
+    pub fn ssl(&self) -> &SslRef {
+        self.inner.ssl()
+    }
+
+    /// Returns a mutable reference to the `Ssl` object associated with this stream.
+    ///
+    /// Handshake parameters such as the session, ALPN protocols and hostname only take effect if
+    /// they are set before the handshake starts. Calling methods which perform I/O on the `SslRef`
+    /// directly is not supported, as the underlying stream can only be driven from a poll.
+    pub fn ssl_mut(&mut self) -> &mut SslRef {
+        self.inner.ssl_mut()
+    }
+
+    /// Calls `f` with a mutable reference to the `Ssl` object associated with this stream.
+    ///
+    /// This is equivalent to calling `f` with [`ssl_mut`](Self::ssl_mut), and is subject to the
+    /// same restrictions.
+    pub fn with_ssl<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut SslRef) -> R,
+    {
+        f(self.ssl_mut())
+    }
+
+    /// Replaces the `SslContext` associated with this stream with `context`.
+    ///
+    /// This is intended for long-running servers rotating certificates. The certificate, private
+    /// key, verify mode, verify callback and verify depth are taken from `context`, as are the
+    /// callbacks OpenSSL looks up through the context during the handshake, such as ALPN
+    /// selection, servername, status and session ticket callbacks. Settings which OpenSSL copies
+    /// into the stream when it is created, such as the protocol versions, cipher list and
+    /// options, are kept from the original context.
+    ///
+    /// **Warning**: only handshakes started after this call use the new context. An established
+    /// session or a handshake which is already in progress is not affected.
+    pub fn update_ssl_ctx(&mut self, context: &SslContextRef) -> Result<(), ErrorStack> {
+        switch_context(self.inner.ssl_mut(), context)
+    }
+
+    /// Returns the protocol selected via Application Layer Protocol Negotiation (ALPN), if any.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.ssl().selected_alpn_protocol()
+    }
+
+    /// Returns the host name sent by the client via Server Name Indication (SNI), if any.
+    ///
+    /// This is equivalent to `ssl().servername(NameType::HOST_NAME)`. On the server side it is
+    /// available once the ClientHello has been processed, including from within the servername
+    /// and client hello callbacks. On the client side it returns the name being sent.
+    pub fn server_name(&self) -> Option<&str> {
+        self.ssl().servername(NameType::HOST_NAME)
+    }
+
+    /// Returns `true` if the handshake resumed a previous session rather than negotiating a new
+    /// one.
+    ///
+    /// A client resumes a session by passing it to [`SslRef::set_session`] before the handshake.
+    /// Under TLS 1.3, the server sends session tickets after the handshake, so they are only
+    /// processed, and reported to the session callback, once the client reads from the stream.
+    /// A client which closes the connection without reading will never receive a session to
+    /// resume.
+    pub fn session_reused(&self) -> bool {
+        self.ssl().session_reused()
+    }
+
+    /// Returns the cipher negotiated by the handshake, if it has completed.
+    pub fn cipher(&self) -> Option<&SslCipherRef> {
+        self.ssl().current_cipher()
+    }
+
+    /// Returns the protocol version negotiated by the handshake, if it has completed.
+    ///
+    /// Requires OpenSSL 1.1.0 or newer.
+    #[cfg(ossl110)]
+    pub fn protocol_version(&self) -> Option<SslVersion> {
+        self.ssl().version2()
+    }
+
+    /// Returns an owned summary of the session, or `None` if the handshake has not completed.
+    ///
+    /// Requires OpenSSL 1.1.0 or newer.
+    #[cfg(ossl110)]
+    pub fn connection_info(&self) -> Option<ConnectionInfo> {
+        ConnectionInfo::new(self.ssl())
+    }
+
+    /// Derives `out_len` bytes of keying material from the session, as described in RFC 5705 and
+    /// RFC 8446.
+    ///
+    /// Both ends of a connection derive the same material for the same `label` and `context`, so
+    /// it can be used to bind application-level authentication to the TLS session. This performs
+    /// no I/O, but is only valid once the handshake has completed, and fails before then.
+    ///
+    /// Requires OpenSSL 1.1.1 or newer.
+    #[cfg(ossl111)]
+    pub fn export_keying_material(
+        &self,
+        out_len: usize,
+        label: &str,
+        context: Option<&[u8]>,
+    ) -> Result<Vec<u8>, ErrorStack> {
+        let mut out = vec![0; out_len];
+        self.ssl()
+            .export_keying_material(&mut out, label, context)?;
+        Ok(out)
+    }
+
+    /// Like [`export_keying_material`](Self::export_keying_material), but derives the material
+    /// from the TLS 1.3 early exporter secret.
+    ///
+    /// The early exporter is only available on connections which sent or accepted early data,
+    /// and the material it derives is not protected against replay.
+    ///
+    /// Requires OpenSSL 1.1.1 or newer.
+    #[cfg(ossl111)]
+    pub fn export_keying_material_early(
+        &self,
+        out_len: usize,
+        label: &str,
+        context: &[u8],
+    ) -> Result<Vec<u8>, ErrorStack> {
+        let mut out = vec![0; out_len];
+        self.ssl()
+            .export_keying_material_early(&mut out, label, context)?;
+        Ok(out)
+    }
+
+    /// Returns the peer's leaf certificate, if present.
+    ///
+    /// The certificate is reference counted, so the returned handle can outlive the stream. Unlike
+    /// [`peer_cert_chain`](Self::peer_cert_chain), this returns the leaf certificate on both the
+    /// client and server side. A server only receives a client certificate if it requested one
+    /// with [`SslVerifyMode::PEER`](ssl::SslVerifyMode::PEER).
+    ///
+    /// # Examples
+    ///
+    /// Extracting the client's identity on a server requiring client certificates:
+    ///
+    /// ```no_run
+    /// use openssl::nid::Nid;
+    /// use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+    /// use tokio::net::TcpListener;
+    /// use tokio_openssl::{SslStream, SslStreamExt};
+    ///
+    /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    /// acceptor.set_private_key_file("key.pem", SslFiletype::PEM)?;
+    /// acceptor.set_certificate_chain_file("cert.pem")?;
+    /// acceptor.set_ca_file("client-ca.pem")?;
+    /// acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    /// let acceptor = acceptor.build();
+    ///
+    /// let listener = TcpListener::bind("0.0.0.0:8443").await?;
+    /// let (stream, _) = listener.accept().await?;
+    /// let mut stream = SslStream::new(Ssl::new(acceptor.context())?, stream)?;
+    /// stream.accept().await?;
+    ///
+    /// let cert = stream.peer_certificate().expect("client certificate required");
+    /// let name = cert.subject_name().entries_by_nid(Nid::COMMONNAME).next();
+    /// if let Some(name) = name {
+    ///     println!("client: {}", name.data().as_utf8()?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peer_certificate(&self) -> Option<X509> {
+        self.ssl().peer_certificate()
+    }
+
+    /// Returns the digest of the peer's leaf certificate, computed with `algo`, if the peer
+    /// presented one.
+    ///
+    /// # Examples
+    ///
+    /// Pinning a server's certificate by its SHA-256 fingerprint:
+    ///
+    /// ```no_run
+    /// use openssl::hash::MessageDigest;
+    /// use openssl::ssl::{SslConnector, SslMethod};
+    /// use std::io;
+    /// use tokio::net::TcpStream;
+    /// use tokio_openssl::SslStreamConnector;
+    ///
+    /// const FINGERPRINT: [u8; 32] = [0; 32];
+    ///
+    /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let connector = SslConnector::builder(SslMethod::tls())?.build();
+    /// let connector = SslStreamConnector::from_connector(connector);
+    /// let stream = TcpStream::connect("example.com:443").await?;
+    /// let stream = connector.connect("example.com", stream).await?;
+    ///
+    /// let fingerprint = stream.peer_cert_fingerprint(MessageDigest::sha256())?;
+    /// if fingerprint.as_deref() != Some(&FINGERPRINT[..]) {
+    ///     // dropping the stream closes the connection
+    ///     let e = io::Error::new(io::ErrorKind::InvalidData, "certificate fingerprint mismatch");
+    ///     return Err(e.into());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peer_cert_fingerprint(
+        &self,
+        algo: MessageDigest,
+    ) -> Result<Option<DigestBytes>, ErrorStack> {
+        self.ssl()
+            .peer_certificate()
+            .map(|cert| cert.digest(algo))
+            .transpose()
+    }
+
+    /// Returns the certificate chain presented by the peer, if present.
+    ///
+    /// On the client side the chain includes the leaf certificate; on the server side it does not.
+    pub fn peer_cert_chain(&self) -> Option<&StackRef<X509>> {
+        self.ssl().peer_cert_chain()
+    }
+
+    /// Returns the certificate chain built and validated by OpenSSL while verifying the peer.
+    ///
+    /// Unlike [`peer_cert_chain`](Self::peer_cert_chain), which is what the peer sent, this chain
+    /// ends at the trust anchor. It is only meaningful after a successful handshake with peer
+    /// verification enabled, and is `None` otherwise.
+    ///
+    /// Fails only if copying the chain fails to allocate.
+    ///
+    /// Requires OpenSSL 1.1.0 or newer.
+    #[cfg(ossl110)]
+    pub fn verified_chain(&self) -> Result<Option<Stack<X509>>, ErrorStack> {
+        let chain = match self.ssl().verified_chain() {
+            Some(chain) => chain,
+            None => return Ok(None),
+        };
+        let mut owned = Stack::new()?;
+        for cert in chain {
+            owned.push(cert.to_owned())?;
+        }
+        Ok(Some(owned))
+    }
+
+    /// Returns the result of verifying the peer's certificate.
+    ///
+    /// This reflects the outcome of OpenSSL's own verification even if a verify callback
+    /// overrode it to allow the handshake to proceed.
+    pub fn verify_result(&self) -> X509VerifyResult {
+        self.ssl().verify_result()
+    }
+
+    /// Returns `true` if the handshake has completed.
+    ///
+    /// Reads and writes performed before this returns `true` will drive the handshake implicitly.
+    pub fn is_handshake_done(&self) -> bool {
+        self.ssl().is_init_finished()
+    }
+
+    /// Returns `true` if this is the server side of the connection.
+    pub fn is_server_side(&self) -> bool {
+        self.ssl().is_server()
+    }
+
+    /// Returns `true` if the handshake has been started through one of the handshake methods but
+    /// has not yet completed.
+    ///
+    /// The futures returned by [`connect`](Self::connect), [`accept`](Self::accept) and
+    /// [`do_handshake`](Self::do_handshake) are cancel safe: if one is dropped while this returns
+    /// `true`, calling the same method again resumes the handshake where it left off.
+    pub fn is_handshake_in_progress(&self) -> bool {
+        self.handshake_start.is_some() && !self.is_handshake_done()
+    }
+
+    /// Returns what OpenSSL is waiting on after an operation returned `Pending`.
+    ///
+    /// OpenSSL may need to read from the underlying stream during a write and vice versa, so
+    /// custom polling loops can use this to register interest in the right readiness. Returns
+    /// [`ErrorCode::NONE`] if OpenSSL isn't waiting on anything.
+    pub fn want(&self) -> ErrorCode {
+        want::want(self.ssl())
+    }
+
+    /// Returns `true` if OpenSSL is waiting for the underlying stream to become readable.
+    pub fn wants_read(&self) -> bool {
+        self.want() == ErrorCode::WANT_READ
+    }
+
+    /// Returns `true` if OpenSSL is waiting for the underlying stream to become writable.
+    pub fn wants_write(&self) -> bool {
+        self.want() == ErrorCode::WANT_WRITE
+    }
+
+    /// Returns the wall-clock time taken by the handshake.
+    ///
+    /// The handshake is timed from the first call to [`poll_connect`](Self::poll_connect),
+    /// [`poll_accept`](Self::poll_accept) or [`poll_do_handshake`](Self::poll_do_handshake) until
+    /// one of them completes successfully. Returns `None` if the handshake has not been started
+    /// or has not yet completed through those methods.
+    pub fn handshake_duration(&self) -> Option<Duration> {
+        self.handshake_duration
+    }
+
+    /// Returns how [`poll_shutdown`](AsyncWrite::poll_shutdown) closes the TLS session.
+    pub fn shutdown_mode(&self) -> ShutdownMode {
+        self.shutdown.mode
+    }
+
+    /// Sets how [`poll_shutdown`](AsyncWrite::poll_shutdown) closes the TLS session.
+    ///
+    /// Defaults to [`ShutdownMode::Bidirectional`].
+    pub fn set_shutdown_mode(&mut self, mode: ShutdownMode) {
+        self.shutdown.mode = mode;
+    }
+
+    /// Returns `true` if quiet shutdown is enabled for this stream.
+    pub fn quiet_shutdown(&self) -> bool {
+        shutdown::quiet_shutdown(self.ssl())
+    }
+
+    /// Enables or disables quiet shutdown with `SSL_set_quiet_shutdown`.
+    ///
+    /// In quiet mode the session is marked closed without sending or waiting for close notify
+    /// alerts, which suits protocols that already signal the end of the data themselves.
+    /// [`poll_shutdown`](AsyncWrite::poll_shutdown) then only shuts down the underlying stream.
+    /// Quiet shutdown enabled directly on the `SslRef` or its context is honored as well.
+    pub fn set_quiet_shutdown(&mut self, quiet: bool) {
+        shutdown::set_quiet_shutdown(self.inner.ssl_mut(), quiet);
+    }
+
+    /// Returns `true` if [`poll_shutdown`](AsyncWrite::poll_shutdown) also shuts down the
+    /// underlying stream.
+    pub fn shutdown_transport(&self) -> bool {
+        self.shutdown.transport
+    }
+
+    /// Controls whether [`poll_shutdown`](AsyncWrite::poll_shutdown) also shuts down the
+    /// underlying stream once the TLS session has been closed, e.g. sending a TCP FIN.
+    ///
+    /// Defaults to `false`.
+    pub fn set_shutdown_transport(&mut self, transport: bool) {
+        self.shutdown.transport = transport;
+    }
+
+    /// Returns the maximum number of bytes of application data discarded while waiting for the
+    /// peer's close notify during [`poll_shutdown`](AsyncWrite::poll_shutdown).
+    pub fn shutdown_drain_limit(&self) -> usize {
+        self.shutdown.drain_limit
+    }
+
+    /// Sets the maximum number of bytes of application data discarded while waiting for the
+    /// peer's close notify during [`poll_shutdown`](AsyncWrite::poll_shutdown).
+    ///
+    /// Once the limit is exceeded, the shutdown fails with a
+    /// [`ConnectionAborted`](io::ErrorKind::ConnectionAborted) error. This prevents a peer from
+    /// keeping the shutdown alive indefinitely by streaming data. Defaults to 16 KiB.
+    pub fn set_shutdown_drain_limit(&mut self, limit: usize) {
+        self.shutdown.drain_limit = limit;
+    }
+
+    /// Returns `true` if application data received while waiting for the peer's close notify is
+    /// retained rather than discarded.
+    pub fn capture_shutdown_data(&self) -> bool {
+        self.shutdown.capture
+    }
+
+    /// Controls whether application data received while waiting for the peer's close notify is
+    /// retained rather than discarded.
+    ///
+    /// Captured data is bounded by the [drain limit](Self::set_shutdown_drain_limit) and can be
+    /// retrieved with [`take_shutdown_data`](Self::take_shutdown_data). Defaults to `false`.
+    pub fn set_capture_shutdown_data(&mut self, capture: bool) {
+        self.shutdown.capture = capture;
+    }
+
+    /// Returns the application data captured during [`poll_shutdown`](AsyncWrite::poll_shutdown),
+    /// leaving an empty buffer in its place.
+    ///
+    /// This is always empty unless [`set_capture_shutdown_data`](Self::set_capture_shutdown_data)
+    /// was enabled.
+    pub fn take_shutdown_data(&mut self) -> Vec<u8> {
+        mem::take(&mut self.shutdown.captured)
+    }
+
+    /// Returns the size of the buffer used to read application data while waiting for the peer's
+    /// close notify.
+    pub fn shutdown_read_buffer_size(&self) -> usize {
+        self.shutdown.read_buf_size
+    }
+
+    /// Sets the size of the buffer used to read application data while waiting for the peer's
+    /// close notify.
+    ///
+    /// The buffer is allocated when the drain phase of [`poll_shutdown`](AsyncWrite::poll_shutdown)
+    /// starts, reused across polls, and freed once shutdown completes. Defaults to 16 KiB.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn set_shutdown_read_buffer_size(&mut self, size: usize) {
+        assert_ne!(size, 0, "shutdown read buffer size must be nonzero");
+        self.shutdown.read_buf_size = size;
+    }
+
+    /// Returns the path MTU configured with [`set_mtu`](Self::set_mtu), if any.
+    ///
+    /// Requires the `dtls` feature.
+    #[cfg(feature = "dtls")]
+    pub fn mtu(&self) -> Option<u32> {
+        self.mtu
+    }
+
+    /// Sets the path MTU used to size DTLS records, avoiding IP fragmentation.
+    ///
+    /// This should be called before the handshake. It only applies to DTLS; OpenSSL rejects it for
+    /// TLS streams, leaving them unaffected.
+    ///
+    /// Requires the `dtls` feature.
+    #[cfg(feature = "dtls")]
+    pub fn set_mtu(&mut self, mtu: u32) -> Result<(), ErrorStack> {
+        self.inner.ssl_mut().set_mtu(mtu)?;
+        self.mtu = Some(mtu);
+        Ok(())
+    }
+
+    /// Returns the maximum number of bytes passed to `SSL_write` by a single
+    /// [`poll_write`](AsyncWrite::poll_write) call.
+    pub fn max_write_chunk(&self) -> Option<usize> {
+        self.max_write_chunk
+    }
+
+    /// Caps the number of bytes passed to `SSL_write` by a single
+    /// [`poll_write`](AsyncWrite::poll_write) call.
+    ///
+    /// Larger writes report a short write count, so callers such as
+    /// [`write_all`](tokio::io::AsyncWriteExt::write_all) loop naturally. This bounds the amount
+    /// of data serialized into records per call. Defaults to `None`, which imposes no limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is `Some(0)`.
+    pub fn set_max_write_chunk(&mut self, max: Option<usize>) {
+        assert_ne!(max, Some(0), "max_write_chunk must be nonzero");
+        self.max_write_chunk = max;
+    }
+
+    /// Returns whether a missing close notify is reported as a clean EOF.
+    pub fn allow_unclean_eof(&self) -> bool {
+        self.allow_unclean_eof
+    }
+
+    /// Controls how reads report the peer closing the transport without sending a close notify.
+    ///
+    /// By default this is an [`io::ErrorKind::UnexpectedEof`] error, since it is indistinguishable
+    /// from an attacker truncating the stream. Many servers close connections this way anyway, so
+    /// protocols which delimit their own messages can set this to treat it as end-of-stream
+    /// instead. An unexpected EOF during the handshake is always an error.
+    pub fn set_allow_unclean_eof(&mut self, allow: bool) {
+        self.allow_unclean_eof = allow;
+    }
+
+    /// Returns the read inactivity timeout.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout.duration()
+    }
+
+    /// Sets a timeout for reads which make no progress.
+    ///
+    /// If a [`poll_read`](AsyncRead::poll_read) call stays pending for longer than `timeout`, it
+    /// fails with an [`io::ErrorKind::TimedOut`] error. The timer restarts after every completed
+    /// read, so this bounds idle time rather than the lifetime of the connection. Defaults to
+    /// `None`, which disables the timeout.
+    ///
+    /// This requires a Tokio runtime with the time driver enabled.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout.set_duration(timeout);
+    }
+
+    /// Returns the write inactivity timeout.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout.duration()
+    }
+
+    /// Sets a timeout for writes which make no progress.
+    ///
+    /// This behaves like [`set_read_timeout`](Self::set_read_timeout), but applies to
+    /// [`poll_write`](AsyncWrite::poll_write).
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout.set_duration(timeout);
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        self.inner.get_ref().stream()
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.inner.get_mut().stream_mut()
+    }
+
+    /// Returns a pinned mutable reference to the underlying stream.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut S> {
+        unsafe { Pin::new_unchecked(self.get_unchecked_mut().inner.get_mut().stream_mut()) }
+    }
+
+    fn shutdown_mut(self: Pin<&mut Self>) -> &mut Shutdown {
+        unsafe { &mut self.get_unchecked_mut().shutdown }
+    }
+
+    fn read_timeout_mut(self: Pin<&mut Self>) -> &mut Timeout {
+        unsafe { &mut self.get_unchecked_mut().read_timeout }
+    }
+
+    fn write_timeout_mut(self: Pin<&mut Self>) -> &mut Timeout {
+        unsafe { &mut self.get_unchecked_mut().write_timeout }
+    }
+
+    fn finish_shutdown(self: Pin<&mut Self>, r: io::Result<()>) -> Poll<io::Result<()>> {
+        #[cfg(feature = "metrics")]
+        if r.is_err() {
+            metrics::add(&metrics::SHUTDOWN_ERRORS_TOTAL, 1);
+        }
+
+        let shutdown = self.shutdown_mut();
+        shutdown.state = match &r {
+            Ok(()) => ShutdownState::Done,
+            Err(e) => ShutdownState::Failed(e.kind()),
+        };
+        shutdown.read_buf = None;
+        Poll::Ready(r)
+    }
+
+    fn poll_timed_handshake<F>(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        f: F,
+    ) -> Poll<Result<(), ssl::Error>>
+    where
+        F: FnOnce(&mut ssl::SslStream<StreamWrapper<S>>) -> Result<(), ssl::Error>,
+    {
+        let start = {
+            let this = unsafe { self.as_mut().get_unchecked_mut() };
+            *this.handshake_start.get_or_insert_with(Instant::now)
+        };
+
+        let r = match self.as_mut().with_context(cx, f) {
+            // the acceptors do their asynchronous work between polls of a handshake they paused
+            // at the ClientHello, so that pause is surfaced rather than retried
+            #[cfg(ossl111)]
+            Err(e)
+                if e.code() == ErrorCode::WANT_CLIENT_HELLO_CB
+                    && crate::client_hello::is_paused(self.ssl()) =>
+            {
+                Poll::Ready(Err(e))
+            }
+            // likewise, the connector verifies the peer while the handshake is paused for it
+            #[cfg(ossl300)]
+            Err(e) if crate::verify::retry::is_paused(&e) => Poll::Ready(Err(e)),
+            r => poll_from_ssl_result_cx(cx, r),
+        };
+
+        if let Poll::Ready(Ok(())) = r {
+            let this = unsafe { self.get_unchecked_mut() };
+            if this.handshake_duration.is_none() {
+                this.handshake_duration = Some(start.elapsed());
+
+                #[cfg(feature = "metrics")]
+                metrics::add(&metrics::HANDSHAKES_TOTAL, 1);
+
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    target: "tokio_openssl",
+                    cipher = %this.inner.ssl().current_cipher().map_or("unknown", |c| c.name()),
+                    version = %this.inner.ssl().version_str(),
+                    event = "handshake_done",
+                );
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        if let Poll::Ready(Err(e)) = &r {
+            metrics::record_handshake_error(e);
+        }
+
+        r
+    }
+
+    /// Like [`with_context`](Self::with_context), converting the result of `f` into a [`Poll`].
+    fn poll_ssl<F, T>(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        f: F,
+    ) -> Poll<Result<T, ssl::Error>>
+    where
+        F: FnOnce(&mut ssl::SslStream<StreamWrapper<S>>) -> Result<T, ssl::Error>,
+    {
+        let r = self.as_mut().with_context(cx, f);
+        poll_from_ssl_result_cx(cx, r)
+    }
+
+    fn with_context<F, R>(self: Pin<&mut Self>, ctx: &mut Context<'_>, f: F) -> R
+    where
+        F: FnOnce(&mut ssl::SslStream<StreamWrapper<S>>) -> R,
+    {
+        // SAFETY: the stream is not moved out of the pinned reference.
+        let this = unsafe { self.get_unchecked_mut() };
+        // SAFETY: the guard clears the context before `ctx`'s borrow ends, even on unwind.
+        unsafe { this.inner.get_mut().set_context(ctx) };
+        let mut guard = ContextGuard(&mut this.inner);
+        f(&mut *guard.0)
+    }
+}
+
+#[cfg(ossl110)]
+impl<S> SslStream<S>
+where
+    S: Read + Write + Unpin,
+{
+    /// Converts this stream into a blocking [`ssl::SslStream`] over the same underlying stream.
+    ///
+    /// The TLS session, including any data OpenSSL has already buffered, carries over to the
+    /// returned stream. It performs blocking I/O through `S`'s [`Read`] and [`Write`]
+    /// implementations, so it must not be used on an async runtime's worker threads; hand it to
+    /// `tokio::task::spawn_blocking` or a dedicated thread instead.
+    ///
+    /// Returns an error if the handshake has not completed.
+    pub fn into_std(mut self) -> Result<ssl::SslStream<S>, Error> {
+        if !self.is_handshake_done() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the TLS handshake has not completed",
+            )));
+        }
+
+        let stream = self.inner.get_mut().stream.take().expect("stream taken");
+        let ptr = self.ssl().as_ptr();
+        // SAFETY: the extra reference keeps the session alive once `self` releases its own. The
+        // old BIO is detached and freed while the BIO method it uses is still owned by
+        // `self.inner`, and its stream has been taken so it never touches `S` again.
+        let ssl = unsafe {
+            openssl_sys::SSL_up_ref(ptr);
+            openssl_sys::SSL_set_bio(ptr, std::ptr::null_mut(), std::ptr::null_mut());
+            <Ssl as foreign_types::ForeignType>::from_ptr(ptr)
+        };
+        drop(self);
+
+        ssl::SslStream::new(ssl, stream).map_err(Error::Stack)
+    }
+}
+
+#[cfg(ossl110)]
+impl<S> SslStream<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    /// Converts a blocking [`ssl::SslStream`] into an `SslStream` over `transport`.
+    ///
+    /// OpenSSL provides no way to move the underlying stream out of an [`ssl::SslStream`], so the
+    /// connection to the peer is supplied separately: typically a Tokio socket created from a
+    /// [`try_clone`](std::net::TcpStream::try_clone) of the blocking one. The TLS session,
+    /// including any data OpenSSL has already buffered, carries over to the returned stream, while
+    /// the blocking stream's underlying stream is dropped. That stream must not buffer data of its
+    /// own, as any such data is lost.
+    ///
+    /// This is the inverse of [`into_std`](SslStream::into_std).
+    pub fn from_std<T>(stream: ssl::SslStream<T>, transport: S) -> Result<Self, ErrorStack> {
+        let ptr = stream.ssl().as_ptr();
+        // SAFETY: the extra reference keeps the session alive once `stream` releases its own. The
+        // old BIO is detached and freed, dropping `T`, while the BIO method it uses is still owned
+        // by `stream`.
+        let ssl = unsafe {
+            openssl_sys::SSL_up_ref(ptr);
+            openssl_sys::SSL_set_bio(ptr, std::ptr::null_mut(), std::ptr::null_mut());
+            <Ssl as foreign_types::ForeignType>::from_ptr(ptr)
+        };
+        drop(stream);
+
+        SslStream::new(ssl, transport)
+    }
+}
+
+impl<S> fmt::Display for SslStream<S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_handshake_done() {
+            return fmt.write_str("SslStream { state: pre-handshake }");
+        }
+
+        let ssl = self.ssl();
+        let cipher = ssl.current_cipher().map_or("none", |c| c.name());
+        write!(
+            fmt,
+            "SslStream {{ version: {}, cipher: {} }}",
+            ssl.version_str(),
+            cipher
+        )
+    }
+}
+
+// The context pointer is only set for the duration of `with_context`, so nothing in the stream
+// itself is address-sensitive.
+impl<S> Unpin for SslStream<S> where S: Unpin {}
+
+/// Convenience methods for [`SslStream`]s over [`Unpin`] streams which don't require pinning.
+///
+/// These mirror the [`Pin`]-based methods of the same name on [`SslStream`].
+pub trait SslStreamExt {
+    /// The underlying stream type.
+    type Stream;
+
+    /// Like [`SslStream::connect`], but takes `&mut self`.
+    fn connect(&mut self) -> Connect<'_, Self::Stream>;
+
+    /// Like [`SslStream::accept`], but takes `&mut self`.
+    fn accept(&mut self) -> Accept<'_, Self::Stream>;
+
+    /// Like [`SslStream::do_handshake`], but takes `&mut self`.
+    fn do_handshake(&mut self) -> DoHandshake<'_, Self::Stream>;
+
+    /// Like [`SslStream::ssl_read`], but takes `&mut self`.
+    fn ssl_read<'a>(&'a mut self, buf: &'a mut [u8]) -> SslRead<'a, Self::Stream>;
+}
+
+impl<S> SslStreamExt for SslStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Stream = S;
+
+    fn connect(&mut self) -> Connect<'_, S> {
+        Connect::new(Pin::new(self))
+    }
+
+    fn accept(&mut self) -> Accept<'_, S> {
+        Accept::new(Pin::new(self))
+    }
+
+    fn do_handshake(&mut self) -> DoHandshake<'_, S> {
+        DoHandshake::new(Pin::new(self))
+    }
+
+    fn ssl_read<'a>(&'a mut self, buf: &'a mut [u8]) -> SslRead<'a, S> {
+        SslRead::new(Pin::new(self), buf)
+    }
+}
+
+impl<S> AsyncRead for SslStream<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // SSL_read's handling of empty buffers varies between OpenSSL versions
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        let allow_unclean_eof = self.allow_unclean_eof;
+        let r = self.as_mut().with_context(ctx, |s| {
+            // SSL_read_ex reads into the uninitialized part of the buffer directly.
+            // SAFETY: OpenSSL only writes to the buffer, so it can't de-initialize any of it.
+            #[cfg(ossl111)]
+            let r = ssl_read(s, allow_unclean_eof, |s| {
+                s.ssl_read_uninit(unsafe { buf.unfilled_mut() })
+            });
+            // This isn't really "proper", but SSL_read only takes an initialized buffer in
+            // rust-openssl even though OpenSSL itself doesn't require it. So this is good enough.
+            #[cfg(not(ossl111))]
+            let r = {
+                let slice = unsafe {
+                    let buf = buf.unfilled_mut();
+                    std::slice::from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), buf.len())
+                };
+                ssl_read(s, allow_unclean_eof, |s| s.ssl_read(slice))
+            };
+            match poll_from_io_result(r)? {
+                Poll::Ready(nread) => {
+                    unsafe {
+                        buf.assume_init(nread);
+                    }
+                    buf.advance(nread);
+                    #[cfg(feature = "metrics")]
+                    metrics::add(&metrics::BYTES_READ_TOTAL, nread as u64);
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(target: "tokio_openssl", bytes = nread, event = "poll_read");
+                    Poll::Ready(Ok(()))
+                }
+                Poll::Pending => Poll::Pending,
+            }
+        });
+        self.read_timeout_mut().poll(ctx, r)
+    }
+}
+
+impl<S> AsyncWrite for SslStream<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        ctx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let buf = match self.max_write_chunk {
+            Some(max) if buf.len() > max => &buf[..max],
+            _ => buf,
+        };
+        let r = self
+            .as_mut()
+            .with_context(ctx, |s| poll_from_io_result(s.write(buf)));
+        #[cfg(feature = "metrics")]
+        if let Poll::Ready(Ok(n)) = r {
+            metrics::add(&metrics::BYTES_WRITTEN_TOTAL, n as u64);
+        }
+        #[cfg(feature = "tracing")]
+        if let Poll::Ready(Ok(n)) = r {
+            tracing::trace!(target: "tokio_openssl", bytes = n, event = "poll_write");
+        }
+        self.write_timeout_mut().poll(ctx, r)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<io::Result<()>> {
+        self.with_context(ctx, |s| poll_from_io_result(s.flush()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<io::Result<()>> {
+        let limit = self.shutdown.drain_limit;
+        self.poll_shutdown_limited(ctx, limit)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        // SSL_write has no scatter-gather interface, and only consumes a prefix of what it's
+        // given anyway, so gather at most one chunk's worth of the leading buffers on the stack
+        // to avoid writing a separate record per slice.
+        let limit = self
+            .max_write_chunk
+            .unwrap_or(VECTORED_STACK_BUF_LEN)
+            .min(VECTORED_STACK_BUF_LEN);
+        let mut bufs = bufs.iter().filter(|b| !b.is_empty());
+        let first = match bufs.next() {
+            Some(first) => first,
+            None => return self.poll_write(cx, &[]),
+        };
+        // a large leading buffer is written as is, without copying
+        if first.len() >= limit {
+            return self.poll_write(cx, first);
+        }
+
+        let mut buf = [mem::MaybeUninit::<u8>::uninit(); VECTORED_STACK_BUF_LEN];
+        let mut len = 0;
+        for b in std::iter::once(first).chain(bufs) {
+            let n = b.len().min(limit - len);
+            // SAFETY: `len + n <= limit <= buf.len()`, and the slices don't overlap.
+            unsafe {
+                std::ptr::copy_nonoverlapping(b.as_ptr(), buf[len..].as_mut_ptr().cast::<u8>(), n)
+            };
+            len += n;
+            if len == limit {
+                break;
+            }
+        }
+        // SAFETY: the first `len` bytes were initialized above.
+        let buf = unsafe { std::slice::from_raw_parts(buf.as_ptr().cast::<u8>(), len) };
+        self.poll_write(cx, buf)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
     }
 }
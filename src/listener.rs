@@ -0,0 +1,135 @@
+use crate::{SslStream, SslStreamAcceptor};
+use futures_util::future;
+use futures_util::stream::Stream;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// A source of incoming connections which can be wrapped by an [`SslListener`].
+///
+/// This trait is sealed and cannot be implemented outside of this crate.
+pub trait Listener: private::Sealed {
+    /// The type of the accepted connections.
+    type Stream: AsyncRead + AsyncWrite + Unpin;
+
+    /// Polls to accept a new incoming connection.
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<(Self::Stream, SocketAddr)>>;
+}
+
+impl private::Sealed for TcpListener {}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<(TcpStream, SocketAddr)>> {
+        TcpListener::poll_accept(self, cx)
+    }
+}
+
+type Handshake<S> = Pin<Box<dyn Future<Output = io::Result<(SslStream<S>, SocketAddr)>> + Send>>;
+
+/// A listener which accepts connections and performs the server side of the TLS handshake on
+/// them.
+///
+/// In addition to [`accept`](Self::accept), this implements [`Stream`], yielding each
+/// connection once its handshake completes. The stream performs one handshake at a time, so a
+/// slow client delays the ones behind it; servers which need concurrent handshakes should
+/// accept on the raw listener and call [`SslStreamAcceptor::accept`] from separate tasks.
+pub struct SslListener<L>
+where
+    L: Listener,
+{
+    listener: L,
+    acceptor: SslStreamAcceptor,
+    handshake: Option<Handshake<L::Stream>>,
+}
+
+impl<L> fmt::Debug for SslListener<L>
+where
+    L: Listener + fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SslListener")
+            .field("listener", &self.listener)
+            .finish()
+    }
+}
+
+impl<L> SslListener<L>
+where
+    L: Listener,
+{
+    /// Creates a new listener which performs handshakes with `acceptor`.
+    pub fn new(listener: L, acceptor: SslStreamAcceptor) -> SslListener<L> {
+        SslListener {
+            listener,
+            acceptor,
+            handshake: None,
+        }
+    }
+
+    /// Returns a shared reference to the underlying listener.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+
+    /// Returns a shared reference to the acceptor used for handshakes.
+    pub fn acceptor(&self) -> &SslStreamAcceptor {
+        &self.acceptor
+    }
+
+    /// Accepts a new connection and performs the TLS handshake on it.
+    ///
+    /// An error is returned if either accepting the connection or the handshake fails. A failed
+    /// handshake only affects that connection, so servers should generally keep accepting.
+    pub async fn accept(&self) -> io::Result<(SslStream<L::Stream>, SocketAddr)> {
+        let (stream, addr) = future::poll_fn(|cx| self.listener.poll_accept(cx)).await?;
+        let stream = self
+            .acceptor
+            .accept(stream)
+            .await
+            .map_err(io::Error::from)?;
+        Ok((stream, addr))
+    }
+}
+
+impl<L> Stream for SslListener<L>
+where
+    L: Listener + Unpin,
+    L::Stream: Send + 'static,
+{
+    type Item = io::Result<(SslStream<L::Stream>, SocketAddr)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if this.handshake.is_none() {
+            let (stream, addr) = match this.listener.poll_accept(cx) {
+                Poll::Ready(Ok(v)) => v,
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => return Poll::Pending,
+            };
+            let acceptor = this.acceptor.clone();
+            this.handshake = Some(Box::pin(async move {
+                let stream = acceptor.accept(stream).await.map_err(io::Error::from)?;
+                Ok((stream, addr))
+            }));
+        }
+
+        let r = match this.handshake.as_mut().unwrap().as_mut().poll(cx) {
+            Poll::Ready(r) => r,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.handshake = None;
+        Poll::Ready(Some(r))
+    }
+}
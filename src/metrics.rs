@@ -0,0 +1,83 @@
+//! Process-wide counters for TLS streams.
+//!
+//! The counters are plain atomics shared by every [`SslStream`](crate::SslStream) in the process,
+//! so they can be exported through whatever metrics registry the application already uses by
+//! periodically reading a [`snapshot`].
+//!
+//! Counters only ever increase unless [`reset`] is called. Monitoring systems such as Prometheus
+//! expect counters to be monotonic and handle restarts on their own, so `reset` is mainly useful
+//! for tests and for exporters which report deltas between scrapes.
+
+use openssl::ssl;
+#[cfg(ossl111)]
+use openssl::ssl::ErrorCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub(crate) static HANDSHAKES_TOTAL: AtomicU64 = AtomicU64::new(0);
+pub(crate) static HANDSHAKE_ERRORS_TOTAL: AtomicU64 = AtomicU64::new(0);
+pub(crate) static BYTES_READ_TOTAL: AtomicU64 = AtomicU64::new(0);
+pub(crate) static BYTES_WRITTEN_TOTAL: AtomicU64 = AtomicU64::new(0);
+pub(crate) static SHUTDOWN_ERRORS_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// A point-in-time copy of the counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// The number of handshakes which completed successfully.
+    pub handshakes_total: u64,
+    /// The number of handshakes which failed.
+    pub handshake_errors_total: u64,
+    /// The number of plaintext bytes read from streams.
+    pub bytes_read_total: u64,
+    /// The number of plaintext bytes written to streams.
+    pub bytes_written_total: u64,
+    /// The number of shutdowns which failed.
+    pub shutdown_errors_total: u64,
+}
+
+/// Returns the current value of every counter.
+///
+/// The counters are read individually, so a snapshot taken while streams are active may not
+/// reflect a single instant.
+pub fn snapshot() -> MetricsSnapshot {
+    MetricsSnapshot {
+        handshakes_total: HANDSHAKES_TOTAL.load(Ordering::Relaxed),
+        handshake_errors_total: HANDSHAKE_ERRORS_TOTAL.load(Ordering::Relaxed),
+        bytes_read_total: BYTES_READ_TOTAL.load(Ordering::Relaxed),
+        bytes_written_total: BYTES_WRITTEN_TOTAL.load(Ordering::Relaxed),
+        shutdown_errors_total: SHUTDOWN_ERRORS_TOTAL.load(Ordering::Relaxed),
+    }
+}
+
+/// Resets every counter to zero.
+pub fn reset() {
+    for counter in &[
+        &HANDSHAKES_TOTAL,
+        &HANDSHAKE_ERRORS_TOTAL,
+        &BYTES_READ_TOTAL,
+        &BYTES_WRITTEN_TOTAL,
+        &SHUTDOWN_ERRORS_TOTAL,
+    ] {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+pub(crate) fn record_handshake_error(e: &ssl::Error) {
+    // the async SNI and verify callbacks suspend the handshake with errors that aren't failures
+    #[cfg(ossl111)]
+    {
+        if e.code() == ErrorCode::WANT_CLIENT_HELLO_CB {
+            return;
+        }
+    }
+    #[cfg(ossl300)]
+    {
+        if crate::verify::retry::is_paused(e) {
+            return;
+        }
+    }
+    add(&HANDSHAKE_ERRORS_TOTAL, 1);
+}
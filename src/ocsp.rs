@@ -0,0 +1,285 @@
+use crate::ex_data::ExIndex;
+use crate::Error;
+use foreign_types::ForeignTypeRef;
+use openssl::asn1::{Asn1GeneralizedTimeRef, Asn1Time, Asn1TimeRef};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::ocsp::{OcspCertId, OcspCertStatus, OcspFlag, OcspResponse, OcspResponseStatus};
+use openssl::ssl::{Ssl, SslConnectorBuilder, SslContextBuilder, SslRef};
+use openssl::stack::Stack;
+use openssl::x509::{X509Ref, X509};
+use std::error;
+use std::future::Future;
+use std::pin::Pin;
+#[cfg(feature = "rt")]
+use std::sync::Weak;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(feature = "rt")]
+use tokio::task::JoinHandle;
+
+/// The clock skew tolerated when checking that a response is current, in seconds.
+const VALIDITY_LEEWAY: u32 = 5 * 60;
+/// How often responses without a nextUpdate time are refreshed.
+#[cfg(feature = "rt")]
+const DEFAULT_REFRESH: Duration = Duration::from_secs(60 * 60);
+/// The minimum delay between successful refreshes.
+#[cfg(feature = "rt")]
+const MIN_REFRESH: Duration = Duration::from_secs(60);
+/// The delay before retrying a failed refresh.
+#[cfg(feature = "rt")]
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The future returned by the fetch function of an [`OcspStapler`], resolving to a DER-encoded
+/// OCSP response.
+pub type OcspFetchFuture =
+    Pin<Box<dyn Future<Output = Result<Vec<u8>, Box<dyn error::Error + Send + Sync>>> + Send>>;
+
+type FetchCallback = dyn Fn() -> OcspFetchFuture + Send + Sync;
+
+struct Stapled {
+    der: Vec<u8>,
+    /// The response's nextUpdate time, if it has one which can be represented.
+    expires: Option<Instant>,
+}
+
+/// Staples OCSP responses for a server's certificate to its handshakes.
+///
+/// The stapler fetches responses with a user-supplied function, typically an HTTP request to the
+/// responder named in the certificate's authority information access extension, and serves the
+/// latest one to clients which request certificate status. Handshakes never wait for a fetch.
+///
+/// Responses are checked to cover the certificate and to be current, but their signatures are not
+/// verified; that is left to the clients. A response is no longer stapled once its nextUpdate time
+/// has passed.
+pub struct OcspStapler {
+    cert: X509,
+    issuer: X509,
+    fetch: Box<FetchCallback>,
+    response: Mutex<Option<Stapled>>,
+}
+
+impl OcspStapler {
+    /// Creates a new stapler for `cert`, which was issued by `issuer`.
+    ///
+    /// No response is stapled until one has been fetched with [`refresh`](Self::refresh) or
+    /// `spawn_refresh`, which requires the `rt` feature.
+    pub fn new<F>(cert: X509, issuer: X509, fetch: F) -> Arc<OcspStapler>
+    where
+        F: Fn() -> OcspFetchFuture + 'static + Send + Sync,
+    {
+        Arc::new(OcspStapler {
+            cert,
+            issuer,
+            fetch: Box::new(fetch),
+            response: Mutex::new(None),
+        })
+    }
+
+    /// Configures `builder` to staple this stapler's response to handshakes.
+    ///
+    /// If an acceptor switches contexts based on the server name, the stapler should be installed
+    /// on the context holding its certificate.
+    ///
+    /// This replaces any status callback previously configured on the builder.
+    pub fn install(self: &Arc<Self>, builder: &mut SslContextBuilder) -> Result<(), ErrorStack> {
+        let stapler = self.clone();
+        builder.set_status_callback(move |ssl| match stapler.response() {
+            Some(der) => {
+                ssl.set_ocsp_status(&der)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        })
+    }
+
+    /// Fetches a new response, replacing the current one if it is valid.
+    ///
+    /// On failure, the current response keeps being stapled until it expires.
+    pub async fn refresh(&self) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let der = (self.fetch)().await?;
+
+        let response = OcspResponse::from_der(&der)?;
+        if response.status() != OcspResponseStatus::SUCCESSFUL {
+            return Err(format!(
+                "OCSP responder returned status {}",
+                response.status().as_raw()
+            )
+            .into());
+        }
+        let basic = response.basic()?;
+        let id = OcspCertId::from_cert(MessageDigest::sha1(), &self.cert, &self.issuer)?;
+        let status = basic
+            .find_status(&id)
+            .ok_or("OCSP response does not cover the certificate")?;
+        status.check_validity(VALIDITY_LEEWAY, None)?;
+
+        let expires = match status.next_update {
+            Some(next_update) => Instant::now().checked_add(time_until(next_update)?),
+            None => None,
+        };
+        *self.response.lock().unwrap() = Some(Stapled { der, expires });
+        Ok(())
+    }
+
+    /// Spawns a task onto the current Tokio runtime which keeps the response fresh.
+    ///
+    /// The task fetches a response immediately, and then again halfway to each response's
+    /// nextUpdate time, or hourly for responses without one. Failed fetches are retried every
+    /// minute. The task exits once the stapler is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    #[cfg(feature = "rt")]
+    pub fn spawn_refresh(self: &Arc<Self>) -> JoinHandle<()> {
+        let stapler = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                let delay = match Weak::upgrade(&stapler) {
+                    Some(stapler) => match stapler.refresh().await {
+                        Ok(()) => stapler.refresh_delay(),
+                        Err(_) => RETRY_INTERVAL,
+                    },
+                    None => break,
+                };
+                tokio::time::sleep(delay).await;
+            }
+        })
+    }
+
+    /// Returns the DER-encoded response currently stapled, if any.
+    pub fn response(&self) -> Option<Vec<u8>> {
+        let response = self.response.lock().unwrap();
+        let response = response.as_ref()?;
+        match response.expires {
+            Some(expires) if expires <= Instant::now() => None,
+            _ => Some(response.der.clone()),
+        }
+    }
+
+    #[cfg(feature = "rt")]
+    fn refresh_delay(&self) -> Duration {
+        let response = self.response.lock().unwrap();
+        match response.as_ref().and_then(|r| r.expires) {
+            Some(expires) => {
+                (expires.saturating_duration_since(Instant::now()) / 2).max(MIN_REFRESH)
+            }
+            None => DEFAULT_REFRESH,
+        }
+    }
+}
+
+fn time_until(time: &Asn1GeneralizedTimeRef) -> Result<Duration, ErrorStack> {
+    // SAFETY: ASN1_GENERALIZEDTIME and ASN1_TIME are both ASN1_STRINGs, and OpenSSL's time
+    // functions accept either.
+    let time = unsafe { Asn1TimeRef::from_ptr(time.as_ptr() as *mut openssl_sys::ASN1_TIME) };
+    let diff = Asn1Time::days_from_now(0)?.diff(time)?;
+    let secs = i64::from(diff.days) * 24 * 60 * 60 + i64::from(diff.secs);
+    Ok(Duration::from_secs(secs.max(0) as u64))
+}
+
+/// How [`SslStreamConnector`](crate::SslStreamConnector)s handle OCSP responses stapled by
+/// servers, configured with
+/// [`SslStreamConnectorBuilder::set_ocsp_stapling`](crate::SslStreamConnectorBuilder::set_ocsp_stapling).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OcspStaplingPolicy {
+    /// Don't request a stapled response.
+    Off,
+    /// Request a stapled response and verify it if the server sends one.
+    ///
+    /// A missing response is only an error if the server's certificate is marked as must-staple.
+    IfPresent,
+    /// Request a stapled response, and fail if the server doesn't send one.
+    Require,
+}
+
+/// The reason a connection's stapled response was rejected.
+struct OcspFailure(String);
+
+static INDEX: ExIndex<Ssl, OcspFailure> = ExIndex::new();
+
+pub(crate) fn set_stapling_policy(
+    builder: &mut SslConnectorBuilder,
+    policy: OcspStaplingPolicy,
+) -> Result<(), ErrorStack> {
+    let index = INDEX.index()?;
+    builder.set_status_callback(move |ssl| match verify_staple(ssl, policy) {
+        Ok(()) => Ok(true),
+        Err(reason) => {
+            ssl.set_ex_data(index, OcspFailure(reason));
+            Ok(false)
+        }
+    })
+}
+
+/// Returns the error describing why the stapled response of `ssl` was rejected, if it was.
+pub(crate) fn failure(ssl: &SslRef) -> Option<Error> {
+    let failure = ssl.ex_data(INDEX.get()?)?;
+    Some(Error::Ocsp(failure.0.clone()))
+}
+
+fn verify_staple(ssl: &SslRef, policy: OcspStaplingPolicy) -> Result<(), String> {
+    let chain = crate::verify::peer_chain(ssl);
+    let leaf = chain
+        .first()
+        .ok_or("server did not present a certificate")?;
+
+    let der = match ssl.ocsp_status() {
+        Some(der) => der,
+        None if policy == OcspStaplingPolicy::Require => {
+            return Err("server did not staple an OCSP response".to_string())
+        }
+        None if must_staple(leaf) => {
+            return Err(
+                "server certificate is must-staple, but no OCSP response was stapled".to_string(),
+            )
+        }
+        None => return Ok(()),
+    };
+
+    let response =
+        OcspResponse::from_der(der).map_err(|e| format!("invalid OCSP response: {}", e))?;
+    if response.status() != OcspResponseStatus::SUCCESSFUL {
+        return Err(format!(
+            "OCSP responder returned status {}",
+            response.status().as_raw()
+        ));
+    }
+    let basic = response
+        .basic()
+        .map_err(|e| format!("invalid OCSP response: {}", e))?;
+
+    let mut certs = Stack::new().map_err(|e| e.to_string())?;
+    for cert in &chain {
+        certs.push(cert.clone()).map_err(|e| e.to_string())?;
+    }
+    basic
+        .verify(&certs, ssl.ssl_context().cert_store(), OcspFlag::empty())
+        .map_err(|e| format!("OCSP response verification failed: {}", e))?;
+
+    // a self-signed leaf is its own issuer
+    let issuer = chain.get(1).unwrap_or(leaf);
+    let id =
+        OcspCertId::from_cert(MessageDigest::sha1(), leaf, issuer).map_err(|e| e.to_string())?;
+    let status = basic
+        .find_status(&id)
+        .ok_or("OCSP response does not cover the server certificate")?;
+    status
+        .check_validity(VALIDITY_LEEWAY, None)
+        .map_err(|e| format!("OCSP response is not current: {}", e))?;
+
+    match status.status {
+        OcspCertStatus::GOOD => Ok(()),
+        OcspCertStatus::REVOKED => Err("server certificate has been revoked".to_string()),
+        _ => Err("server certificate status is unknown".to_string()),
+    }
+}
+
+/// Returns `true` if `cert` has the TLS feature extension from RFC 7633, which marks it as
+/// must-staple.
+fn must_staple(cert: &X509Ref) -> bool {
+    // SAFETY: `cert` is a valid certificate, which the lookup only reads.
+    unsafe { openssl_sys::X509_get_ext_by_NID(cert.as_ptr(), Nid::TLSFEATURE.as_raw(), -1) >= 0 }
+}
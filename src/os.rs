@@ -0,0 +1,133 @@
+//! Access to the OS handle of the underlying stream.
+
+use crate::SslStream;
+use std::io;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// A socket which can report errors that occurred outside of reads and writes, such as the peer
+/// resetting the connection.
+///
+/// This trait is sealed, and implemented for Tokio's TCP and Unix domain sockets when the `net`
+/// feature is enabled.
+pub trait TakeError: private::Sealed {
+    /// Returns and clears the value of the socket's `SO_ERROR` option.
+    fn take_error(&self) -> io::Result<Option<io::Error>>;
+}
+
+#[cfg(feature = "net")]
+impl private::Sealed for tokio::net::TcpStream {}
+
+#[cfg(feature = "net")]
+impl TakeError for tokio::net::TcpStream {
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        tokio::net::TcpStream::take_error(self)
+    }
+}
+
+#[cfg(all(unix, feature = "net"))]
+impl private::Sealed for tokio::net::UnixStream {}
+
+#[cfg(all(unix, feature = "net"))]
+impl TakeError for tokio::net::UnixStream {
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        tokio::net::UnixStream::take_error(self)
+    }
+}
+
+impl<S> SslStream<S>
+where
+    S: TakeError,
+{
+    /// Returns and clears any pending error on the underlying socket.
+    ///
+    /// This detects errors such as a connection reset by the peer without reading from the
+    /// stream. Data buffered by OpenSSL is unaffected.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.get_ref().take_error()
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+
+    impl<S> SslStream<S>
+    where
+        S: AsRawFd,
+    {
+        /// Returns the file descriptor of the underlying stream.
+        ///
+        /// This allows the stream to be registered with an event loop such as `epoll` directly.
+        /// Reading from or writing to the descriptor bypasses TLS and will corrupt the session.
+        pub fn raw_fd(&self) -> RawFd {
+            self.get_ref().as_raw_fd()
+        }
+    }
+
+    impl<S> AsRawFd for SslStream<S>
+    where
+        S: AsRawFd,
+    {
+        fn as_raw_fd(&self) -> RawFd {
+            self.raw_fd()
+        }
+    }
+
+    impl<S> AsFd for SslStream<S>
+    where
+        S: AsFd,
+    {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            self.get_ref().as_fd()
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::*;
+    use std::os::windows::io::{
+        AsHandle, AsRawHandle, AsRawSocket, AsSocket, BorrowedHandle, BorrowedSocket, RawHandle,
+        RawSocket,
+    };
+
+    impl<S> AsRawSocket for SslStream<S>
+    where
+        S: AsRawSocket,
+    {
+        fn as_raw_socket(&self) -> RawSocket {
+            self.get_ref().as_raw_socket()
+        }
+    }
+
+    impl<S> AsSocket for SslStream<S>
+    where
+        S: AsSocket,
+    {
+        fn as_socket(&self) -> BorrowedSocket<'_> {
+            self.get_ref().as_socket()
+        }
+    }
+
+    impl<S> AsRawHandle for SslStream<S>
+    where
+        S: AsRawHandle,
+    {
+        fn as_raw_handle(&self) -> RawHandle {
+            self.get_ref().as_raw_handle()
+        }
+    }
+
+    impl<S> AsHandle for SslStream<S>
+    where
+        S: AsHandle,
+    {
+        fn as_handle(&self) -> BorrowedHandle<'_> {
+            self.get_ref().as_handle()
+        }
+    }
+}
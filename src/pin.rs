@@ -0,0 +1,97 @@
+//! Public key pinning for client connections.
+
+use crate::ex_data::ExIndex;
+use openssl::error::ErrorStack;
+use openssl::sha;
+use openssl::ssl::{Ssl, SslConnectorBuilder, SslRef, SslVerifyMode};
+use openssl::stack::StackRef;
+use openssl::x509::{X509StoreContext, X509VerifyResult, X509};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// When the public key pins configured by
+/// [`SslStreamConnectorBuilder::set_spki_pins`](crate::SslStreamConnectorBuilder::set_spki_pins)
+/// are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinMode {
+    /// Check the pins once the handshake has completed.
+    ///
+    /// If none match, [`SslStreamConnector::connect`](crate::SslStreamConnector::connect) fails
+    /// with [`Error::PinMismatch`](crate::Error::PinMismatch) and the stream is dropped, but the
+    /// server is not sent an alert.
+    AfterHandshake,
+    /// Check the pins during certificate verification.
+    ///
+    /// If none match, the handshake is aborted with a certificate alert and fails with a
+    /// [`HandshakeError`](crate::HandshakeError) whose verify result is
+    /// [`X509VerifyResult::APPLICATION_VERIFICATION`].
+    Verify,
+}
+
+/// Records whether the pins of a connection checked in [`PinMode::AfterHandshake`] matched.
+pub(crate) struct PinState(AtomicBool);
+
+static INDEX: ExIndex<Ssl, PinState> = ExIndex::new();
+
+pub(crate) fn set_spki_pins(
+    builder: &mut SslConnectorBuilder,
+    pins: Vec<[u8; 32]>,
+    mode: PinMode,
+) -> Result<(), ErrorStack> {
+    let index = INDEX.index()?;
+    let ssl_index = X509StoreContext::ssl_idx()?;
+
+    builder.set_verify_callback(SslVerifyMode::PEER, move |preverify_ok, ctx| {
+        // the whole chain has been built and verified once the leaf is reached
+        if !preverify_ok || ctx.error_depth() != 0 {
+            return preverify_ok;
+        }
+        if ctx.chain().map_or(false, |chain| matches(chain, &pins)) {
+            return true;
+        }
+
+        match mode {
+            PinMode::AfterHandshake => {
+                // fail closed for connections which weren't created by `SslStreamConnector`
+                let recorded = ctx
+                    .ex_data(ssl_index)
+                    .and_then(|ssl: &SslRef| ssl.ex_data(index))
+                    .map(|state| state.0.store(false, Ordering::Relaxed))
+                    .is_some();
+                if !recorded {
+                    ctx.set_error(X509VerifyResult::APPLICATION_VERIFICATION);
+                }
+                recorded
+            }
+            PinMode::Verify => {
+                ctx.set_error(X509VerifyResult::APPLICATION_VERIFICATION);
+                false
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Prepares `ssl` to record the outcome of checking its pins.
+pub(crate) fn init(ssl: &mut SslRef) -> Result<(), ErrorStack> {
+    ssl.set_ex_data(INDEX.index()?, PinState(AtomicBool::new(true)));
+    Ok(())
+}
+
+/// Returns `true` if the pins of `ssl` were checked after the handshake and none matched.
+pub(crate) fn rejected(ssl: &SslRef) -> bool {
+    INDEX
+        .get()
+        .and_then(|index| ssl.ex_data(index))
+        .map_or(false, |state| !state.0.load(Ordering::Relaxed))
+}
+
+fn matches(chain: &StackRef<X509>, pins: &[[u8; 32]]) -> bool {
+    chain.iter().any(|cert| {
+        let spki = match cert.public_key().and_then(|key| key.public_key_to_der()) {
+            Ok(spki) => spki,
+            Err(_) => return false,
+        };
+        let hash = sha::sha256(&spki);
+        pins.iter().any(|pin| *pin == hash)
+    })
+}
@@ -0,0 +1,91 @@
+//! [`tower_service::Service`] integration for [`SslStreamConnector`].
+
+use crate::{SslStream, SslStreamConnector};
+use http::Uri;
+use std::error;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower_service::Service;
+
+type BoxError = Box<dyn error::Error + Send + Sync>;
+
+/// The future returned by [`ConnectorService`].
+pub type ConnectorServiceFuture<S> =
+    Pin<Box<dyn Future<Output = Result<SslStream<S>, BoxError>> + Send>>;
+
+/// A [`Service`] which establishes a transport with an inner service, such as hyper's
+/// `HttpConnector`, and then performs a TLS handshake over it with an [`SslStreamConnector`].
+///
+/// The host of the requested [`Uri`] is used for SNI and hostname verification, and its port, if
+/// present, to key [cached sessions](crate::SslStreamConnectorBuilder::set_session_cache).
+///
+/// Requires the `tower` Cargo feature.
+#[derive(Clone)]
+pub struct ConnectorService<C> {
+    inner: C,
+    connector: SslStreamConnector,
+}
+
+impl<C> ConnectorService<C> {
+    /// Creates a new service which performs handshakes with `connector` over the transports
+    /// produced by `inner`.
+    pub fn new(inner: C, connector: SslStreamConnector) -> ConnectorService<C> {
+        ConnectorService { inner, connector }
+    }
+
+    /// Returns a shared reference to the inner service.
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Returns a shared reference to the connector.
+    pub fn connector(&self) -> &SslStreamConnector {
+        &self.connector
+    }
+}
+
+impl<C, S> Service<Uri> for ConnectorService<C>
+where
+    C: Service<Uri, Response = S>,
+    C::Error: Into<BoxError>,
+    C::Future: Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Response = SslStream<S>;
+    type Error = BoxError;
+    type Future = ConnectorServiceFuture<S>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> ConnectorServiceFuture<S> {
+        // IPv6 literals are bracketed in URIs
+        let host = match uri.host() {
+            Some(host) => host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            None => return Box::pin(future::ready(Err("URI has no host".into()))),
+        };
+        let port = uri.port_u16();
+        let connect = self.inner.call(uri);
+        let connector = self.connector.clone();
+
+        Box::pin(async move {
+            let stream = connect.await.map_err(Into::into)?;
+            let stream = match port {
+                Some(port) => connector.connect_with_port(&host, port, stream).await?,
+                None => connector.connect(&host, stream).await?,
+            };
+            Ok(stream)
+        })
+    }
+}
@@ -0,0 +1,155 @@
+#[cfg(ossl111)]
+use openssl::ssl::SslVersion;
+use openssl::ssl::{SslSession, SslSessionRef};
+use std::collections::{HashMap, VecDeque};
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The number of sessions retained per server. TLS 1.3 servers typically issue two tickets per
+/// connection, and each can only be used once.
+const SESSIONS_PER_KEY: usize = 4;
+
+/// The future returned by the methods of [`SessionCache`].
+pub type SessionCacheFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// An external store of client sessions, such as a database shared by several processes.
+///
+/// Sessions are passed to and from the store DER-encoded. A cache is installed with
+/// [`SslStreamConnectorBuilder::set_async_session_cache`](crate::SslStreamConnectorBuilder::set_async_session_cache).
+pub trait SessionCache: Send + Sync {
+    /// Stores a session negotiated with the server identified by `key`.
+    fn put(&self, key: SessionKey, session: Vec<u8>) -> SessionCacheFuture<'_, ()>;
+
+    /// Looks up a session for the server identified by `key`.
+    fn get<'a>(&'a self, key: &'a SessionKey) -> SessionCacheFuture<'a, Option<Vec<u8>>>;
+}
+
+/// Identifies the server a session was negotiated with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionKey {
+    domain: String,
+    port: Option<u16>,
+}
+
+impl SessionKey {
+    /// Creates a new key.
+    pub fn new(domain: &str, port: Option<u16>) -> SessionKey {
+        SessionKey {
+            domain: domain.to_string(),
+            port,
+        }
+    }
+
+    /// Returns the server's domain.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Returns the server's port, if it was provided when connecting.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+/// A [`SessionCache`] which keeps the most recent session for each server in memory.
+///
+/// This is mostly useful as a reference implementation and for testing.
+#[derive(Debug, Default)]
+pub struct MemorySessionCache {
+    sessions: Mutex<HashMap<SessionKey, Vec<u8>>>,
+}
+
+impl MemorySessionCache {
+    /// Creates an empty cache.
+    pub fn new() -> MemorySessionCache {
+        MemorySessionCache::default()
+    }
+}
+
+impl SessionCache for MemorySessionCache {
+    fn put(&self, key: SessionKey, session: Vec<u8>) -> SessionCacheFuture<'_, ()> {
+        self.sessions.lock().unwrap().insert(key, session);
+        Box::pin(future::ready(()))
+    }
+
+    fn get<'a>(&'a self, key: &'a SessionKey) -> SessionCacheFuture<'a, Option<Vec<u8>>> {
+        let session = self.sessions.lock().unwrap().get(key).cloned();
+        Box::pin(future::ready(session))
+    }
+}
+
+/// The in-memory cache used by
+/// [`SslStreamConnectorBuilder::set_session_cache`](crate::SslStreamConnectorBuilder::set_session_cache).
+pub(crate) struct LocalSessionCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<HashMap<SessionKey, VecDeque<(Instant, SslSession)>>>,
+}
+
+impl LocalSessionCache {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> LocalSessionCache {
+        LocalSessionCache {
+            capacity,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn insert(&self, key: &SessionKey, session: SslSession) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(key) && entries.len() >= self.capacity {
+            // evict the server whose newest session is the oldest
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, sessions)| sessions.back().map(|(time, _)| *time))
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        let sessions = entries.entry(key.clone()).or_default();
+        if sessions.len() == SESSIONS_PER_KEY {
+            sessions.pop_front();
+        }
+        sessions.push_back((Instant::now(), session));
+    }
+
+    /// Returns the newest unexpired session for `key`.
+    ///
+    /// Single-use TLS 1.3 tickets are removed from the cache, while older sessions remain
+    /// available to later connections.
+    pub(crate) fn get(&self, key: &SessionKey) -> Option<SslSession> {
+        let mut entries = self.entries.lock().unwrap();
+        let sessions = entries.get_mut(key)?;
+
+        let now = Instant::now();
+        sessions.retain(|(time, _)| now.duration_since(*time) < self.ttl);
+        let session = match sessions.back() {
+            Some((_, session)) if single_use(session) => sessions.pop_back().map(|(_, s)| s),
+            Some((_, session)) => Some(session.clone()),
+            None => None,
+        };
+
+        if sessions.is_empty() {
+            entries.remove(key);
+        }
+        session
+    }
+}
+
+#[cfg(ossl111)]
+fn single_use(session: &SslSessionRef) -> bool {
+    session.protocol_version() == SslVersion::TLS1_3
+}
+
+#[cfg(not(ossl111))]
+fn single_use(_: &SslSessionRef) -> bool {
+    false
+}
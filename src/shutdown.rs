@@ -0,0 +1,130 @@
+use foreign_types::ForeignTypeRef;
+use openssl::ssl::{self, ErrorCode, SslRef};
+use std::io;
+use std::os::raw::c_int;
+use std::task::Poll;
+
+/// The number of `SSL_read` calls made per poll while waiting for the peer's close notify.
+pub(crate) const SHUTDOWN_DRAIN_ITERATIONS_PER_POLL: usize = 16;
+
+const DEFAULT_SHUTDOWN_DRAIN_LIMIT: usize = 16 * 1024;
+
+const DEFAULT_SHUTDOWN_READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// How [`poll_shutdown`](tokio::io::AsyncWrite::poll_shutdown) closes the TLS session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// Send a close notify alert and return without waiting for the peer's.
+    SendOnly,
+    /// Send a close notify alert and wait for the peer's, discarding any application data
+    /// received in the meantime, up to the
+    /// [drain limit](crate::SslStream::set_shutdown_drain_limit).
+    Bidirectional,
+}
+
+/// Shutdown configuration and progress of an [`SslStream`](crate::SslStream).
+#[derive(Debug)]
+pub(crate) struct Shutdown {
+    pub(crate) state: ShutdownState,
+    pub(crate) mode: ShutdownMode,
+    pub(crate) transport: bool,
+    pub(crate) drain_limit: usize,
+    pub(crate) drained: usize,
+    pub(crate) read_buf_size: usize,
+    /// Allocated when the drain phase starts and freed once shutdown finishes.
+    pub(crate) read_buf: Option<Box<[u8]>>,
+    pub(crate) capture: bool,
+    pub(crate) captured: Vec<u8>,
+}
+
+impl Shutdown {
+    pub(crate) fn new() -> Shutdown {
+        Shutdown {
+            state: ShutdownState::NotStarted,
+            mode: ShutdownMode::Bidirectional,
+            transport: false,
+            drain_limit: DEFAULT_SHUTDOWN_DRAIN_LIMIT,
+            drained: 0,
+            read_buf_size: DEFAULT_SHUTDOWN_READ_BUFFER_SIZE,
+            read_buf: None,
+            capture: false,
+            captured: Vec::new(),
+        }
+    }
+
+    /// Takes the drain buffer, allocating it if necessary.
+    ///
+    /// The buffer should be handed back with `put_read_buf` if the drain phase is still pending.
+    pub(crate) fn take_read_buf(&mut self) -> Box<[u8]> {
+        match self.read_buf.take() {
+            Some(buf) => buf,
+            None => vec![0; self.read_buf_size].into_boxed_slice(),
+        }
+    }
+
+    pub(crate) fn put_read_buf(&mut self, buf: Box<[u8]>) {
+        if buf.len() == self.read_buf_size {
+            self.read_buf = Some(buf);
+        }
+    }
+}
+
+/// The progress of `poll_shutdown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ShutdownState {
+    NotStarted,
+    /// Our close notify has been written and the transport is being flushed. `received` is set if
+    /// the peer's close notify had already arrived.
+    FlushingCloseNotify {
+        received: bool,
+    },
+    SentCloseNotify,
+    /// The TLS session is closed and the underlying stream is being shut down.
+    ShuttingDownTransport,
+    Done,
+    Failed(io::ErrorKind),
+}
+
+/// A private enum that should not be used
+pub(crate) enum _ShouldKeepPollSslRead {
+    KeepPollSslRead(usize),
+    Finished,
+}
+
+/// Convert SSL_read() result to Poll<T> enum when we are doing SSL_shutdown()
+pub(crate) fn cvt_shutdown_ssl_read_ossl(
+    r: Poll<Result<usize, ssl::Error>>,
+) -> Poll<Result<_ShouldKeepPollSslRead, ssl::Error>> {
+    match r {
+        Poll::Ready(r) => match r {
+            Ok(n) => {
+                // We read some data, we should keep reading until we got ZERO_RETURN
+                Poll::Ready(Ok(_ShouldKeepPollSslRead::KeepPollSslRead(n)))
+            }
+            Err(e) => match e.code() {
+                ErrorCode::WANT_READ | ErrorCode::WANT_WRITE => Poll::Pending,
+                ErrorCode::ZERO_RETURN => Poll::Ready(Ok(_ShouldKeepPollSslRead::Finished)),
+                // other side closed underlying socket without sending the close notify
+                // we assume it is okay
+                ErrorCode::SYSCALL if e.io_error().is_none() => {
+                    Poll::Ready(Ok(_ShouldKeepPollSslRead::Finished))
+                }
+                _ => Poll::Ready(Err(e)),
+            },
+        },
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+extern "C" {
+    fn SSL_set_quiet_shutdown(ssl: *mut openssl_sys::SSL, mode: c_int);
+    fn SSL_get_quiet_shutdown(ssl: *const openssl_sys::SSL) -> c_int;
+}
+
+pub(crate) fn set_quiet_shutdown(ssl: &mut SslRef, quiet: bool) {
+    unsafe { SSL_set_quiet_shutdown(ssl.as_ptr(), quiet as c_int) }
+}
+
+pub(crate) fn quiet_shutdown(ssl: &SslRef) -> bool {
+    unsafe { SSL_get_quiet_shutdown(ssl.as_ptr()) != 0 }
+}
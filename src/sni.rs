@@ -0,0 +1,169 @@
+//! Selection of a server certificate from a fixed set based on the server name indication.
+
+use crate::{Error, SslStream, SslStreamAcceptor};
+use openssl::error::ErrorStack;
+use openssl::pkey::{PKeyRef, Private};
+use openssl::ssl::{
+    NameType, SniError, SslAcceptor, SslAcceptorBuilder, SslAlert, SslContext, SslMethod,
+};
+use openssl::x509::X509;
+use std::collections::HashMap;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+type ConfigureCallback =
+    dyn Fn(&str, &mut SslAcceptorBuilder) -> Result<(), ErrorStack> + Send + Sync;
+
+/// A builder for [`SniAcceptor`]s.
+pub struct SniAcceptorBuilder {
+    builder: SslAcceptorBuilder,
+    configure: Option<Box<ConfigureCallback>>,
+    contexts: HashMap<String, SslContext>,
+    default: Option<SslContext>,
+    reject_unknown_names: bool,
+}
+
+impl SniAcceptorBuilder {
+    /// Adds the certificate chain and private key served to clients requesting `name`.
+    ///
+    /// `chain` starts with the leaf certificate, followed by any intermediates. Names are matched
+    /// exactly, but case-insensitively and ignoring a trailing dot. Adding a name again replaces
+    /// its certificate.
+    ///
+    /// The name's context is configured by the
+    /// [configure callback](Self::set_configure_callback), if one has been set.
+    pub fn add_certificate(
+        &mut self,
+        name: &str,
+        chain: &[X509],
+        key: &PKeyRef<Private>,
+    ) -> Result<(), ErrorStack> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+        let mut certs = chain.iter();
+        if let Some(leaf) = certs.next() {
+            builder.set_certificate(leaf)?;
+        }
+        for cert in certs {
+            builder.add_extra_chain_cert(cert.clone())?;
+        }
+        builder.set_private_key(key)?;
+        builder.check_private_key()?;
+        if let Some(configure) = &self.configure {
+            configure(name, &mut builder)?;
+        }
+
+        self.contexts
+            .insert(normalize(name), builder.build().into_context());
+        Ok(())
+    }
+
+    /// Sets a callback which configures the context of each name added by
+    /// [`add_certificate`](Self::add_certificate) after this call.
+    ///
+    /// Once a name has been matched, OpenSSL looks up the verification settings and callbacks
+    /// such as ALPN selection, OCSP stapling and session ticket keys through that name's context
+    /// rather than the wrapped builder's, so they have to be configured here as well.
+    pub fn set_configure_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&str, &mut SslAcceptorBuilder) -> Result<(), ErrorStack> + 'static + Send + Sync,
+    {
+        self.configure = Some(Box::new(callback));
+    }
+
+    /// Sets the context whose certificate is served to clients which don't send a host name, or
+    /// which send one that wasn't added.
+    ///
+    /// If no default is set, those handshakes fail with an `unrecognized_name` alert.
+    pub fn set_default_context(&mut self, context: SslContext) {
+        self.default = Some(context);
+    }
+
+    /// If set, handshakes requesting a host name which wasn't added fail with an
+    /// `unrecognized_name` alert rather than using the default context.
+    ///
+    /// Clients which don't send a host name still use the default context. Defaults to `false`.
+    pub fn set_reject_unknown_names(&mut self, reject: bool) {
+        self.reject_unknown_names = reject;
+    }
+
+    /// Consumes the builder, returning an [`SniAcceptor`].
+    ///
+    /// This replaces any servername callback previously configured on the wrapped
+    /// [`SslAcceptorBuilder`].
+    pub fn build(self) -> SniAcceptor {
+        let SniAcceptorBuilder {
+            mut builder,
+            configure: _,
+            contexts,
+            default,
+            reject_unknown_names,
+        } = self;
+
+        builder.set_servername_callback(move |ssl, alert| {
+            let name = ssl.servername(NameType::HOST_NAME).map(normalize);
+            let context = match name.as_ref().and_then(|name| contexts.get(name)) {
+                Some(context) => Some(context),
+                None if name.is_some() && reject_unknown_names => None,
+                None => default.as_ref(),
+            };
+            match context {
+                Some(context) => {
+                    crate::switch_context(ssl, context).map_err(|_| SniError::ALERT_FATAL)
+                }
+                None => {
+                    *alert = SslAlert::UNRECOGNIZED_NAME;
+                    Err(SniError::ALERT_FATAL)
+                }
+            }
+        });
+
+        SniAcceptor {
+            acceptor: SslStreamAcceptor::from_acceptor(builder.build()),
+        }
+    }
+}
+
+/// An acceptor which serves one of a fixed set of certificates, selected by the host name the
+/// client sends in the server name indication extension.
+///
+/// For certificates which are only known once a client connects, see
+/// [`SslStreamAcceptorBuilder::set_async_sni_callback`](crate::SslStreamAcceptorBuilder::set_async_sni_callback).
+#[derive(Clone)]
+pub struct SniAcceptor {
+    acceptor: SslStreamAcceptor,
+}
+
+impl SniAcceptor {
+    /// Creates a new builder wrapping an [`SslAcceptorBuilder`].
+    ///
+    /// The wrapped builder's protocol versions, cipher suites and options apply to every
+    /// connection. The certificate, verification settings and callbacks such as ALPN selection
+    /// are switched along with the context chosen for the host name; see
+    /// [`SniAcceptorBuilder::set_configure_callback`].
+    pub fn builder(builder: SslAcceptorBuilder) -> SniAcceptorBuilder {
+        SniAcceptorBuilder {
+            builder,
+            configure: None,
+            contexts: HashMap::new(),
+            default: None,
+            reject_unknown_names: false,
+        }
+    }
+
+    /// Performs a server-side TLS handshake over `stream`.
+    pub async fn accept<S>(&self, stream: S) -> Result<SslStream<S>, Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.acceptor.accept(stream).await
+    }
+}
+
+impl From<SniAcceptor> for SslStreamAcceptor {
+    fn from(acceptor: SniAcceptor) -> SslStreamAcceptor {
+        acceptor.acceptor
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase()
+}
@@ -0,0 +1,115 @@
+use crate::SslStream;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// An [`SslStream`] behind a mutex, which can be read from and written to through a shared
+/// reference.
+///
+/// `AsyncRead` and `AsyncWrite` are implemented for `&SyncSslStream<S>`, so an
+/// `Arc<SyncSslStream<S>>` can be shared between a task which reads and a task which writes.
+/// The lock is only held for the duration of each poll.
+///
+/// Each poll registers the waker of the calling task with the underlying stream. If a read needs
+/// to write to the underlying stream, as happens during renegotiation or key updates, or vice
+/// versa, the waker of a concurrent operation in the other direction may be replaced, so readers
+/// and writers should not rely on being woken for progress in the opposite direction.
+#[derive(Debug)]
+pub struct SyncSslStream<S>(Mutex<SslStream<S>>);
+
+impl<S> SyncSslStream<S> {
+    /// Wraps a stream.
+    pub fn new(stream: SslStream<S>) -> SyncSslStream<S> {
+        SyncSslStream(Mutex::new(stream))
+    }
+
+    /// Locks the wrapped stream.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a previous poll of the stream panicked while holding the lock.
+    pub fn lock(&self) -> MutexGuard<'_, SslStream<S>> {
+        self.0.lock().expect("SslStream mutex poisoned")
+    }
+
+    /// Consumes the wrapper, returning the wrapped stream.
+    pub fn into_inner(self) -> SslStream<S> {
+        self.0.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn try_lock(&self) -> io::Result<MutexGuard<'_, SslStream<S>>> {
+        self.0
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "SslStream mutex poisoned"))
+    }
+}
+
+impl<S> AsyncRead for &SyncSslStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.try_lock()?).poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for &SyncSslStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.try_lock()?).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.try_lock()?).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.try_lock()?).poll_shutdown(cx)
+    }
+}
+
+impl<S> AsyncRead for SyncSslStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for SyncSslStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_shutdown(cx)
+    }
+}
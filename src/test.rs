@@ -1,10 +1,37 @@
-use crate::SslStream;
-use futures_util::future;
-use openssl::ssl::{Ssl, SslAcceptor, SslConnector, SslFiletype, SslMethod};
+use crate::test_utils::{
+    self, connect_pair, connected_faulty_stream_pair, connected_stream_pair, ssl_stream_pair,
+    tls_stream_pair, tls_stream_pair_with, FaultyStream, WriteFault,
+};
+use crate::{
+    AlpnDispatcher, BufferedSslStream, ConnectorSession, HandshakeMode, HookedSslStream, IoHook,
+    OcspStapler, OcspStaplingPolicy, PinMode, ShutdownMode, SniAcceptor, SslStream,
+    SslStreamAcceptor, SslStreamConnector, SslStreamExt, StreamWrapper, SyncSslStream,
+    TicketKeyManager, VerifyFuture,
+};
+use futures_util::{future, FutureExt, SinkExt, StreamExt};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::sha;
+use openssl::ssl::{
+    self, AlpnError, ErrorCode, ShutdownResult, Ssl, SslAcceptor, SslConnector, SslFiletype,
+    SslMethod, SslSessionCacheMode, SslVerifyMode, StatusType,
+};
+use openssl::x509::{X509VerifyResult, X509};
+use std::io::{self, IoSlice};
 use std::net::ToSocketAddrs;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
-use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, DuplexStream, ReadBuf,
+};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio_util::codec::{Framed, LinesCodec};
 
 #[tokio::test]
 async fn google() {
@@ -36,22 +63,10 @@ async fn google() {
 
 #[tokio::test]
 async fn server_shutdown_both_client_and_server() {
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
+    let (client, server) = tls_stream_pair();
 
     let server = async move {
-        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        acceptor
-            .set_private_key_file("tests/key.pem", SslFiletype::PEM)
-            .unwrap();
-        acceptor
-            .set_certificate_chain_file("tests/cert.pem")
-            .unwrap();
-        let acceptor = acceptor.build();
-
-        let ssl = Ssl::new(acceptor.context()).unwrap();
-        let stream = listener.accept().await.unwrap().0;
-        let mut stream = SslStream::new(ssl, stream).unwrap();
+        let mut stream = server;
 
         Pin::new(&mut stream).accept().await.unwrap();
 
@@ -66,18 +81,8 @@ async fn server_shutdown_both_client_and_server() {
             .unwrap()
     };
 
-    let client = async {
-        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
-        connector.set_ca_file("tests/cert.pem").unwrap();
-        let ssl = connector
-            .build()
-            .configure()
-            .unwrap()
-            .into_ssl("localhost")
-            .unwrap();
-
-        let stream = TcpStream::connect(&addr).await.unwrap();
-        let mut stream = SslStream::new(ssl, stream).unwrap();
+    let client = async move {
+        let mut stream = client;
 
         Pin::new(&mut stream).connect().await.unwrap();
 
@@ -96,22 +101,10 @@ async fn server_shutdown_both_client_and_server() {
 
 #[tokio::test]
 async fn server_shutdown_client_only() {
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
+    let (client, server) = tls_stream_pair();
 
     let server = async move {
-        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        acceptor
-            .set_private_key_file("tests/key.pem", SslFiletype::PEM)
-            .unwrap();
-        acceptor
-            .set_certificate_chain_file("tests/cert.pem")
-            .unwrap();
-        let acceptor = acceptor.build();
-
-        let ssl = Ssl::new(acceptor.context()).unwrap();
-        let stream = listener.accept().await.unwrap().0;
-        let mut stream = SslStream::new(ssl, stream).unwrap();
+        let mut stream = server;
 
         Pin::new(&mut stream).accept().await.unwrap();
 
@@ -120,28 +113,27 @@ async fn server_shutdown_client_only() {
         assert_eq!(&buf, b"asdf");
 
         stream.write_all(b"jkl;").await.unwrap();
-    };
 
-    let client = async {
-        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
-        connector.set_ca_file("tests/cert.pem").unwrap();
-        let ssl = connector
-            .build()
-            .configure()
-            .unwrap()
-            .into_ssl("localhost")
-            .unwrap();
+        // close the transport without sending a close notify, keeping the stream alive until the
+        // client's close notify has been written to it
+        stream.get_mut().shutdown().await.unwrap();
+        stream
+    };
 
-        let stream = TcpStream::connect(&addr).await.unwrap();
-        let mut stream = SslStream::new(ssl, stream).unwrap();
+    let client = async move {
+        let mut stream = client;
 
         Pin::new(&mut stream).connect().await.unwrap();
 
         stream.write_all(b"asdf").await.unwrap();
 
-        let mut buf = vec![];
-        stream.read_to_end(&mut buf).await.unwrap();
-        assert_eq!(buf, b"jkl;");
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"jkl;");
+
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
         future::poll_fn(|ctx| Pin::new(&mut stream).poll_shutdown(ctx))
             .await
             .unwrap()
@@ -152,22 +144,10 @@ async fn server_shutdown_client_only() {
 
 #[tokio::test]
 async fn server_shutdown_server_only() {
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
+    let (client, server) = tls_stream_pair();
 
     let server = async move {
-        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        acceptor
-            .set_private_key_file("tests/key.pem", SslFiletype::PEM)
-            .unwrap();
-        acceptor
-            .set_certificate_chain_file("tests/cert.pem")
-            .unwrap();
-        let acceptor = acceptor.build();
-
-        let ssl = Ssl::new(acceptor.context()).unwrap();
-        let stream = listener.accept().await.unwrap().0;
-        let mut stream = SslStream::new(ssl, stream).unwrap();
+        let mut stream = server;
 
         Pin::new(&mut stream).accept().await.unwrap();
 
@@ -182,18 +162,8 @@ async fn server_shutdown_server_only() {
             .unwrap()
     };
 
-    let client = async {
-        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
-        connector.set_ca_file("tests/cert.pem").unwrap();
-        let ssl = connector
-            .build()
-            .configure()
-            .unwrap()
-            .into_ssl("localhost")
-            .unwrap();
-
-        let stream = TcpStream::connect(&addr).await.unwrap();
-        let mut stream = SslStream::new(ssl, stream).unwrap();
+    let client = async move {
+        let mut stream = client;
 
         Pin::new(&mut stream).connect().await.unwrap();
 
@@ -209,22 +179,10 @@ async fn server_shutdown_server_only() {
 
 #[tokio::test]
 async fn server_shutdown_none() {
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
+    let (client, server) = tls_stream_pair();
 
     let server = async move {
-        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        acceptor
-            .set_private_key_file("tests/key.pem", SslFiletype::PEM)
-            .unwrap();
-        acceptor
-            .set_certificate_chain_file("tests/cert.pem")
-            .unwrap();
-        let acceptor = acceptor.build();
-
-        let ssl = Ssl::new(acceptor.context()).unwrap();
-        let stream = listener.accept().await.unwrap().0;
-        let mut stream = SslStream::new(ssl, stream).unwrap();
+        let mut stream = server;
 
         Pin::new(&mut stream).accept().await.unwrap();
 
@@ -235,23 +193,42 @@ async fn server_shutdown_none() {
         stream.write_all(b"jkl;").await.unwrap();
     };
 
-    let client = async {
-        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
-        connector.set_ca_file("tests/cert.pem").unwrap();
-        let ssl = connector
-            .build()
-            .configure()
-            .unwrap()
-            .into_ssl("localhost")
-            .unwrap();
-
-        let stream = TcpStream::connect(&addr).await.unwrap();
-        let mut stream = SslStream::new(ssl, stream).unwrap();
+    let client = async move {
+        let mut stream = client;
 
         Pin::new(&mut stream).connect().await.unwrap();
 
         stream.write_all(b"asdf").await.unwrap();
 
+        // the server drops the connection without a close notify
+        let mut buf = vec![];
+        let err = stream.read_to_end(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(buf, b"jkl;");
+    };
+
+    future::join(server, client).await;
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn allow_unclean_eof() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+        stream.write_all(b"jkl;").await.unwrap();
+    };
+
+    let client = async move {
+        let mut stream = client;
+        assert!(!stream.allow_unclean_eof());
+        stream.set_allow_unclean_eof(true);
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
         let mut buf = vec![];
         stream.read_to_end(&mut buf).await.unwrap();
         assert_eq!(buf, b"jkl;");
@@ -259,3 +236,3124 @@ async fn server_shutdown_none() {
 
     future::join(server, client).await;
 }
+
+#[tokio::test]
+async fn allow_unclean_eof_handshake() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        // read the start of the client hello, then hang up
+        let mut buf = [0; 5];
+        stream.get_mut().read_exact(&mut buf).await.unwrap();
+    };
+
+    let client = async move {
+        let mut stream = client;
+        stream.set_allow_unclean_eof(true);
+
+        Pin::new(&mut stream).connect().await.unwrap_err();
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn with_ssl() {
+    let (mut client, mut server) = tls_stream_pair();
+
+    client.with_ssl(|ssl| ssl.set_verify(SslVerifyMode::NONE));
+    let hostname = client.with_ssl(|ssl| {
+        ssl.set_hostname("example.com").unwrap();
+        ssl.servername(ssl::NameType::HOST_NAME).map(str::to_string)
+    });
+    assert_eq!(hostname.as_deref(), Some("example.com"));
+
+    // verification would fail for the new hostname if it were still enabled
+    connect_pair(&mut client, &mut server).await;
+    assert_eq!(server.server_name(), Some("example.com"));
+}
+
+#[tokio::test]
+async fn session_resumption() {
+    let acceptor = test_utils::acceptor().build();
+    let session = Arc::new(Mutex::new(None));
+    let mut connector = test_utils::connector();
+    connector.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+    connector.set_new_session_callback({
+        let session = session.clone();
+        move |_, s| *session.lock().unwrap() = Some(s)
+    });
+    let connector = connector.build();
+
+    let (mut client, mut server) = tls_stream_pair_with(&connector, &acceptor);
+    connect_pair(&mut client, &mut server).await;
+    assert!(!client.session_reused());
+
+    // TLS 1.3 session tickets are sent after the handshake and only processed by a read
+    #[cfg(ossl111)]
+    assert!(session.lock().unwrap().is_none());
+    server.write_all(b"asdf").await.unwrap();
+    let mut buf = [0; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    let session = session.lock().unwrap().take().unwrap();
+
+    let (mut client, mut server) = tls_stream_pair_with(&connector, &acceptor);
+    unsafe { client.ssl_mut().set_session(&session).unwrap() };
+    connect_pair(&mut client, &mut server).await;
+    assert!(client.session_reused());
+    assert!(server.session_reused());
+}
+
+#[tokio::test]
+async fn connect_with_session() {
+    let acceptor = test_utils::acceptor().build();
+    let session = Arc::new(Mutex::new(None));
+    let mut connector = test_utils::connector();
+    connector
+        .set_max_proto_version(Some(ssl::SslVersion::TLS1_2))
+        .unwrap();
+    connector.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+    connector.set_new_session_callback({
+        let session = session.clone();
+        move |_, s| *session.lock().unwrap() = Some(s)
+    });
+    let connector = connector.build();
+
+    let (mut client, mut server) = tls_stream_pair_with(&connector, &acceptor);
+    let (r1, r2) = future::join(
+        unsafe { Pin::new(&mut client).connect_with_session(None) },
+        Pin::new(&mut server).accept(),
+    )
+    .await;
+    r1.unwrap();
+    r2.unwrap();
+    assert!(!client.session_reused());
+
+    // TLS 1.2 sessions are available as soon as the handshake completes
+    let session = session.lock().unwrap().take().unwrap();
+
+    let (mut client, mut server) = tls_stream_pair_with(&connector, &acceptor);
+    let (r1, r2) = future::join(
+        unsafe { Pin::new(&mut client).connect_with_session(Some(&session)) },
+        Pin::new(&mut server).accept(),
+    )
+    .await;
+    r1.unwrap();
+    r2.unwrap();
+    assert!(client.session_reused());
+    assert!(server.session_reused());
+}
+
+#[tokio::test]
+async fn spki_pins() {
+    async fn connect(
+        domain: &str,
+        pins: Vec<[u8; 32]>,
+        mode: PinMode,
+    ) -> Result<SslStream<DuplexStream>, crate::Error> {
+        let acceptor = SslStreamAcceptor::from_acceptor(test_utils::acceptor().build());
+        let mut connector = SslStreamConnector::builder(test_utils::connector());
+        connector.set_spki_pins(pins, mode).unwrap();
+        let connector = connector.build();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, _) =
+            future::join(connector.connect(domain, client), acceptor.accept(server)).await;
+        client
+    }
+
+    let cert = X509::from_pem(&std::fs::read("tests/cert.pem").unwrap()).unwrap();
+    let pin = sha::sha256(&cert.public_key().unwrap().public_key_to_der().unwrap());
+    let other = [0; 32];
+
+    for mode in [PinMode::AfterHandshake, PinMode::Verify] {
+        connect("localhost", vec![other, pin], mode).await.unwrap();
+
+        // a matching pin doesn't override the usual verification
+        match connect("example.com", vec![pin], mode).await {
+            Err(crate::Error::Handshake(e)) => {
+                assert_eq!(
+                    e.verify_result().as_raw(),
+                    openssl_sys::X509_V_ERR_HOSTNAME_MISMATCH
+                )
+            }
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    match connect("localhost", vec![other], PinMode::AfterHandshake).await {
+        Err(crate::Error::PinMismatch) => {}
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+    match connect("localhost", vec![other], PinMode::Verify).await {
+        Err(crate::Error::Handshake(e)) => assert_eq!(
+            e.verify_result(),
+            X509VerifyResult::APPLICATION_VERIFICATION
+        ),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn ocsp_stapler() {
+    let response = include_bytes!("../tests/ocsp.der");
+    let cert = X509::from_pem(include_bytes!("../tests/cert.pem")).unwrap();
+
+    // the first fetch succeeds, and later ones fail
+    let fetched = Arc::new(AtomicBool::new(false));
+    let stapler = OcspStapler::new(cert.clone(), cert, {
+        let fetched = fetched.clone();
+        move || {
+            let first = !fetched.swap(true, Ordering::SeqCst);
+            Box::pin(async move {
+                if first {
+                    Ok(response.to_vec())
+                } else {
+                    Err("responder unavailable".into())
+                }
+            })
+        }
+    });
+    assert_eq!(stapler.response(), None);
+    stapler.refresh().await.unwrap();
+    stapler.refresh().await.unwrap_err();
+    // the previous response is still stapled
+    assert_eq!(stapler.response().as_deref(), Some(&response[..]));
+
+    let mut acceptor = test_utils::acceptor();
+    stapler.install(&mut acceptor).unwrap();
+    let server = Ssl::new(acceptor.build().context()).unwrap();
+
+    let mut client = test_utils::connector()
+        .build()
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    client.set_status_type(StatusType::OCSP).unwrap();
+
+    let (mut client, mut server) = ssl_stream_pair(client, server);
+    connect_pair(&mut client, &mut server).await;
+
+    assert_eq!(client.ssl().ocsp_status(), Some(&response[..]));
+}
+
+#[tokio::test]
+async fn ocsp_stapling_policy() {
+    async fn connect(
+        staple: Option<&'static [u8]>,
+        policy: OcspStaplingPolicy,
+    ) -> Result<SslStream<DuplexStream>, crate::Error> {
+        let mut acceptor = test_utils::acceptor();
+        if let Some(staple) = staple {
+            let cert = X509::from_pem(include_bytes!("../tests/cert.pem")).unwrap();
+            let stapler = OcspStapler::new(cert.clone(), cert, move || {
+                Box::pin(async move { Ok(staple.to_vec()) })
+            });
+            stapler.refresh().await.unwrap();
+            stapler.install(&mut acceptor).unwrap();
+        }
+        let acceptor = SslStreamAcceptor::from_acceptor(acceptor.build());
+
+        let mut connector = SslStreamConnector::builder(test_utils::connector());
+        connector.set_ocsp_stapling(policy).unwrap();
+        let connector = connector.build();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, _) = future::join(
+            connector.connect("localhost", client),
+            acceptor.accept(server),
+        )
+        .await;
+        client
+    }
+
+    let good = &include_bytes!("../tests/ocsp.der")[..];
+    let revoked = &include_bytes!("../tests/ocsp-revoked.der")[..];
+
+    let stream = connect(Some(good), OcspStaplingPolicy::Require)
+        .await
+        .unwrap();
+    assert_eq!(stream.ssl().ocsp_status(), Some(good));
+    connect(None, OcspStaplingPolicy::IfPresent).await.unwrap();
+
+    match connect(Some(revoked), OcspStaplingPolicy::IfPresent).await {
+        Err(crate::Error::Ocsp(e)) => assert!(e.contains("revoked"), "{}", e),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+    match connect(None, OcspStaplingPolicy::Require).await {
+        Err(crate::Error::Ocsp(e)) => assert!(e.contains("did not staple"), "{}", e),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn ticket_key_rotation() {
+    let manager = TicketKeyManager::new().unwrap();
+    let mut acceptor = test_utils::acceptor();
+    manager.install(&mut acceptor).unwrap();
+    let acceptor = SslStreamAcceptor::from_acceptor(acceptor.build());
+    let connector = SslStreamConnector::from_connector(test_utils::connector().build());
+
+    let connect = |session: Option<ConnectorSession>| {
+        let acceptor = acceptor.clone();
+        let connector = connector.clone();
+        async move {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let (client, server) = match &session {
+                Some(session) => {
+                    future::join(
+                        connector.connect_with_session("localhost", client, session),
+                        acceptor.accept(server),
+                    )
+                    .await
+                }
+                None => {
+                    future::join(
+                        connector.connect("localhost", client),
+                        acceptor.accept(server),
+                    )
+                    .await
+                }
+            };
+            let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+            // read the session tickets sent after the handshake
+            server.write_all(b"asdf").await.unwrap();
+            let mut buf = [0; 4];
+            client.read_exact(&mut buf).await.unwrap();
+            (client.session_reused(), connector.session(&client).unwrap())
+        }
+    };
+
+    let (reused, session) = connect(None).await;
+    assert!(!reused);
+
+    // tickets encrypted with the previous key are still accepted
+    manager.rotate().unwrap();
+    let (reused, _) = connect(Some(session.clone())).await;
+    assert!(reused);
+
+    manager.rotate().unwrap();
+    let (reused, _) = connect(Some(session)).await;
+    assert!(!reused);
+}
+
+#[tokio::test]
+async fn connector_session_resumption() {
+    let acceptor = SslStreamAcceptor::from_acceptor(test_utils::acceptor().build());
+    let connector = SslStreamConnector::from_connector(test_utils::connector().build());
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let (client, server) = future::join(
+        connector.connect("localhost", client),
+        acceptor.accept(server),
+    )
+    .await;
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+    assert!(!client.session_reused());
+
+    // read the session tickets sent after the handshake
+    server.write_all(b"asdf").await.unwrap();
+    let mut buf = [0; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    let session = connector.session(&client).unwrap();
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let (client, server) = future::join(
+        connector.connect_with_session("localhost", client, &session),
+        acceptor.accept(server),
+    )
+    .await;
+    assert!(client.unwrap().session_reused());
+    assert!(server.unwrap().session_reused());
+
+    // sessions are tied to the connector which negotiated them
+    let other = SslStreamConnector::from_connector(test_utils::connector().build());
+    let (client, _server) = tokio::io::duplex(64 * 1024);
+    let e = other
+        .connect_with_session("localhost", client, &session)
+        .await
+        .unwrap_err();
+    assert_eq!(io::Error::from(e).kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn connector_session_cache() {
+    let acceptor = SslStreamAcceptor::from_acceptor(test_utils::acceptor().build());
+    let mut connector = SslStreamConnector::builder(test_utils::connector());
+    connector
+        .set_session_cache(16, Duration::from_secs(60))
+        .unwrap();
+    let connector = connector.build();
+
+    for &reused in &[false, true, true] {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = future::join(
+            connector.connect("localhost", client),
+            acceptor.accept(server),
+        )
+        .await;
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        assert_eq!(client.session_reused(), reused);
+
+        // read the session tickets sent after the handshake
+        server.write_all(b"asdf").await.unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).await.unwrap();
+    }
+
+    // sessions aren't shared between ports
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let (client, server) = future::join(
+        connector.connect_with_port("localhost", 443, client),
+        acceptor.accept(server),
+    )
+    .await;
+    server.unwrap();
+    assert!(!client.unwrap().session_reused());
+}
+
+#[cfg(feature = "rt")]
+#[tokio::test]
+async fn async_session_cache() {
+    use crate::{MemorySessionCache, SessionCache, SessionKey};
+
+    async fn connect(
+        cache: Arc<MemorySessionCache>,
+        acceptor: &SslStreamAcceptor,
+    ) -> SslStream<DuplexStream> {
+        let mut connector = SslStreamConnector::builder(test_utils::connector());
+        connector.set_async_session_cache(cache).unwrap();
+        let connector = connector.build();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = future::join(
+            connector.connect_with_port("localhost", 443, client),
+            acceptor.accept(server),
+        )
+        .await;
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        // read the session tickets sent after the handshake
+        server.write_all(b"asdf").await.unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        client
+    }
+
+    let acceptor = SslStreamAcceptor::from_acceptor(test_utils::acceptor().build());
+    let key = SessionKey::new("localhost", Some(443));
+
+    // the first "process" negotiates a session, which is stored in the background
+    let first = Arc::new(MemorySessionCache::new());
+    assert!(!connect(first.clone(), &acceptor).await.session_reused());
+    let mut session = None;
+    for _ in 0..100 {
+        session = first.get(&key).await;
+        if session.is_some() {
+            break;
+        }
+        tokio::task::yield_now().await;
+    }
+
+    // the second only shares the serialized session
+    let second = Arc::new(MemorySessionCache::new());
+    second.put(key, session.unwrap()).await;
+    assert!(connect(second, &acceptor).await.session_reused());
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn export_keying_material() {
+    let (client, server) = connected_stream_pair().await;
+
+    let a = client
+        .export_keying_material(32, "EXPORTER-test", Some(b"context"))
+        .unwrap();
+    let b = server
+        .export_keying_material(32, "EXPORTER-test", Some(b"context"))
+        .unwrap();
+    assert_eq!(a.len(), 32);
+    assert_eq!(a, b);
+
+    let c = client
+        .export_keying_material(32, "EXPORTER-other", Some(b"context"))
+        .unwrap();
+    assert_ne!(a, c);
+
+    // the material is only available once the handshake completes
+    let (client, _server) = tls_stream_pair();
+    assert!(client
+        .export_keying_material(32, "EXPORTER-test", None)
+        .is_err());
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn channel_binding() {
+    use crate::ChannelBinding;
+
+    let (client, server) = connected_stream_pair().await;
+    let a = client.channel_binding(ChannelBinding::TlsExporter).unwrap();
+    let b = server.channel_binding(ChannelBinding::TlsExporter).unwrap();
+    assert_eq!(a.len(), 32);
+    assert_eq!(a, b);
+
+    let e = client
+        .channel_binding(ChannelBinding::TlsUnique)
+        .unwrap_err();
+    assert_eq!(io::Error::from(e).kind(), io::ErrorKind::InvalidInput);
+
+    let mut connector = test_utils::connector();
+    connector
+        .set_max_proto_version(Some(ssl::SslVersion::TLS1_2))
+        .unwrap();
+    let (mut client, mut server) =
+        tls_stream_pair_with(&connector.build(), &test_utils::acceptor().build());
+    connect_pair(&mut client, &mut server).await;
+
+    let a = client.channel_binding(ChannelBinding::TlsUnique).unwrap();
+    let b = server.channel_binding(ChannelBinding::TlsUnique).unwrap();
+    assert!(!a.is_empty());
+    assert_eq!(a, b);
+    assert_eq!(
+        client.channel_binding(ChannelBinding::TlsExporter).unwrap(),
+        server.channel_binding(ChannelBinding::TlsExporter).unwrap(),
+    );
+}
+
+#[tokio::test]
+async fn early_data() {
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor
+        .set_private_key_file("tests/key.pem", SslFiletype::PEM)
+        .unwrap();
+    acceptor
+        .set_certificate_chain_file("tests/cert.pem")
+        .unwrap();
+    acceptor.set_max_early_data(1024).unwrap();
+    let acceptor = acceptor.build();
+
+    let session = Arc::new(Mutex::new(None));
+    let mut connector = test_utils::connector();
+    connector.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+    connector.set_new_session_callback({
+        let session = session.clone();
+        move |_, s| *session.lock().unwrap() = Some(s)
+    });
+    let connector = connector.build();
+
+    // the first connection only hands out a session ticket
+    let (client, server) = tls_stream_pair_with(&connector, &acceptor);
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+        stream.write_all(b"jkl;").await.unwrap();
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        // reading processes the session tickets sent after the handshake
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"jkl;");
+    };
+
+    future::join(server, client).await;
+
+    let session = session.lock().unwrap().take().unwrap();
+    assert!(session.max_early_data() > 0);
+
+    let mut ssl = connector
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    unsafe { ssl.set_session(&session).unwrap() };
+    let (client, server) = ssl_stream_pair(ssl, Ssl::new(acceptor.context()).unwrap());
+
+    let server = async move {
+        let mut stream = server;
+
+        let mut early = vec![];
+        let mut buf = [0; 4];
+        loop {
+            let n = Pin::new(&mut stream)
+                .read_early_data(&mut buf)
+                .await
+                .unwrap();
+            if n == 0 {
+                break;
+            }
+            early.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(early, b"hello world!");
+
+        Pin::new(&mut stream).accept().await.unwrap();
+        stream
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream)
+            .write_early_data_all(b"hello ")
+            .await
+            .unwrap();
+        let n = Pin::new(&mut stream)
+            .write_early_data_vectored(&[IoSlice::new(b"world"), IoSlice::new(b"!")])
+            .await
+            .unwrap();
+        assert_eq!(n, 6);
+
+        Pin::new(&mut stream).connect().await.unwrap();
+        stream
+    };
+
+    let (server, client) = future::join(server, client).await;
+    assert_eq!(
+        client
+            .export_keying_material_early(32, "EXPORTER-test", b"context")
+            .unwrap(),
+        server
+            .export_keying_material_early(32, "EXPORTER-test", b"context")
+            .unwrap(),
+    );
+}
+
+#[tokio::test]
+async fn alpn() {
+    // Returns the protocol selected by each side, or `None` if the handshake failed.
+    async fn negotiate(
+        server_protos: &'static [u8],
+        error: AlpnError,
+    ) -> Option<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+        let mut acceptor = test_utils::acceptor();
+        acceptor.set_alpn_select_callback(move |_, client| {
+            ssl::select_next_proto(server_protos, client).ok_or(error)
+        });
+        let mut connector = test_utils::connector();
+        connector.set_alpn_protos(b"\x02h2\x08http/1.1").unwrap();
+        let (mut client, mut server) = tls_stream_pair_with(&connector.build(), &acceptor.build());
+
+        let (r1, r2) = future::join(
+            Pin::new(&mut client).connect(),
+            Pin::new(&mut server).accept(),
+        )
+        .await;
+        if r1.is_err() {
+            assert!(r2.is_err());
+            return None;
+        }
+        r2.unwrap();
+
+        Some((
+            client.alpn_protocol().map(|p| p.to_vec()),
+            server.alpn_protocol().map(|p| p.to_vec()),
+        ))
+    }
+
+    let h2 = Some(b"h2".to_vec());
+    assert_eq!(
+        negotiate(b"\x02h2\x08http/1.1", AlpnError::NOACK).await,
+        Some((h2.clone(), h2))
+    );
+
+    // without overlap, the handshake proceeds without a protocol
+    assert_eq!(
+        negotiate(b"\x06spdy/1", AlpnError::NOACK).await,
+        Some((None, None))
+    );
+
+    // unless the server requires ALPN
+    assert_eq!(negotiate(b"\x06spdy/1", AlpnError::ALERT_FATAL).await, None);
+}
+
+#[tokio::test]
+async fn handshake_state() {
+    let mut acceptor = test_utils::acceptor();
+    acceptor.set_alpn_select_callback(|_, client| {
+        ssl::select_next_proto(b"\x08http/1.1", client).ok_or(AlpnError::NOACK)
+    });
+    let mut connector = test_utils::connector();
+    connector.set_alpn_protos(b"\x02h2\x08http/1.1").unwrap();
+    let (client, server) = tls_stream_pair_with(&connector.build(), &acceptor.build());
+
+    let server = async move {
+        let mut stream = server;
+        assert!(!stream.is_handshake_done());
+        assert_eq!(stream.to_string(), "SslStream { state: pre-handshake }");
+        assert_eq!(stream.handshake_duration(), None);
+
+        Pin::new(&mut stream).accept().await.unwrap();
+        assert!(stream.is_handshake_done());
+        assert!(stream.handshake_duration().is_some());
+        assert_eq!(stream.server_name(), Some("localhost"));
+        assert_eq!(stream.alpn_protocol(), Some(&b"http/1.1"[..]));
+        assert!(stream.is_server_side());
+        assert!(stream.peer_certificate().is_none());
+        assert!(stream
+            .peer_cert_fingerprint(MessageDigest::sha256())
+            .unwrap()
+            .is_none());
+    };
+
+    let client = async move {
+        let mut stream = client;
+        assert!(!stream.is_handshake_done());
+        assert_eq!(stream.handshake_duration(), None);
+
+        Pin::new(&mut stream).connect().await.unwrap();
+        assert!(stream.is_handshake_done());
+        assert!(stream.handshake_duration().is_some());
+        assert_eq!(stream.alpn_protocol(), Some(&b"http/1.1"[..]));
+        assert!(!stream.is_server_side());
+        assert!(stream.to_string().starts_with("SslStream { version: TLSv1"));
+
+        let cert = stream.peer_certificate().unwrap();
+        let cn = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .unwrap();
+        assert_eq!(&**cn.data().as_utf8().unwrap(), "localhost");
+        let fingerprint = stream
+            .peer_cert_fingerprint(MessageDigest::sha256())
+            .unwrap()
+            .unwrap();
+        assert_eq!(*fingerprint, sha::sha256(&cert.to_der().unwrap()));
+        assert_eq!(stream.peer_cert_chain().unwrap().len(), 1);
+        assert_eq!(stream.verify_result(), X509VerifyResult::OK);
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn max_write_chunk() {
+    let (client, server) = tls_stream_pair();
+
+    let data = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+
+    let server = async {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        let mut buf = vec![0; data.len() + 1000];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf[..1000], &data[..1000]);
+        assert_eq!(&buf[1000..], &data[..]);
+    };
+
+    let client = async {
+        let mut stream = client;
+        stream.set_max_write_chunk(Some(1000));
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        let n = future::poll_fn(|ctx| Pin::new(&mut stream).poll_write(ctx, &data))
+            .await
+            .unwrap();
+        assert_eq!(n, 1000);
+
+        stream.write_all(&data).await.unwrap();
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn write_vectored() {
+    let (client, server) = tls_stream_pair();
+
+    let large = vec![b'x'; 64 * 1024];
+
+    let server = async {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        let mut buf = [0; 8];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"asdfjkl;");
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf.len(), large.len() + 1);
+        assert_eq!(buf[0], b'y');
+        assert_eq!(&buf[1..], &large[..]);
+    };
+
+    let client = async {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+        assert!(stream.is_write_vectored());
+
+        let n = stream
+            .write_vectored(&[
+                IoSlice::new(b"asdf"),
+                IoSlice::new(b""),
+                IoSlice::new(b"jkl;"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(n, 8);
+
+        let mut written = 0;
+        while written < large.len() + 1 {
+            let bufs = if written == 0 {
+                [IoSlice::new(b"y"), IoSlice::new(&large)]
+            } else {
+                [IoSlice::new(b""), IoSlice::new(&large[written - 1..])]
+            };
+            let n = stream.write_vectored(&bufs).await.unwrap();
+            // only a single record's worth is gathered
+            if written == 0 {
+                assert!(n <= 16 * 1024, "{}", n);
+            }
+            written += n;
+        }
+
+        future::poll_fn(|ctx| Pin::new(&mut stream).poll_shutdown(ctx))
+            .await
+            .unwrap()
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn copy_bidirectional_tls() {
+    const A_TO_B: usize = 16 * 1024 * 1024;
+    const B_TO_A: usize = 24 * 1024 * 1024;
+
+    let (mut a, mut proxy_a) = connected_stream_pair().await;
+    let (mut proxy_b, mut b) = connected_stream_pair().await;
+
+    let proxy = crate::copy_bidirectional_tls(&mut proxy_a, &mut proxy_b);
+
+    let a = async {
+        let data = vec![b'a'; A_TO_B];
+        a.write_all(&data).await.unwrap();
+
+        let mut buf = vec![0; B_TO_A];
+        a.read_exact(&mut buf).await.unwrap();
+        assert!(buf.iter().all(|b| *b == b'b'));
+
+        future::poll_fn(|ctx| Pin::new(&mut a).poll_shutdown(ctx))
+            .await
+            .unwrap();
+    };
+
+    let b = async {
+        let mut buf = vec![0; A_TO_B];
+        b.read_exact(&mut buf).await.unwrap();
+        assert!(buf.iter().all(|b| *b == b'a'));
+
+        let data = vec![b'b'; B_TO_A];
+        b.write_all(&data).await.unwrap();
+
+        future::poll_fn(|ctx| Pin::new(&mut b).poll_shutdown(ctx))
+            .await
+            .unwrap();
+    };
+
+    let (counts, _, _) = future::join3(proxy, a, b).await;
+    assert_eq!(counts.unwrap(), (A_TO_B as u64, B_TO_A as u64));
+}
+
+#[tokio::test]
+async fn copy_bidirectional_pinned() {
+    let (mut a, proxy_a) = connected_stream_pair().await;
+    let (proxy_b, mut b) = connected_stream_pair().await;
+    let mut proxy_a = Box::pin(proxy_a);
+    let mut proxy_b = Box::pin(proxy_b);
+
+    let proxy = proxy_a.as_mut().copy_bidirectional(proxy_b.as_mut());
+
+    let a = async {
+        a.write_all(b"ping").await.unwrap();
+        Pin::new(&mut a).send_close_notify().await.unwrap();
+
+        let mut buf = vec![];
+        a.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"pong!");
+    };
+
+    let b = async {
+        // a's half-close is propagated through the proxy
+        let mut buf = vec![];
+        b.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"ping");
+
+        b.write_all(b"pong!").await.unwrap();
+        Pin::new(&mut b).send_close_notify().await.unwrap();
+    };
+
+    let (counts, _, _) = future::join3(proxy, a, b).await;
+    assert_eq!(counts.unwrap(), (4, 5));
+}
+
+#[cfg(all(feature = "serde", ossl111))]
+#[tokio::test]
+async fn serde_round_trip() {
+    let (client, _server) = connected_stream_pair().await;
+
+    let info = client.connection_info().unwrap();
+    let json = serde_json::to_string(&info).unwrap();
+    let info2: crate::ConnectionInfo = serde_json::from_str(&json).unwrap();
+    assert_eq!(info2.version, info.version);
+    assert_eq!(info2.cipher, info.cipher);
+    assert_eq!(info2.alpn, None);
+    assert_eq!(info2.sni.as_deref(), Some("localhost"));
+    assert_eq!(info2.session_reused, info.session_reused);
+    assert_eq!(
+        info2.peer_certificate.unwrap().to_der().unwrap(),
+        info.peer_certificate.unwrap().to_der().unwrap()
+    );
+    assert!(!json.contains("alpn"));
+
+    let id = client.id();
+    let json = serde_json::to_string(&id).unwrap();
+    assert_eq!(
+        serde_json::from_str::<crate::SslStreamId>(&json).unwrap(),
+        id
+    );
+
+    let error = crate::Error::PinMismatch;
+    let json = serde_json::to_string(&error).unwrap();
+    assert_eq!(
+        json,
+        r#"{"kind":"pin_mismatch","message":"server certificate did not match any pinned key"}"#
+    );
+    let info: crate::ErrorInfo = serde_json::from_str(&json).unwrap();
+    assert_eq!(info, error.info());
+    assert_eq!(info.kind, crate::ErrorKind::PinMismatch);
+}
+
+#[tokio::test]
+async fn server_name_absent() {
+    let client = test_utils::connector()
+        .build()
+        .configure()
+        .unwrap()
+        .use_server_name_indication(false)
+        .into_ssl("localhost")
+        .unwrap();
+    let server = Ssl::new(test_utils::acceptor().build().context()).unwrap();
+    let (mut client, mut server) = ssl_stream_pair(client, server);
+
+    connect_pair(&mut client, &mut server).await;
+    assert_eq!(server.server_name(), None);
+}
+
+#[tokio::test]
+async fn read_to_string_bounded() {
+    async fn read(data: &'static [u8], limit: usize) -> io::Result<String> {
+        let (mut client, mut server) = connected_stream_pair().await;
+
+        client.write_all(data).await.unwrap();
+        Pin::new(&mut client).send_close_notify().await.unwrap();
+
+        let mut buf = String::new();
+        Pin::new(&mut server)
+            .read_to_string_bounded(&mut buf, limit)
+            .await
+            .map(|n| {
+                assert_eq!(n, buf.len());
+                buf
+            })
+    }
+
+    assert_eq!(read(b"hello", 5).await.unwrap(), "hello");
+    assert_eq!(
+        read(b"hello!", 5).await.unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+    assert_eq!(
+        read(b"\xff", 5).await.unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+}
+
+#[tokio::test]
+async fn ssl_read_vectored() {
+    let (mut client, mut server) = connected_stream_pair().await;
+
+    client.write_all(b"asdfjkl;").await.unwrap();
+
+    let mut a = [0; 4];
+    let mut b = [0; 8];
+    let mut bufs = [
+        io::IoSliceMut::new(&mut a),
+        io::IoSliceMut::new(&mut []),
+        io::IoSliceMut::new(&mut b),
+    ];
+    let n = Pin::new(&mut server)
+        .ssl_read_vectored(&mut bufs)
+        .await
+        .unwrap();
+    assert_eq!(n, 8);
+    assert_eq!(&a, b"asdf");
+    assert_eq!(&b[..4], b"jkl;");
+}
+
+#[cfg(feature = "bytes")]
+#[tokio::test]
+async fn read_buf_ssl() {
+    let (mut client, mut server) = connected_stream_pair().await;
+
+    client.write_all(b"asdfjkl;").await.unwrap();
+
+    let mut buf = bytes::BytesMut::with_capacity(4);
+    buf.extend_from_slice(b">");
+    let n = Pin::new(&mut server).read_buf_ssl(&mut buf).await.unwrap();
+    assert!(n > 0);
+    while buf.len() < 9 {
+        Pin::new(&mut server).read_buf_ssl(&mut buf).await.unwrap();
+    }
+    assert_eq!(&buf[..], b">asdfjkl;");
+
+    // a full fixed-size buffer reads nothing
+    let mut full = &mut [0u8; 0][..];
+    let n = Pin::new(&mut server).read_buf_ssl(&mut full).await.unwrap();
+    assert_eq!(n, 0);
+}
+
+#[tokio::test]
+async fn unpin_convenience_methods() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        stream.accept().await.unwrap();
+
+        let mut buf = [0; 4];
+        let n = stream.ssl_read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], &b"asdf"[..n]);
+        stream.read_exact(&mut buf[n..]).await.unwrap();
+        assert_eq!(&buf, b"asdf");
+
+        stream.write_all(b"jkl;").await.unwrap();
+        stream.shutdown().await.unwrap();
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        stream.connect().await.unwrap();
+
+        stream.write_all(b"asdf").await.unwrap();
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"jkl;");
+        stream.shutdown().await.unwrap();
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn ssl_stream_acceptor() {
+    let acceptor = SslStreamAcceptor::builder(test_utils::acceptor()).build();
+    let connector = test_utils::connector().build();
+
+    for _ in 0..2 {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+
+        let acceptor = acceptor.clone();
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server).await.unwrap();
+            stream.write_all(b"jkl;").await.unwrap();
+        });
+
+        let ssl = connector
+            .configure()
+            .unwrap()
+            .into_ssl("localhost")
+            .unwrap();
+        let mut stream = SslStream::new(ssl, client).unwrap();
+        stream.connect().await.unwrap();
+
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"jkl;");
+
+        server.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn handshake_error_downcast() {
+    let acceptor = SslStreamAcceptor::builder(test_utils::acceptor()).build();
+    let (client, server) = tokio::io::duplex(64 * 1024);
+
+    let server = async move {
+        let err = match acceptor.accept(server).await {
+            Ok(_) => panic!("handshake succeeded"),
+            Err(e) => io::Error::from(e),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // the client rejects our certificate with an alert
+        let err = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<crate::Error>())
+            .unwrap();
+        match err {
+            crate::Error::Handshake(e) => assert_eq!(e.error().code(), ErrorCode::SSL),
+            e => panic!("unexpected error {:?}", e),
+        }
+    };
+
+    let client = async move {
+        // doesn't trust the test certificate
+        let ssl = SslConnector::builder(SslMethod::tls())
+            .unwrap()
+            .build()
+            .configure()
+            .unwrap()
+            .into_ssl("localhost")
+            .unwrap();
+        let mut stream = SslStream::new(ssl, client).unwrap();
+        let err = Pin::new(&mut stream).connect().await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::SSL);
+        stream
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn update_ssl_ctx() {
+    // the acceptor has no certificate, so the handshake only succeeds with the updated context
+    let acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())
+        .unwrap()
+        .build();
+    let mut connector = test_utils::connector();
+    connector.set_alpn_protos(b"\x02h2").unwrap();
+    let (client, mut server) = tls_stream_pair_with(&connector.build(), &acceptor);
+
+    // callbacks configured on the new context apply too
+    let mut context = test_utils::acceptor();
+    context.set_alpn_select_callback(|_, client| {
+        ssl::select_next_proto(b"\x02h2", client).ok_or(AlpnError::NOACK)
+    });
+    server.update_ssl_ctx(context.build().context()).unwrap();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+        assert_eq!(stream.alpn_protocol(), Some(&b"h2"[..]));
+        stream.write_all(b"jkl;").await.unwrap();
+        stream
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"jkl;");
+    };
+
+    future::join(server, client).await;
+}
+
+#[test]
+fn handshake_futures_are_send() {
+    fn assert_send<T: Send>() {}
+
+    assert_send::<crate::Connect<'static, TcpStream>>();
+    assert_send::<crate::Accept<'static, TcpStream>>();
+    assert_send::<crate::DoHandshake<'static, TcpStream>>();
+    assert_send::<crate::SslRead<'static, TcpStream>>();
+    assert_send::<crate::ConnectFuture<TcpStream>>();
+    assert_send::<crate::AcceptFuture<TcpStream>>();
+    assert_send::<crate::HandshakeFuture<TcpStream>>();
+}
+
+#[tokio::test]
+async fn owned_handshake_futures() {
+    let (client, server) = tls_stream_pair();
+
+    let server = tokio::spawn(server.into_accept());
+    let client = tokio::spawn(client.into_connect());
+
+    let mut server = server.await.unwrap().unwrap();
+    let mut client = client.await.unwrap().unwrap();
+    assert!(client.is_handshake_done());
+
+    client.write_all(b"asdf").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"asdf");
+}
+
+#[tokio::test]
+async fn owned_handshake_future_error() {
+    // doesn't trust the test certificate
+    let connector = SslConnector::builder(SslMethod::tls()).unwrap().build();
+    let (client, server) = tls_stream_pair_with(&connector, &test_utils::acceptor().build());
+
+    let server = tokio::spawn(server.into_accept());
+    let (e, stream) = client.into_connect().await.unwrap_err();
+    assert_eq!(e.code(), ErrorCode::SSL);
+    assert_eq!(
+        stream.verify_result(),
+        X509VerifyResult::from_raw(openssl_sys::X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT)
+    );
+
+    server.await.unwrap().unwrap_err();
+}
+
+#[tokio::test]
+async fn want() {
+    let (mut client, _server) = tls_stream_pair();
+    assert_eq!(client.want(), ErrorCode::NONE);
+
+    // the client sends its hello and then waits for the server's response
+    let r = future::poll_fn(|cx| Poll::Ready(Pin::new(&mut client).poll_connect(cx))).await;
+    assert!(r.is_pending());
+    assert_eq!(client.want(), ErrorCode::WANT_READ);
+    assert!(client.wants_read());
+    assert!(!client.wants_write());
+}
+
+#[tokio::test]
+async fn empty_read_buf() {
+    async fn read_empty(stream: &mut SslStream<DuplexStream>) {
+        let mut buf = ReadBuf::new(&mut []);
+        let r =
+            future::poll_fn(|cx| Poll::Ready(Pin::new(&mut *stream).poll_read(cx, &mut buf))).await;
+        assert!(matches!(r, Poll::Ready(Ok(()))));
+    }
+
+    let (mut client, mut server) = connected_stream_pair().await;
+
+    read_empty(&mut server).await;
+
+    client.write_all(b"asdf").await.unwrap();
+    client.flush().await.unwrap();
+    read_empty(&mut server).await;
+
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"asdf");
+}
+
+#[tokio::test]
+async fn handshake_resumes_after_cancellation() {
+    let (mut client, mut server) = tls_stream_pair();
+
+    assert!(!client.is_handshake_in_progress());
+
+    // the server isn't running yet, so the client stalls after sending its hello
+    let r = tokio::time::timeout(Duration::from_millis(10), client.connect()).await;
+    assert!(r.is_err());
+    assert!(client.is_handshake_in_progress());
+
+    // likewise, the server stalls waiting for the client's Finished message
+    let r = tokio::time::timeout(Duration::from_millis(10), server.accept()).await;
+    assert!(r.is_err());
+    assert!(server.is_handshake_in_progress());
+
+    connect_pair(&mut client, &mut server).await;
+    assert!(!client.is_handshake_in_progress());
+    assert!(!server.is_handshake_in_progress());
+
+    client.write_all(b"asdf").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"asdf");
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn async_sni_callback() {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+
+    let names = Arc::new(Mutex::new(vec![]));
+
+    // the acceptor's own context has no certificate, so the handshake only succeeds if the
+    // resolved context is used
+    let mut acceptor =
+        SslStreamAcceptor::builder(SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap());
+    acceptor
+        .set_async_sni_callback({
+            let names = names.clone();
+            move |name| {
+                names.lock().unwrap().push(name);
+                Box::pin(async {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+
+                    let acceptor = test_utils::acceptor().build();
+                    Ok::<_, openssl::ssl::Error>(acceptor.into_context())
+                })
+            }
+        })
+        .unwrap();
+    let acceptor = acceptor.build();
+
+    let server = async move {
+        let mut stream = acceptor.accept(server).await.unwrap();
+        stream.write_all(b"jkl;").await.unwrap();
+    };
+
+    let client = async move {
+        let ssl = test_utils::connector()
+            .build()
+            .configure()
+            .unwrap()
+            .into_ssl("localhost")
+            .unwrap();
+        let mut stream = SslStream::new(ssl, client).unwrap();
+        stream.connect().await.unwrap();
+
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"jkl;");
+    };
+
+    future::join(server, client).await;
+    assert_eq!(*names.lock().unwrap(), ["localhost"]);
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn async_sni_callback_client_certificate() {
+    // only the resolved context requires a client certificate
+    let mut acceptor = SslStreamAcceptor::builder(test_utils::acceptor());
+    acceptor
+        .set_async_sni_callback(|_| {
+            Box::pin(async {
+                let mut acceptor = test_utils::acceptor();
+                acceptor.set_ca_file("tests/cert.pem").unwrap();
+                acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+                Ok::<_, openssl::ssl::Error>(acceptor.build().into_context())
+            })
+        })
+        .unwrap();
+    let acceptor = acceptor.build();
+
+    let mut with_cert = test_utils::connector();
+    with_cert
+        .set_private_key_file("tests/key.pem", SslFiletype::PEM)
+        .unwrap();
+    with_cert
+        .set_certificate_chain_file("tests/cert.pem")
+        .unwrap();
+
+    for (connector, present) in [(test_utils::connector(), false), (with_cert, true)] {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let ssl = connector
+            .build()
+            .configure()
+            .unwrap()
+            .into_ssl("localhost")
+            .unwrap();
+        let mut client = SslStream::new(ssl, client).unwrap();
+
+        // dropping the server's stream lets the client's read finish
+        let server = async {
+            let stream = acceptor.accept(server).await;
+            stream.map(|stream| stream.peer_certificate().is_some())
+        };
+        // a TLS 1.3 client only learns of the rejection once it reads
+        let client = async move {
+            let _ = client.connect().await;
+            let mut buf = [0; 1];
+            let _ = client.read(&mut buf).await;
+        };
+
+        let (r, ()) = future::join(server, client).await;
+        if present {
+            assert!(r.unwrap());
+        } else {
+            assert!(r.is_err());
+        }
+    }
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn async_client_hello_callback() {
+    let certs = Arc::new(
+        ["a.example", "b.example"]
+            .iter()
+            .map(|name| (name.to_string(), test_utils::self_signed_cert(name)))
+            .collect::<Vec<_>>(),
+    );
+    let alpn = Arc::new(Mutex::new(vec![]));
+
+    let mut acceptor =
+        SslStreamAcceptor::builder(SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap());
+    acceptor
+        .set_async_client_hello_callback({
+            let certs = certs.clone();
+            let alpn = alpn.clone();
+            move |hello| {
+                alpn.lock().unwrap().push(hello.alpn_protocols().to_vec());
+                let certs = certs.clone();
+                Box::pin(async move {
+                    let name = hello.server_name().unwrap();
+                    // resolve the second name first
+                    let delay = if name == "a.example" { 20 } else { 0 };
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+
+                    let (_, (cert, key)) = certs.iter().find(|(n, _)| n == name).unwrap();
+                    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+                    acceptor.set_certificate(cert).unwrap();
+                    acceptor.set_private_key(key).unwrap();
+                    Ok::<_, openssl::ssl::Error>(acceptor.build().into_context())
+                })
+            }
+        })
+        .unwrap();
+    let acceptor = acceptor.build();
+
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_verify(SslVerifyMode::NONE);
+    connector.set_alpn_protos(b"\x02h2\x08http/1.1").unwrap();
+    let connector = SslStreamConnector::from_connector(connector.build());
+
+    let handshake = |name: &'static str| {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let acceptor = &acceptor;
+        let connector = &connector;
+        async move {
+            let (client, server) =
+                future::join(connector.connect(name, client), acceptor.accept(server)).await;
+            server.unwrap();
+            client.unwrap().ssl().peer_certificate().unwrap()
+        }
+    };
+    let (a, b) = future::join(handshake("a.example"), handshake("b.example")).await;
+
+    assert_eq!(a.to_der().unwrap(), certs[0].1 .0.to_der().unwrap());
+    assert_eq!(b.to_der().unwrap(), certs[1].1 .0.to_der().unwrap());
+    let expected = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    assert_eq!(*alpn.lock().unwrap(), [expected.clone(), expected]);
+}
+
+#[tokio::test]
+async fn alpn_dispatcher() {
+    #[derive(Debug, Clone, PartialEq)]
+    enum Protocol {
+        Http1,
+        Custom,
+    }
+
+    let mut dispatcher = AlpnDispatcher::new();
+    dispatcher.add_protocol(b"http/1.1", Protocol::Http1);
+    dispatcher.add_protocol(b"custom/1", Protocol::Custom);
+
+    let mut acceptor = SslStreamAcceptor::builder(test_utils::acceptor());
+    dispatcher.configure(&mut acceptor);
+    let acceptor = acceptor.build();
+
+    for (offered, expected) in [
+        (&b"\x08custom/1\x02h2"[..], Some(Protocol::Custom)),
+        (b"\x08custom/1\x08http/1.1", Some(Protocol::Http1)),
+        (b"\x02h2", None),
+    ] {
+        let mut connector = test_utils::connector();
+        connector.set_alpn_protos(offered).unwrap();
+        let connector = SslStreamConnector::from_connector(connector.build());
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = future::join(
+            connector.connect("localhost", client),
+            dispatcher.accept(&acceptor, server),
+        )
+        .await;
+        let (_, protocol) = server.unwrap();
+        assert_eq!(protocol, expected);
+        assert_eq!(
+            client.unwrap().alpn_protocol().is_some(),
+            expected.is_some()
+        );
+    }
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn lazy_config_acceptor() {
+    use crate::LazyAcceptor;
+
+    let contexts = ["a.example", "b.example"]
+        .iter()
+        .map(|&name| {
+            let (cert, key) = test_utils::self_signed_cert(name);
+            let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+            acceptor.set_certificate(&cert).unwrap();
+            acceptor.set_private_key(&key).unwrap();
+            (name, acceptor.build().into_context())
+        })
+        .collect::<Vec<_>>();
+
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_verify(SslVerifyMode::NONE);
+    let connector = SslStreamConnector::from_connector(connector.build());
+
+    // the protocol settings come from the base rather than the chosen contexts
+    let mut base = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    base.set_max_proto_version(Some(ssl::SslVersion::TLS1_2))
+        .unwrap();
+    let lazy = LazyAcceptor::new(base).unwrap();
+
+    for name in ["a.example", "b.example", "c.example"] {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+
+        let server = async {
+            let start = lazy.accept(server).unwrap().await.unwrap();
+            assert_eq!(start.client_hello().server_name(), Some(name));
+            // unknown names are rejected before handshaking
+            let (_, context) = contexts.iter().find(|(n, _)| *n == name)?;
+            Some(start.into_stream(context).await.unwrap())
+        };
+
+        let (client, server) = future::join(connector.connect(name, client), server).await;
+        match server {
+            Some(_) => {
+                let client = client.unwrap();
+                assert_eq!(client.ssl().version2(), Some(ssl::SslVersion::TLS1_2));
+                let cert = client.ssl().peer_certificate().unwrap();
+                let cn = cert
+                    .subject_name()
+                    .entries_by_nid(Nid::COMMONNAME)
+                    .next()
+                    .unwrap();
+                assert_eq!(cn.data().as_slice(), name.as_bytes());
+            }
+            None => {
+                client.unwrap_err();
+            }
+        }
+    }
+}
+
+async fn sni_handshake(
+    acceptor: &SniAcceptor,
+    name: &str,
+) -> (
+    Result<SslStream<DuplexStream>, crate::Error>,
+    Result<SslStream<DuplexStream>, crate::Error>,
+) {
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_verify(SslVerifyMode::NONE);
+    let connector = SslStreamConnector::from_connector(connector.build());
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    future::join(connector.connect(name, client), acceptor.accept(server)).await
+}
+
+#[tokio::test]
+async fn sni_acceptor() {
+    let mut acceptor =
+        SniAcceptor::builder(SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap());
+    for name in ["a.example", "b.example"] {
+        let (cert, key) = test_utils::self_signed_cert(name);
+        acceptor.add_certificate(name, &[cert], &key).unwrap();
+    }
+    acceptor.set_default_context(test_utils::acceptor().build().into_context());
+    let acceptor = acceptor.build();
+
+    for (name, expected) in [
+        ("a.example", "a.example"),
+        ("B.Example", "b.example"),
+        ("c.example", "localhost"),
+    ] {
+        let (client, server) = sni_handshake(&acceptor, name).await;
+        server.unwrap();
+        let cert = client.unwrap().ssl().peer_certificate().unwrap();
+        let cn = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .unwrap();
+        assert_eq!(cn.data().as_slice(), expected.as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn sni_acceptor_alpn() {
+    let mut acceptor =
+        SniAcceptor::builder(SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap());
+    acceptor.set_configure_callback(|name, builder| {
+        assert_eq!(name, "a.example");
+        builder.set_alpn_select_callback(|_, client| {
+            ssl::select_next_proto(b"\x02h2", client).ok_or(AlpnError::NOACK)
+        });
+        Ok(())
+    });
+    let (cert, key) = test_utils::self_signed_cert("a.example");
+    acceptor
+        .add_certificate("a.example", &[cert], &key)
+        .unwrap();
+    let acceptor = acceptor.build();
+
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_verify(SslVerifyMode::NONE);
+    connector.set_alpn_protos(b"\x02h2\x08http/1.1").unwrap();
+    let connector = SslStreamConnector::from_connector(connector.build());
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let (client, server) = future::join(
+        connector.connect("a.example", client),
+        acceptor.accept(server),
+    )
+    .await;
+    assert_eq!(
+        client.unwrap().ssl().selected_alpn_protocol(),
+        Some(&b"h2"[..])
+    );
+    assert_eq!(
+        server.unwrap().ssl().selected_alpn_protocol(),
+        Some(&b"h2"[..])
+    );
+}
+
+#[tokio::test]
+async fn sni_acceptor_rejects_unknown_names() {
+    let mut acceptor =
+        SniAcceptor::builder(SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap());
+    let (cert, key) = test_utils::self_signed_cert("a.example");
+    acceptor
+        .add_certificate("a.example", &[cert], &key)
+        .unwrap();
+    acceptor.set_default_context(test_utils::acceptor().build().into_context());
+    acceptor.set_reject_unknown_names(true);
+    let acceptor = acceptor.build();
+
+    let (client, server) = sni_handshake(&acceptor, "c.example").await;
+    server.unwrap_err();
+    match client {
+        Err(crate::Error::Handshake(e)) => {
+            assert!(e.error().to_string().contains("unrecognized name"))
+        }
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn shutdown_is_idempotent() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        for _ in 0..3 {
+            stream.shutdown().await.unwrap();
+        }
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+        for _ in 0..3 {
+            stream.shutdown().await.unwrap();
+        }
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn shutdown_drain_limit() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        // never read the client's close notify, just keep streaming data until it hangs up
+        let buf = [0; 16 * 1024];
+        while stream.write_all(&buf).await.is_ok() {}
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+        let limit = stream.shutdown_drain_limit();
+        assert_ne!(limit, 64 * 1024);
+
+        let e = tokio::time::timeout(
+            Duration::from_secs(10),
+            future::poll_fn(|cx| {
+                Pin::new(&mut stream).poll_shutdown_with_drain_limit(cx, 64 * 1024)
+            }),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+        // the override doesn't replace the configured limit
+        assert_eq!(stream.shutdown_drain_limit(), limit);
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn framed_lines() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server.into_boxed();
+
+        stream.get_pin_mut().accept().await.unwrap();
+
+        let mut framed = Framed::new(stream, LinesCodec::new());
+        let line = framed.next().await.unwrap().unwrap();
+        framed.send(line.to_uppercase()).await.unwrap();
+    };
+
+    let client = async move {
+        let mut stream = client.into_boxed();
+
+        stream.get_pin_mut().connect().await.unwrap();
+
+        let mut framed = Framed::new(stream, LinesCodec::new());
+        framed.send("hello").await.unwrap();
+        assert_eq!(framed.next().await.unwrap().unwrap(), "HELLO");
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn shutdown_read_buffer_size() {
+    async fn drain_burst(read_buffer_size: usize) {
+        let (client, server) = tls_stream_pair();
+
+        let server = async move {
+            let mut stream = server;
+
+            Pin::new(&mut stream).accept().await.unwrap();
+
+            stream.write_all(&[0; 128 * 1024]).await.unwrap();
+            stream.shutdown().await.unwrap();
+        };
+
+        let client = async move {
+            let mut stream = client;
+            stream.set_shutdown_read_buffer_size(read_buffer_size);
+            stream.set_shutdown_drain_limit(256 * 1024);
+            assert_eq!(stream.shutdown_read_buffer_size(), read_buffer_size);
+
+            Pin::new(&mut stream).connect().await.unwrap();
+
+            tokio::time::timeout(Duration::from_secs(10), stream.shutdown())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(stream.shutdown.read_buf.is_none());
+        };
+
+        future::join(server, client).await;
+    }
+
+    drain_burst(1).await;
+    drain_burst(64 * 1024).await;
+}
+
+#[tokio::test]
+async fn capture_shutdown_data() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        // wait for the client's close notify before sending the trailer
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+
+        stream.write_all(b"221 bye\r\n").await.unwrap();
+        stream.shutdown().await.unwrap();
+    };
+
+    let client = async move {
+        let mut stream = client;
+        stream.set_capture_shutdown_data(true);
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        stream.shutdown().await.unwrap();
+        assert_eq!(stream.take_shutdown_data(), b"221 bye\r\n");
+        assert!(stream.take_shutdown_data().is_empty());
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn shutdown_mode() {
+    async fn run(mode: ShutdownMode, peer_responds: bool) {
+        let (client, server) = tls_stream_pair();
+        let (tx, rx) = oneshot::channel::<()>();
+
+        let server = async move {
+            let mut stream = server;
+
+            Pin::new(&mut stream).accept().await.unwrap();
+
+            let mut buf = vec![];
+            stream.read_to_end(&mut buf).await.unwrap();
+
+            if peer_responds {
+                stream.shutdown().await.unwrap();
+            } else if mode == ShutdownMode::SendOnly {
+                // hold the connection open without a close notify until the client is done
+                let _ = rx.await;
+            }
+        };
+
+        let client = async move {
+            let mut stream = client;
+            assert_eq!(stream.shutdown_mode(), ShutdownMode::Bidirectional);
+            stream.set_shutdown_mode(mode);
+
+            Pin::new(&mut stream).connect().await.unwrap();
+
+            tokio::time::timeout(Duration::from_secs(10), stream.shutdown())
+                .await
+                .unwrap()
+                .unwrap();
+            let _ = tx.send(());
+
+            // keep the transport open for the server's close notify
+            stream
+        };
+
+        future::join(server, client).await;
+    }
+
+    for &mode in &[ShutdownMode::SendOnly, ShutdownMode::Bidirectional] {
+        run(mode, true).await;
+        run(mode, false).await;
+    }
+}
+
+#[tokio::test]
+async fn buffered_stream() {
+    let (client, mut server) = connected_stream_pair().await;
+    let mut client = BufferedSslStream::new(client);
+
+    // more than the transport can hold, but the writes complete without waiting for the peer
+    let data = vec![7; 256 * 1024];
+    for chunk in data.chunks(100) {
+        client.write_all(chunk).now_or_never().unwrap().unwrap();
+    }
+    assert_eq!(client.buffered_len(), data.len());
+
+    let mut buf = vec![0; data.len()];
+    let (r1, r2) = future::join(client.flush(), server.read_exact(&mut buf)).await;
+    r1.unwrap();
+    r2.unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(client.buffered_len(), 0);
+}
+
+#[tokio::test]
+async fn client_certificate() {
+    let mut connector = test_utils::connector();
+    connector
+        .set_private_key_file("tests/key.pem", SslFiletype::PEM)
+        .unwrap();
+    connector
+        .set_certificate_chain_file("tests/cert.pem")
+        .unwrap();
+    let mut acceptor = test_utils::acceptor();
+    acceptor.set_ca_file("tests/cert.pem").unwrap();
+    acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    let (mut client, mut server) = tls_stream_pair_with(&connector.build(), &acceptor.build());
+
+    connect_pair(&mut client, &mut server).await;
+
+    let cert = server.peer_certificate().unwrap();
+    drop(server);
+    let cn = cert
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .unwrap();
+    assert_eq!(&**cn.data().as_utf8().unwrap(), "localhost");
+}
+
+#[tokio::test]
+async fn require_client_certificates() {
+    async fn connect(
+        client_cert: Option<(&X509, &PKey<Private>)>,
+    ) -> Result<SslStream<DuplexStream>, crate::Error> {
+        let mut acceptor = SslStreamAcceptor::builder(test_utils::acceptor());
+        acceptor
+            .require_client_certificates("tests/cert.pem")
+            .unwrap();
+        let acceptor = acceptor.build();
+
+        let mut connector = test_utils::connector();
+        if let Some((cert, key)) = client_cert {
+            connector.set_certificate(cert).unwrap();
+            connector.set_private_key(key).unwrap();
+        }
+        let connector = SslStreamConnector::from_connector(connector.build());
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (_, server) = future::join(
+            connector.connect("localhost", client),
+            acceptor.accept(server),
+        )
+        .await;
+        server
+    }
+
+    let cert = X509::from_pem(&std::fs::read("tests/cert.pem").unwrap()).unwrap();
+    let key = PKey::private_key_from_pem(&std::fs::read("tests/key.pem").unwrap()).unwrap();
+    let server = connect(Some((&cert, &key))).await.unwrap();
+    assert_eq!(
+        server.peer_certificate().unwrap().to_der().unwrap(),
+        cert.to_der().unwrap()
+    );
+
+    // a client without a certificate fails before verification
+    match connect(None).await {
+        Err(crate::Error::Handshake(e)) => {
+            assert_eq!(e.verify_result(), X509VerifyResult::OK);
+            assert!(e.peer_certificates().is_empty());
+        }
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+
+    // a certificate from another CA fails verification
+    let (cert, key) = test_utils::self_signed_cert("localhost");
+    match connect(Some((&cert, &key))).await {
+        Err(crate::Error::Handshake(e)) => assert_ne!(e.verify_result(), X509VerifyResult::OK),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn async_verify_callback() {
+    fn verifier(reject: bool) -> impl Fn(Vec<X509>) -> VerifyFuture + Send + Sync {
+        move |chain| {
+            Box::pin(async move {
+                assert!(!chain.is_empty());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                if reject {
+                    Err(Box::<dyn std::error::Error + Send + Sync>::from(
+                        "certificate revoked",
+                    ))
+                } else {
+                    Ok(())
+                }
+            })
+        }
+    }
+
+    async fn connect(
+        acceptor: &SslStreamAcceptor,
+        connector: &SslStreamConnector,
+    ) -> (
+        Result<SslStream<DuplexStream>, crate::Error>,
+        Result<SslStream<DuplexStream>, crate::Error>,
+    ) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        future::join(
+            connector.connect("localhost", client),
+            acceptor.accept(server),
+        )
+        .await
+    }
+
+    let acceptor = SslStreamAcceptor::from_acceptor(test_utils::acceptor().build());
+    let mut connectors = vec![];
+    for reject in [false, true] {
+        let mut connector = SslStreamConnector::builder(test_utils::connector());
+        connector.set_post_handshake_verify_callback(verifier(reject));
+        connectors.push((reject, connector.build()));
+
+        // the handshake is suspended during verification
+        #[cfg(ossl300)]
+        {
+            let mut connector = SslStreamConnector::builder(test_utils::connector());
+            connector
+                .set_async_verify_callback(verifier(reject))
+                .unwrap();
+            connectors.push((reject, connector.build()));
+        }
+    }
+    for (reject, connector) in connectors {
+        let (client, _) = connect(&acceptor, &connector).await;
+        match client {
+            Ok(_) => assert!(!reject),
+            Err(crate::Error::Verify(e)) => {
+                assert!(reject);
+                assert_eq!(e.to_string(), "certificate revoked");
+            }
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    // client certificates are verified once the handshake completes
+    let mut acceptor = SslStreamAcceptor::builder(test_utils::acceptor());
+    acceptor
+        .require_client_certificates("tests/cert.pem")
+        .unwrap();
+    acceptor.set_post_handshake_verify_callback(verifier(true));
+    let acceptor = acceptor.build();
+    let mut connector = test_utils::connector();
+    connector
+        .set_private_key_file("tests/key.pem", SslFiletype::PEM)
+        .unwrap();
+    connector
+        .set_certificate_chain_file("tests/cert.pem")
+        .unwrap();
+    let connector = SslStreamConnector::from_connector(connector.build());
+    match connect(&acceptor, &connector).await.1 {
+        Err(crate::Error::Verify(e)) => assert_eq!(e.to_string(), "certificate revoked"),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn raw_fd() {
+    use std::os::unix::io::AsRawFd;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let fd = stream.as_raw_fd();
+    let ssl = Ssl::new(test_utils::connector().build().context()).unwrap();
+    let stream = SslStream::new(ssl, stream).unwrap();
+
+    assert_eq!(stream.raw_fd(), fd);
+    assert_eq!(stream.as_raw_fd(), fd);
+}
+
+#[derive(Default)]
+struct RecordingHook {
+    read: Mutex<Vec<u8>>,
+    written: Mutex<Vec<u8>>,
+}
+
+impl IoHook for RecordingHook {
+    fn on_read(&self, bytes: &[u8]) {
+        self.read.lock().unwrap().extend_from_slice(bytes);
+    }
+
+    fn on_write(&self, bytes: &[u8]) {
+        self.written.lock().unwrap().extend_from_slice(bytes);
+    }
+}
+
+#[tokio::test]
+async fn io_hook() {
+    let (client, mut server) = connected_stream_pair().await;
+    let hook = RecordingHook::default();
+    let mut client = HookedSslStream::new(client, &hook);
+
+    let server = async move {
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        server.write_all(b"jkl;").await.unwrap();
+    };
+
+    let client = async {
+        client.write_all(b"asdf").await.unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).await.unwrap();
+    };
+
+    future::join(server, client).await;
+    assert_eq!(*hook.written.lock().unwrap(), b"asdf");
+    assert_eq!(*hook.read.lock().unwrap(), b"jkl;");
+}
+
+#[cfg(feature = "hooks")]
+#[tokio::test]
+async fn file_io_hook() {
+    use crate::FileIoHook;
+
+    let path = std::env::temp_dir().join(format!("tokio-openssl-hook-{}", std::process::id()));
+    let file = tokio::fs::File::create(&path).await.unwrap();
+
+    let (client, mut server) = connected_stream_pair().await;
+    let mut client = HookedSslStream::new(client, FileIoHook::new(file));
+
+    let server = async move {
+        let mut buf = [0; 18];
+        server.read_exact(&mut buf).await.unwrap();
+    };
+    let client = async move {
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    };
+    future::join(server, client).await;
+
+    // the dump is written in the background
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let dump = loop {
+        let dump = std::fs::read_to_string(&path).unwrap();
+        if dump.lines().count() == 2 {
+            break dump;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "plaintext not dumped: {:?}",
+            dump
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        dump,
+        "write 00000000  47 45 54 20 2f 20 48 54 54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|\n\
+         write 00000010  0d 0a                                            |..|\n"
+    );
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn keylog_file() {
+    let path = std::env::temp_dir().join(format!("tokio-openssl-keylog-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut acceptor = SslStreamAcceptor::builder(test_utils::acceptor());
+    acceptor.set_keylog_file(&path).unwrap();
+    let acceptor = acceptor.build();
+    let connector = SslStreamConnector::from_connector(test_utils::connector().build());
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let (client, server) = future::join(
+        connector.connect("localhost", client),
+        acceptor.accept(server),
+    )
+    .await;
+    client.unwrap();
+    server.unwrap();
+
+    // the lines are written in the background
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let log = loop {
+        let log = std::fs::read_to_string(&path).unwrap();
+        if log.contains("CLIENT_TRAFFIC_SECRET_0") && log.contains("SERVER_TRAFFIC_SECRET_0") {
+            break log;
+        }
+        assert!(Instant::now() < deadline, "secrets not logged: {:?}", log);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(log.ends_with('\n'));
+    assert!(log.contains("CLIENT_HANDSHAKE_TRAFFIC_SECRET "));
+    for line in log.lines() {
+        assert_eq!(line.split(' ').count(), 3, "{:?}", line);
+    }
+}
+
+#[cfg(all(unix, feature = "net"))]
+#[tokio::test]
+async fn take_error() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let ssl = Ssl::new(test_utils::connector().build().context()).unwrap();
+    let stream = SslStream::new(ssl, stream).unwrap();
+    assert!(stream.take_error().unwrap().is_none());
+
+    // closing with a zero linger timeout resets the connection
+    let (peer, _) = listener.accept().await.unwrap();
+    peer.set_linger(Some(Duration::from_secs(0))).unwrap();
+    drop(peer);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let e = loop {
+        if let Some(e) = stream.take_error().unwrap() {
+            break e;
+        }
+        assert!(Instant::now() < deadline, "connection reset not reported");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+    assert!(stream.take_error().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn sync_stream() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let acceptor = SslStreamAcceptor::from_acceptor(test_utils::acceptor().build());
+    let connector = SslStreamConnector::from_connector(test_utils::connector().build());
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = acceptor.accept(stream).await.unwrap();
+
+        stream.write_all(b"jkl;").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"asdf");
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = connector.connect("localhost", stream).await.unwrap();
+    let stream = Arc::new(SyncSslStream::new(stream));
+
+    let reader = tokio::spawn({
+        let stream = stream.clone();
+        async move {
+            let mut buf = [0; 4];
+            (&*stream).read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"jkl;");
+        }
+    });
+    let writer = tokio::spawn({
+        let stream = stream.clone();
+        async move {
+            (&*stream).write_all(b"asdf").await.unwrap();
+        }
+    });
+
+    reader.await.unwrap();
+    writer.await.unwrap();
+    server.await.unwrap();
+}
+
+#[cfg(ossl110)]
+#[tokio::test]
+async fn verified_chain() {
+    let (client, server) = connected_stream_pair().await;
+
+    assert_eq!(client.verify_result(), X509VerifyResult::OK);
+    let chain = client.verified_chain().unwrap().unwrap();
+    assert_eq!(chain.len(), 1);
+    assert_eq!(
+        chain[0].to_der().unwrap(),
+        client.peer_certificate().unwrap().to_der().unwrap()
+    );
+
+    // the server didn't request a client certificate
+    assert!(server.verified_chain().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn verify_result_self_signed() {
+    // the server's certificate is not trusted, but the callback accepts it anyway
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+    let (client, server) =
+        tls_stream_pair_with(&connector.build(), &test_utils::acceptor().build());
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        assert_eq!(
+            stream.verify_result(),
+            X509VerifyResult::from_raw(openssl_sys::X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT)
+        );
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn record_mac_failure_message() {
+    let (client, mid_client) = tokio::io::duplex(64 * 1024);
+    let (mid_server, server) = tokio::io::duplex(64 * 1024);
+    let corrupt = Arc::new(AtomicBool::new(false));
+
+    // relay the transports, flipping the last byte sent by the client once corruption is enabled
+    let (mut client_read, mut client_write) = tokio::io::split(mid_client);
+    let (mut server_read, mut server_write) = tokio::io::split(mid_server);
+    tokio::spawn({
+        let corrupt = corrupt.clone();
+        async move {
+            let mut buf = [0; 4096];
+            loop {
+                let n = match client_read.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                if corrupt.load(Ordering::SeqCst) {
+                    buf[n - 1] ^= 1;
+                }
+                if server_write.write_all(&buf[..n]).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    tokio::spawn(async move { tokio::io::copy(&mut server_read, &mut client_write).await });
+
+    let connector = test_utils::connector().build();
+    let ssl = connector
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    let mut client = SslStream::new(ssl, client).unwrap();
+    let acceptor = test_utils::acceptor().build();
+    let ssl = Ssl::new(acceptor.context()).unwrap();
+    let mut server = SslStream::new(ssl, server).unwrap();
+
+    connect_pair(&mut client, &mut server).await;
+
+    corrupt.store(true, Ordering::SeqCst);
+    client.write_all(b"asdf").await.unwrap();
+
+    let mut buf = [0; 4];
+    let err = server.read_exact(&mut buf).await.unwrap_err();
+    assert!(
+        err.to_string().contains("bad record mac"),
+        "unexpected error: {}",
+        err
+    );
+    match err.get_ref().and_then(|e| e.downcast_ref::<crate::Error>()) {
+        Some(crate::Error::Ssl(e)) => assert_eq!(e.code(), ErrorCode::SSL),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn handshake_error_verify_result() {
+    // doesn't trust the test certificate
+    let connector = SslConnector::builder(SslMethod::tls()).unwrap().build();
+    let (client, server) = tls_stream_pair_with(&connector, &test_utils::acceptor().build());
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap_err();
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        let err = Pin::new(&mut stream).connect().await.unwrap_err();
+        let err = crate::HandshakeError::new(stream.ssl(), err);
+        assert_eq!(
+            err.verify_result(),
+            X509VerifyResult::from_raw(openssl_sys::X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT)
+        );
+        assert!(err.to_string().contains("certificate verify failed"));
+
+        #[cfg(ossl300)]
+        {
+            let leaf =
+                openssl::x509::X509::from_pem(&std::fs::read("tests/cert.pem").unwrap()).unwrap();
+            assert_eq!(
+                err.peer_certificates()[0].to_der().unwrap(),
+                leaf.to_der().unwrap()
+            );
+        }
+        stream
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn shutdown_tls() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"last words");
+
+        let r = Pin::new(&mut stream).shutdown_tls().await.unwrap();
+        assert_eq!(r, ShutdownResult::Received);
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        stream.write_all(b"last words").await.unwrap();
+        let r = Pin::new(&mut stream).shutdown_tls().await.unwrap();
+        assert_eq!(r, ShutdownResult::Sent);
+
+        let r = Pin::new(&mut stream).shutdown_tls().await.unwrap();
+        assert_eq!(r, ShutdownResult::Received);
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn ssl_write_all() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf.len(), 1024 * 1024);
+        assert!(buf.iter().enumerate().all(|(i, &b)| b == i as u8));
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        let buf = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        Pin::new(&mut stream).ssl_write_all(&buf).await.unwrap();
+        stream.shutdown().await.unwrap();
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn shutdown_flushes_buffered_transport() {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+
+    let acceptor = test_utils::acceptor().build();
+    let ssl = Ssl::new(acceptor.context()).unwrap();
+    let mut server = SslStream::new(ssl, server).unwrap();
+
+    let ssl = test_utils::connector()
+        .build()
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    // OpenSSL doesn't flush after writing a close notify, so it sits in the buffer unless
+    // poll_shutdown flushes it explicitly
+    let mut client = SslStream::new(ssl, BufWriter::new(client)).unwrap();
+
+    connect_pair(&mut client, &mut server).await;
+
+    let server = async {
+        let mut buf = vec![];
+        server.read_to_end(&mut buf).await.unwrap();
+        server.shutdown().await.unwrap();
+    };
+
+    let client = async move {
+        client.shutdown().await.unwrap();
+    };
+
+    tokio::time::timeout(Duration::from_secs(10), future::join(server, client))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn write_shutdown() {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+
+    let acceptor = test_utils::acceptor().build();
+    let ssl = Ssl::new(acceptor.context()).unwrap();
+    let mut server = SslStream::new(ssl, server).unwrap();
+
+    let ssl = test_utils::connector()
+        .build()
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    // the close notify only reaches the server if it is flushed out of the buffer
+    let mut client = SslStream::new(ssl, BufWriter::new(client)).unwrap();
+
+    connect_pair(&mut client, &mut server).await;
+
+    let server = async {
+        let mut buf = vec![];
+        server.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"request");
+
+        server.write_all(b"response").await.unwrap();
+        server.shutdown().await.unwrap();
+    };
+
+    let client = async move {
+        client.write_all(b"request").await.unwrap();
+        Pin::new(&mut client).write_shutdown().await.unwrap();
+        client.write_all(b"more").await.unwrap_err();
+
+        let mut buf = vec![];
+        client.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"response");
+        client.shutdown().await.unwrap();
+    };
+
+    tokio::time::timeout(Duration::from_secs(10), future::join(server, client))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_transport() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        // the client shuts down the transport after its close notify
+        let mut buf = [0; 1];
+        assert_eq!(stream.get_mut().read(&mut buf).await.unwrap(), 0);
+    };
+
+    let client = async move {
+        let mut stream = client;
+        assert!(!stream.shutdown_transport());
+        stream.set_shutdown_transport(true);
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        stream.shutdown().await.unwrap();
+        stream.get_mut().write_all(b"x").await.unwrap_err();
+    };
+
+    future::join(server, client).await;
+}
+
+#[cfg(feature = "net")]
+#[tokio::test]
+async fn ssl_listener() {
+    use crate::SslListener;
+
+    // this exercises the TcpListener integration, so it can't use an in-memory transport
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let acceptor = SslStreamAcceptor::builder(test_utils::acceptor()).build();
+    let mut listener = SslListener::new(listener, acceptor);
+
+    let server = async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream.write_all(b"asdf").await.unwrap();
+
+        let (mut stream, _) = listener.next().await.unwrap().unwrap();
+        stream.write_all(b"jkl;").await.unwrap();
+    };
+
+    let client = async {
+        let connector = test_utils::connector().build();
+
+        for expected in &[b"asdf", b"jkl;"] {
+            let ssl = connector
+                .configure()
+                .unwrap()
+                .into_ssl("localhost")
+                .unwrap();
+            let stream = TcpStream::connect(&addr).await.unwrap();
+            let mut stream = SslStream::new(ssl, stream).unwrap();
+            stream.connect().await.unwrap();
+
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, *expected);
+        }
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn shutdown_timeout() {
+    let (client, server) = tls_stream_pair();
+    let (tx, rx) = oneshot::channel::<()>();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        // never respond to the client's close notify
+        let _ = rx.await;
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        let start = Instant::now();
+        let acked = Pin::new(&mut stream)
+            .shutdown_timeout(Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(!acked);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        tx.send(()).unwrap();
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn read_timeout() {
+    let (client, server) = tls_stream_pair();
+    let (tx, rx) = oneshot::channel::<()>();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+
+        // go quiet without closing the connection
+        let _ = rx.await;
+    };
+
+    let client = async move {
+        let mut stream = client;
+        stream.set_read_timeout(Some(Duration::from_millis(100)));
+        assert_eq!(stream.read_timeout(), Some(Duration::from_millis(100)));
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let start = Instant::now();
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        tx.send(()).unwrap();
+    };
+
+    future::join(server, client).await;
+}
+
+#[tokio::test]
+async fn half_close() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"GET / HTTP/1.0\r\n\r\n");
+
+        // keep streaming the response after the client's close notify
+        for i in 0..64 {
+            stream.write_all(&[i; 4096]).await.unwrap();
+        }
+        stream.shutdown().await.unwrap();
+    };
+
+    let client = async move {
+        let mut stream = client;
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        stream.write_all(b"GET / HTTP/1.0\r\n\r\n").await.unwrap();
+        Pin::new(&mut stream).send_close_notify().await.unwrap();
+        Pin::new(&mut stream).send_close_notify().await.unwrap();
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf.len(), 64 * 4096);
+        assert!(buf
+            .chunks(4096)
+            .enumerate()
+            .all(|(i, c)| c.iter().all(|&b| b == i as u8)));
+
+        stream.shutdown().await.unwrap();
+    };
+
+    future::join(server, client).await;
+}
+
+#[cfg(feature = "dtls")]
+#[test]
+fn mtu_is_rejected_for_tls() {
+    let (mut client, _) = tls_stream_pair();
+    assert_eq!(client.mtu(), None);
+    client.set_mtu(1200).unwrap_err();
+    assert_eq!(client.mtu(), None);
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn metrics() {
+    // other tests run concurrently, so only lower bounds can be checked
+    let before = crate::metrics::snapshot();
+
+    let (mut client, mut server) = connected_stream_pair().await;
+    client.write_all(b"asdf").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+
+    let after = crate::metrics::snapshot();
+    assert!(after.handshakes_total >= before.handshakes_total + 2);
+    assert!(after.bytes_written_total >= before.bytes_written_total + 4);
+    assert!(after.bytes_read_total >= before.bytes_read_total + 4);
+}
+
+#[tokio::test]
+async fn transport_write_zero() {
+    let (mut client, _server) = connected_faulty_stream_pair().await;
+
+    client.get_mut().fault = Some(WriteFault::Zero);
+    let err = client.write(b"asdf").await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+}
+
+#[tokio::test]
+async fn transport_panic() {
+    let (mut client, _server) = connected_faulty_stream_pair().await;
+
+    client.get_mut().fault = Some(WriteFault::Panic);
+    let r = AssertUnwindSafe(client.write(b"asdf")).catch_unwind().await;
+    assert!(r.is_err());
+
+    // the context pointer must not be left dangling by the unwind
+    assert!(client.inner.get_ref().context.is_none());
+    client.get_mut().fault = None;
+    assert!(client.is_handshake_done());
+    drop(client);
+}
+
+#[tokio::test]
+async fn verify_callback_panic() {
+    use foreign_types::ForeignTypeRef;
+    use std::os::raw::c_int;
+
+    type VerifyCallback = extern "C-unwind" fn(c_int, *mut openssl_sys::X509_STORE_CTX) -> c_int;
+
+    extern "C" {
+        fn SSL_set_verify(ssl: *mut openssl_sys::SSL, mode: c_int, cb: Option<VerifyCallback>);
+    }
+
+    // The openssl crate calls closures registered with `set_verify_callback` from an
+    // `extern "C"` function, so a panic in one aborts the process rather than unwinding.
+    // Registering the callback directly lets the panic unwind through OpenSSL into `connect`,
+    // which relies on OpenSSL having been built with unwind tables.
+    extern "C-unwind" fn verify(_: c_int, _: *mut openssl_sys::X509_STORE_CTX) -> c_int {
+        panic!("verify callback failed");
+    }
+
+    let (mut client, mut server) = tls_stream_pair();
+    unsafe {
+        SSL_set_verify(
+            client.ssl_mut().as_ptr(),
+            SslVerifyMode::PEER.bits(),
+            Some(verify),
+        )
+    };
+
+    let server = tokio::spawn(async move {
+        let _ = Pin::new(&mut server).accept().await;
+    });
+
+    let r = AssertUnwindSafe(Pin::new(&mut client).connect())
+        .catch_unwind()
+        .await;
+    let payload = r.unwrap_err();
+    assert_eq!(
+        payload.downcast_ref::<&str>(),
+        Some(&"verify callback failed")
+    );
+
+    // the context pointer must not be left dangling by the unwind
+    assert!(client.inner.get_ref().context.is_none());
+    assert!(!client.is_handshake_done());
+    drop(client);
+
+    server.await.unwrap();
+}
+
+#[cfg(ossl110)]
+#[tokio::test]
+async fn into_std() {
+    let (client, mut server) = connected_faulty_stream_pair().await;
+    let mut client = client.into_std().unwrap();
+
+    io::Write::write_all(&mut client, b"asdf").unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"asdf");
+
+    server.write_all(b"jkl;").await.unwrap();
+    io::Read::read_exact(&mut client, &mut buf).unwrap();
+    assert_eq!(&buf, b"jkl;");
+}
+
+#[cfg(ossl110)]
+#[tokio::test]
+async fn from_std() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = async move {
+        let acceptor = SslStreamAcceptor::from_acceptor(test_utils::acceptor().build());
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = acceptor.accept(stream).await.unwrap();
+
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"asdf");
+        stream.write_all(b"jkl;").await.unwrap();
+    };
+
+    let client = async move {
+        let sync = tokio::task::spawn_blocking(move || {
+            let stream = std::net::TcpStream::connect(addr).unwrap();
+            test_utils::connector()
+                .build()
+                .connect("localhost", stream)
+                .unwrap()
+        })
+        .await
+        .unwrap();
+
+        let transport = sync.get_ref().try_clone().unwrap();
+        transport.set_nonblocking(true).unwrap();
+        let transport = TcpStream::from_std(transport).unwrap();
+        let mut stream = SslStream::from_std(sync, transport).unwrap();
+        assert!(stream.is_handshake_done());
+
+        stream.write_all(b"asdf").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"jkl;");
+    };
+
+    future::join(server, client).await;
+}
+
+#[cfg(ossl110)]
+#[test]
+fn into_std_before_handshake() {
+    let (stream, _peer) = tokio::io::duplex(64);
+    let stream = test_utils::FaultyStream {
+        inner: stream,
+        fault: None,
+    };
+    let ssl = Ssl::new(test_utils::acceptor().build().context()).unwrap();
+    let stream = SslStream::new(ssl, stream).unwrap();
+
+    let e = stream.into_std().unwrap_err();
+    assert_eq!(io::Error::from(e).kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn stream_wrapper_context() {
+    let mut wrapper = StreamWrapper::new(&b"asdf"[..]);
+    let mut buf = [0; 4];
+
+    // I/O outside of a poll fails rather than using a stale context
+    assert!(io::Read::read(&mut wrapper, &mut buf).is_err());
+
+    let waker = futures_util::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    unsafe { wrapper.set_context(&mut cx) };
+    assert_eq!(io::Read::read(&mut wrapper, &mut buf).unwrap(), 4);
+    assert_eq!(&buf, b"asdf");
+    wrapper.clear_context();
+
+    assert!(io::Read::read(&mut wrapper, &mut buf).is_err());
+}
+
+#[test]
+fn stream_wrapper_would_block() {
+    let (stream, _peer) = tokio::io::duplex(4);
+    let mut wrapper = StreamWrapper::new(stream);
+
+    let waker = futures_util::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    unsafe { wrapper.set_context(&mut cx) };
+    assert_eq!(io::Write::write(&mut wrapper, b"asdfjkl;").unwrap(), 4);
+    let err = io::Write::write(&mut wrapper, b"jkl;").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    wrapper.clear_context();
+}
+
+#[tokio::test]
+async fn cipher_and_protocol_version() {
+    let (mut client, mut server) = tls_stream_pair();
+    assert!(client.cipher().is_none());
+
+    connect_pair(&mut client, &mut server).await;
+
+    assert!(client.cipher().is_some());
+    #[cfg(ossl111)]
+    assert_eq!(client.protocol_version(), Some(ssl::SslVersion::TLS1_3));
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn connection_info() {
+    let mut acceptor = test_utils::acceptor();
+    acceptor.set_alpn_select_callback(|_, client| {
+        ssl::select_next_proto(b"\x02h2", client).ok_or(AlpnError::NOACK)
+    });
+    let mut connector = test_utils::connector();
+    connector.set_alpn_protos(b"\x02h2\x08http/1.1").unwrap();
+    let (mut client, mut server) = tls_stream_pair_with(&connector.build(), &acceptor.build());
+    assert!(client.connection_info().is_none());
+
+    connect_pair(&mut client, &mut server).await;
+
+    let info = client.connection_info().unwrap();
+    assert_eq!(info.version, ssl::SslVersion::TLS1_3);
+    assert_eq!(info.cipher, client.cipher().unwrap().name());
+    assert_eq!(info.alpn.as_deref(), Some(&b"h2"[..]));
+    assert!(!info.session_reused);
+    assert!(info.peer_certificate.is_some());
+
+    let info = server.connection_info().unwrap();
+    assert_eq!(info.sni.as_deref(), Some("localhost"));
+    assert!(info.peer_certificate.is_none());
+}
+
+#[test]
+fn stream_id() {
+    let (client, server) = tls_stream_pair();
+    assert_ne!(client.id(), server.id());
+
+    // moving the stream doesn't change its ID
+    let id = client.id();
+    let client = Box::new(client);
+    assert_eq!(client.id(), id);
+}
+
+#[tokio::test]
+async fn new_with_mode() {
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+
+    let ssl = test_utils::connector()
+        .build()
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    let mut client = SslStream::new_with_mode(ssl, client_stream, HandshakeMode::Client).unwrap();
+    let acceptor = test_utils::acceptor().build();
+    let ssl = Ssl::new(acceptor.context()).unwrap();
+    let mut server = SslStream::new_with_mode(ssl, server_stream, HandshakeMode::Server).unwrap();
+    assert!(server.is_server_side());
+
+    let (r1, r2) = future::join(client.do_handshake(), server.do_handshake()).await;
+    r1.unwrap();
+    r2.unwrap();
+
+    client.write_all(b"asdf").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"asdf");
+}
+
+#[tokio::test]
+async fn quiet_shutdown() {
+    let (client, server) = tls_stream_pair();
+
+    let server = async move {
+        let mut stream = server;
+        stream.set_quiet_shutdown(true);
+        assert!(stream.quiet_shutdown());
+
+        Pin::new(&mut stream).accept().await.unwrap();
+
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"asdf");
+        stream.write_all(b"jkl;").await.unwrap();
+
+        // the client shuts down its transport without sending a close notify
+        let mut buf = vec![];
+        stream.get_mut().read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+
+        stream.shutdown().await.unwrap();
+    };
+
+    let client = async move {
+        let mut stream = client;
+        stream.set_quiet_shutdown(true);
+
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        stream.write_all(b"asdf").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"jkl;");
+
+        stream.shutdown().await.unwrap();
+
+        let mut buf = vec![];
+        stream.get_mut().read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+    };
+
+    future::join(server, client).await;
+}
+
+#[test]
+fn poll_from_ssl_result() {
+    let ssl = test_utils::connector()
+        .build()
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    let (client, _server) = tokio::io::duplex(64 * 1024);
+    let stream = FaultyStream {
+        inner: client,
+        fault: None,
+    };
+    let mut stream = ssl::SslStream::new(ssl, stream).unwrap();
+
+    // the server never responds, so the handshake would block
+    assert!(crate::poll_from_ssl_result(stream.connect()).is_pending());
+    assert!(crate::poll_from_io_result::<()>(Err(io::ErrorKind::WouldBlock.into())).is_pending());
+}
+
+#[tokio::test]
+async fn cert_callback_retry() {
+    use std::os::raw::{c_int, c_void};
+
+    extern "C" {
+        fn SSL_CTX_set_cert_cb(
+            ctx: *mut openssl_sys::SSL_CTX,
+            cb: Option<unsafe extern "C" fn(*mut openssl_sys::SSL, *mut c_void) -> c_int>,
+            arg: *mut c_void,
+        );
+    }
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    // asks OpenSSL to retry the first time it's called
+    unsafe extern "C" fn cert_cb(_: *mut openssl_sys::SSL, _: *mut c_void) -> c_int {
+        if CALLS.fetch_add(1, Ordering::SeqCst) == 0 {
+            -1
+        } else {
+            1
+        }
+    }
+
+    let mut acceptor = test_utils::acceptor();
+    unsafe { SSL_CTX_set_cert_cb(acceptor.as_ptr(), Some(cert_cb), std::ptr::null_mut()) };
+    let (mut client, mut server) =
+        tls_stream_pair_with(&test_utils::connector().build(), &acceptor.build());
+
+    connect_pair(&mut client, &mut server).await;
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(ossl111)]
+#[tokio::test]
+async fn client_hello_callback_retry() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut acceptor = test_utils::acceptor();
+    acceptor.set_client_hello_callback({
+        let calls = calls.clone();
+        move |_, _| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => Ok(ssl::ClientHelloResponse::RETRY),
+            _ => Ok(ssl::ClientHelloResponse::SUCCESS),
+        }
+    });
+    let (mut client, mut server) =
+        tls_stream_pair_with(&test_utils::connector().build(), &acceptor.build());
+
+    connect_pair(&mut client, &mut server).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test]
+async fn connector_service() {
+    use crate::ConnectorService;
+    use tower_service::Service;
+
+    struct TcpConnect;
+
+    impl Service<http::Uri> for TcpConnect {
+        type Response = TcpStream;
+        type Error = io::Error;
+        type Future = Pin<Box<dyn std::future::Future<Output = io::Result<TcpStream>> + Send>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, uri: http::Uri) -> Self::Future {
+            let addr = format!("{}:{}", uri.host().unwrap(), uri.port_u16().unwrap());
+            Box::pin(TcpStream::connect(addr))
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let acceptor = SslStreamAcceptor::from_acceptor(test_utils::acceptor().build());
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = acceptor.accept(stream).await.unwrap();
+        stream.write_all(b"asdf").await.unwrap();
+    });
+
+    let connector = SslStreamConnector::from_connector(test_utils::connector().build());
+    let mut service = ConnectorService::new(TcpConnect, connector);
+    future::poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+    let uri = format!("https://localhost:{}", port).parse().unwrap();
+    let mut stream = service.call(uri).await.unwrap();
+
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"asdf");
+    server.await.unwrap();
+
+    let uri = "/path".parse().unwrap();
+    assert!(service.call(uri).await.is_err());
+}
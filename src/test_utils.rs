@@ -0,0 +1,215 @@
+use crate::SslStream;
+use futures_util::future;
+use futures_util::task::noop_waker_ref;
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{
+    Ssl, SslAcceptor, SslAcceptorBuilder, SslConnector, SslConnectorBuilder, SslFiletype, SslMethod,
+};
+use openssl::x509::{X509Builder, X509NameBuilder, X509};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+
+/// Returns an acceptor builder configured with the test certificate.
+pub(crate) fn acceptor() -> SslAcceptorBuilder {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor
+        .set_private_key_file("tests/key.pem", SslFiletype::PEM)
+        .unwrap();
+    acceptor
+        .set_certificate_chain_file("tests/cert.pem")
+        .unwrap();
+    acceptor
+}
+
+/// Returns a connector builder which trusts the test certificate.
+pub(crate) fn connector() -> SslConnectorBuilder {
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_ca_file("tests/cert.pem").unwrap();
+    connector
+}
+
+/// Returns a `(client, server)` pair of streams connected over an in-memory transport.
+///
+/// The handshake has not been performed.
+pub(crate) fn tls_stream_pair() -> (SslStream<DuplexStream>, SslStream<DuplexStream>) {
+    tls_stream_pair_with(&connector().build(), &acceptor().build())
+}
+
+/// Like [`tls_stream_pair`], but with the handshake already completed.
+pub(crate) async fn connected_stream_pair() -> (SslStream<DuplexStream>, SslStream<DuplexStream>) {
+    let (mut client, mut server) = tls_stream_pair();
+    connect_pair(&mut client, &mut server).await;
+    (client, server)
+}
+
+/// Completes the handshake between `client` and `server`, panicking if either side fails.
+pub(crate) async fn connect_pair<C, S>(client: &mut SslStream<C>, server: &mut SslStream<S>)
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (r1, r2) = future::join(
+        Pin::new(&mut *client).connect(),
+        Pin::new(&mut *server).accept(),
+    )
+    .await;
+    r1.unwrap();
+    r2.unwrap();
+}
+
+/// Like [`tls_stream_pair`], but with a custom connector and acceptor.
+pub(crate) fn tls_stream_pair_with(
+    connector: &SslConnector,
+    acceptor: &SslAcceptor,
+) -> (SslStream<DuplexStream>, SslStream<DuplexStream>) {
+    let client = connector
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    let server = Ssl::new(acceptor.context()).unwrap();
+    ssl_stream_pair(client, server)
+}
+
+/// Like [`tls_stream_pair`], but with custom client and server `Ssl`s.
+pub(crate) fn ssl_stream_pair(
+    client: Ssl,
+    server: Ssl,
+) -> (SslStream<DuplexStream>, SslStream<DuplexStream>) {
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+    (
+        SslStream::new(client, client_stream).unwrap(),
+        SslStream::new(server, server_stream).unwrap(),
+    )
+}
+
+/// A misbehavior of [`FaultyStream`] writes.
+pub(crate) enum WriteFault {
+    /// Report that zero bytes were written.
+    Zero,
+    /// Panic.
+    Panic,
+}
+
+/// An in-memory transport whose writes misbehave while `fault` is set.
+pub(crate) struct FaultyStream {
+    pub(crate) inner: DuplexStream,
+    pub(crate) fault: Option<WriteFault>,
+}
+
+impl AsyncRead for FaultyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for FaultyStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.fault {
+            Some(WriteFault::Zero) => Poll::Ready(Ok(0)),
+            Some(WriteFault::Panic) => panic!("transport write failed"),
+            None => Pin::new(&mut self.inner).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// Non-blocking std I/O, so the stream can back a sync `ssl::SslStream` in tests.
+impl io::Read for FaultyStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut buf = ReadBuf::new(buf);
+        match Pin::new(self).poll_read(&mut cx, &mut buf) {
+            Poll::Ready(r) => r.map(|()| buf.filled().len()),
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl io::Write for FaultyStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match Pin::new(self).poll_write(&mut cx, buf) {
+            Poll::Ready(r) => r,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Like [`connected_stream_pair`], but the client's transport is a [`FaultyStream`].
+pub(crate) async fn connected_faulty_stream_pair(
+) -> (SslStream<FaultyStream>, SslStream<DuplexStream>) {
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+
+    let client = connector()
+        .build()
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+    let client_stream = FaultyStream {
+        inner: client_stream,
+        fault: None,
+    };
+    let mut client = SslStream::new(client, client_stream).unwrap();
+    let server = Ssl::new(acceptor().build().context()).unwrap();
+    let mut server = SslStream::new(server, server_stream).unwrap();
+
+    connect_pair(&mut client, &mut server).await;
+
+    (client, server)
+}
+
+/// Returns a new self-signed certificate for `cn` and its private key.
+pub(crate) fn self_signed_cert(cn: &str) -> (X509, PKey<Private>) {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_nid(Nid::COMMONNAME, cn).unwrap();
+    let name = name.build();
+
+    let mut serial = BigNum::new().unwrap();
+    serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+
+    let mut cert = X509Builder::new().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_serial_number(&serial.to_asn1_integer().unwrap())
+        .unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+    (cert.build(), key)
+}
@@ -0,0 +1,324 @@
+use crate::ex_data::ExIndex;
+use foreign_types::ForeignTypeRef;
+use openssl::error::ErrorStack;
+use openssl::rand;
+use openssl::ssl::{SslContext, SslContextBuilder, SslRef};
+#[cfg(not(ossl300))]
+use openssl_sys::HMAC_CTX;
+use openssl_sys::{EVP_CIPHER_CTX, SSL};
+#[cfg(ossl300)]
+use openssl_sys::{OSSL_PARAM, SSL_CTX};
+#[cfg(ossl300)]
+use std::os::raw::c_char;
+#[cfg(not(ossl300))]
+use std::os::raw::c_void;
+use std::os::raw::{c_int, c_uchar};
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+#[cfg(feature = "rt")]
+use std::sync::Weak;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "rt")]
+use std::time::Duration;
+#[cfg(feature = "rt")]
+use tokio::task::JoinHandle;
+
+const NAME_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+/// The IV length of AES-256-CBC.
+const IV_LEN: usize = 16;
+
+/// `SSL_CTRL_SET_TLSEXT_TICKET_KEY_CB` from `<openssl/ssl.h>`, the control behind the
+/// `SSL_CTX_set_tlsext_ticket_key_cb` macro.
+#[cfg(not(ossl300))]
+const SSL_CTRL_SET_TLSEXT_TICKET_KEY_CB: c_int = 72;
+
+#[cfg(not(ossl300))]
+type TicketKeyCallback = unsafe extern "C" fn(
+    *mut SSL,
+    *mut c_uchar,
+    *mut c_uchar,
+    *mut EVP_CIPHER_CTX,
+    *mut HMAC_CTX,
+    c_int,
+) -> c_int;
+
+#[cfg(ossl300)]
+#[allow(non_camel_case_types)]
+enum EVP_MAC_CTX {}
+
+#[cfg(ossl300)]
+type TicketKeyEvpCallback = unsafe extern "C" fn(
+    *mut SSL,
+    *mut c_uchar,
+    *mut c_uchar,
+    *mut EVP_CIPHER_CTX,
+    *mut EVP_MAC_CTX,
+    c_int,
+) -> c_int;
+
+// declared in `<openssl/ssl.h>` and `<openssl/evp.h>`, but not by openssl-sys
+#[cfg(ossl300)]
+extern "C" {
+    fn SSL_CTX_set_tlsext_ticket_key_evp_cb(
+        ctx: *mut SSL_CTX,
+        fp: Option<TicketKeyEvpCallback>,
+    ) -> c_int;
+    fn EVP_MAC_init(
+        ctx: *mut EVP_MAC_CTX,
+        key: *const c_uchar,
+        keylen: usize,
+        params: *const OSSL_PARAM,
+    ) -> c_int;
+}
+
+struct TicketKey {
+    name: [u8; NAME_LEN],
+    cipher_key: [u8; KEY_LEN],
+    hmac_key: [u8; KEY_LEN],
+}
+
+impl TicketKey {
+    fn generate() -> Result<TicketKey, ErrorStack> {
+        let mut key = TicketKey {
+            name: [0; NAME_LEN],
+            cipher_key: [0; KEY_LEN],
+            hmac_key: [0; KEY_LEN],
+        };
+        rand::rand_bytes(&mut key.name)?;
+        rand::rand_bytes(&mut key.cipher_key)?;
+        rand::rand_bytes(&mut key.hmac_key)?;
+        Ok(key)
+    }
+}
+
+impl Drop for TicketKey {
+    fn drop(&mut self) {
+        for byte in self.cipher_key.iter_mut().chain(&mut self.hmac_key) {
+            // SAFETY: the pointer comes from a unique reference. Volatile writes can't be elided.
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+struct Keys {
+    current: TicketKey,
+    previous: Option<TicketKey>,
+}
+
+/// Manages the keys used to encrypt TLS session tickets issued by a server.
+///
+/// OpenSSL generates a ticket key when a context is created and uses it for the context's whole
+/// lifetime, so a compromise of the key exposes every session resumed with its tickets. A
+/// `TicketKeyManager` instead uses keys which are [rotated](Self::rotate) periodically. Tickets
+/// encrypted with the previous key are still accepted, and renewed with the current key, so that
+/// tickets survive a single rotation.
+///
+/// Keys are generated with OpenSSL's RNG and zeroed when they are discarded.
+pub struct TicketKeyManager {
+    keys: Mutex<Keys>,
+}
+
+impl TicketKeyManager {
+    /// Creates a new manager with a freshly generated key.
+    pub fn new() -> Result<Arc<TicketKeyManager>, ErrorStack> {
+        Ok(Arc::new(TicketKeyManager {
+            keys: Mutex::new(Keys {
+                current: TicketKey::generate()?,
+                previous: None,
+            }),
+        }))
+    }
+
+    /// Configures `builder` to encrypt and decrypt session tickets with this manager's keys.
+    ///
+    /// A manager can be installed on several contexts. If an acceptor switches contexts based on
+    /// the server name, the manager should be installed on each of them.
+    ///
+    /// This replaces any ticket key callback previously configured on the builder.
+    pub fn install(self: &Arc<Self>, builder: &mut SslContextBuilder) -> Result<(), ErrorStack> {
+        builder.set_ex_data(INDEX.index()?, self.clone());
+        // SAFETY: the callback has the signature OpenSSL expects of a ticket key callback.
+        unsafe { set_ticket_key_callback(builder) }
+    }
+
+    /// Replaces the current key with a newly generated one.
+    ///
+    /// The current key is retained to decrypt existing tickets until the next rotation, while
+    /// the key before it is discarded.
+    pub fn rotate(&self) -> Result<(), ErrorStack> {
+        let key = TicketKey::generate()?;
+        let mut keys = self.keys.lock().unwrap();
+        let previous = std::mem::replace(&mut keys.current, key);
+        keys.previous = Some(previous);
+        Ok(())
+    }
+
+    /// Spawns a task onto the current Tokio runtime which rotates the keys every `period`.
+    ///
+    /// The task exits once the manager is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime, or if `period` is zero.
+    #[cfg(feature = "rt")]
+    pub fn spawn_rotation(self: &Arc<Self>, period: Duration) -> JoinHandle<()> {
+        let manager = Arc::downgrade(self);
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+                match Weak::upgrade(&manager) {
+                    Some(manager) => {
+                        // on failure, keep using the current key until the next attempt
+                        let _ = manager.rotate();
+                    }
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+static INDEX: ExIndex<SslContext, Arc<TicketKeyManager>> = ExIndex::new();
+
+#[cfg(not(ossl300))]
+unsafe fn set_ticket_key_callback(builder: &mut SslContextBuilder) -> Result<(), ErrorStack> {
+    let callback: TicketKeyCallback = ticket_key_callback;
+    // `SSL_CTX_callback_ctrl` takes every callback as a generic function pointer
+    openssl_sys::SSL_CTX_callback_ctrl(
+        builder.as_ptr(),
+        SSL_CTRL_SET_TLSEXT_TICKET_KEY_CB,
+        Some(std::mem::transmute::<TicketKeyCallback, extern "C" fn()>(
+            callback,
+        )),
+    );
+    Ok(())
+}
+
+#[cfg(ossl300)]
+unsafe fn set_ticket_key_callback(builder: &mut SslContextBuilder) -> Result<(), ErrorStack> {
+    if SSL_CTX_set_tlsext_ticket_key_evp_cb(builder.as_ptr(), Some(ticket_key_evp_callback)) != 1 {
+        return Err(ErrorStack::get());
+    }
+    Ok(())
+}
+
+#[cfg(not(ossl300))]
+unsafe extern "C" fn ticket_key_callback(
+    ssl: *mut SSL,
+    key_name: *mut c_uchar,
+    iv: *mut c_uchar,
+    cipher_ctx: *mut EVP_CIPHER_CTX,
+    hmac_ctx: *mut HMAC_CTX,
+    enc: c_int,
+) -> c_int {
+    handle_ticket_key(ssl, key_name, iv, cipher_ctx, enc, |key| {
+        openssl_sys::HMAC_Init_ex(
+            hmac_ctx,
+            key.hmac_key.as_ptr() as *const c_void,
+            KEY_LEN as c_int,
+            openssl_sys::EVP_sha256(),
+            ptr::null_mut(),
+        )
+    })
+}
+
+#[cfg(ossl300)]
+unsafe extern "C" fn ticket_key_evp_callback(
+    ssl: *mut SSL,
+    key_name: *mut c_uchar,
+    iv: *mut c_uchar,
+    cipher_ctx: *mut EVP_CIPHER_CTX,
+    mac_ctx: *mut EVP_MAC_CTX,
+    enc: c_int,
+) -> c_int {
+    handle_ticket_key(ssl, key_name, iv, cipher_ctx, enc, |key| {
+        let params = [
+            openssl_sys::OSSL_PARAM_construct_utf8_string(
+                b"digest\0".as_ptr() as *const c_char,
+                b"SHA256\0".as_ptr() as *mut c_char,
+                0,
+            ),
+            openssl_sys::OSSL_PARAM_construct_end(),
+        ];
+        EVP_MAC_init(mac_ctx, key.hmac_key.as_ptr(), KEY_LEN, params.as_ptr())
+    })
+}
+
+/// Sets up the ticket encryption of a connection with one of the manager's keys, calling
+/// `init_hmac` to key the ticket's MAC.
+unsafe fn handle_ticket_key<F>(
+    ssl: *mut SSL,
+    key_name: *mut c_uchar,
+    iv: *mut c_uchar,
+    cipher_ctx: *mut EVP_CIPHER_CTX,
+    enc: c_int,
+    init_hmac: F,
+) -> c_int
+where
+    F: Fn(&TicketKey) -> c_int,
+{
+    let ssl = SslRef::from_ptr(ssl);
+    let manager = match INDEX
+        .get()
+        .and_then(|index| ssl.ssl_context().ex_data(index))
+    {
+        Some(manager) => manager,
+        None => return -1,
+    };
+    let keys = match manager.keys.lock() {
+        Ok(keys) => keys,
+        Err(_) => return -1,
+    };
+    let key_name = &mut *(key_name as *mut [u8; NAME_LEN]);
+
+    if enc == 1 {
+        let key = &keys.current;
+        let iv = std::slice::from_raw_parts_mut(iv, IV_LEN);
+        if rand::rand_bytes(iv).is_err() {
+            return -1;
+        }
+        *key_name = key.name;
+        if openssl_sys::EVP_EncryptInit_ex(
+            cipher_ctx,
+            openssl_sys::EVP_aes_256_cbc(),
+            ptr::null_mut(),
+            key.cipher_key.as_ptr(),
+            iv.as_ptr(),
+        ) != 1
+            || init_hmac(key) != 1
+        {
+            return -1;
+        }
+        1
+    } else {
+        let (key, current) = if *key_name == keys.current.name {
+            (&keys.current, true)
+        } else {
+            match &keys.previous {
+                Some(key) if *key_name == key.name => (key, false),
+                // unknown key; perform a full handshake
+                _ => return 0,
+            }
+        };
+        if openssl_sys::EVP_DecryptInit_ex(
+            cipher_ctx,
+            openssl_sys::EVP_aes_256_cbc(),
+            ptr::null_mut(),
+            key.cipher_key.as_ptr(),
+            iv,
+        ) != 1
+            || init_hmac(key) != 1
+        {
+            return -1;
+        }
+        // tickets encrypted with the previous key are renewed
+        if current {
+            1
+        } else {
+            2
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{self, Instant, Sleep};
+
+/// An inactivity timeout for one direction of an [`SslStream`](crate::SslStream).
+///
+/// The timer is armed when an operation first returns `Pending` and disarmed whenever one
+/// completes, so it bounds the time spent waiting without progress rather than the total time.
+#[derive(Debug, Default)]
+pub(crate) struct Timeout {
+    duration: Option<Duration>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    armed: bool,
+}
+
+impl Timeout {
+    pub(crate) fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    pub(crate) fn set_duration(&mut self, duration: Option<Duration>) {
+        self.duration = duration;
+        self.armed = false;
+    }
+
+    /// Passes `r` through, replacing a `Pending` result with a `TimedOut` error once the timeout
+    /// has elapsed without progress.
+    pub(crate) fn poll<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        r: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        let duration = match (self.duration, &r) {
+            (Some(duration), Poll::Pending) => duration,
+            _ => {
+                self.armed = false;
+                return r;
+            }
+        };
+
+        let deadline = Instant::now() + duration;
+        let sleep = self
+            .sleep
+            .get_or_insert_with(|| Box::pin(time::sleep_until(deadline)));
+        if !self.armed {
+            sleep.as_mut().reset(deadline);
+            self.armed = true;
+        }
+
+        match sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                self.armed = false;
+                Poll::Ready(Err(io::Error::from(io::ErrorKind::TimedOut)))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
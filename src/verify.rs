@@ -0,0 +1,200 @@
+//! Asynchronous verification of peer certificate chains.
+
+use crate::{Error, SslStream};
+use openssl::ssl::SslRef;
+use openssl::x509::X509;
+use std::error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// The future returned by an asynchronous certificate verification callback.
+///
+/// An error rejects the peer, and is returned by the handshake as [`Error::Verify`].
+pub type VerifyFuture =
+    Pin<Box<dyn Future<Output = Result<(), Box<dyn error::Error + Send + Sync>>> + Send>>;
+
+pub(crate) type VerifyCallback = dyn Fn(Vec<X509>) -> VerifyFuture + Send + Sync;
+
+/// Returns the peer's certificate chain, leaf first.
+pub(crate) fn peer_chain(ssl: &SslRef) -> Vec<X509> {
+    #[cfg(ossl110)]
+    if let Some(chain) = ssl.verified_chain() {
+        return chain.iter().map(|c| c.to_owned()).collect();
+    }
+
+    // a server's view of the chain doesn't include the leaf
+    let mut chain = vec![];
+    if ssl.is_server() {
+        chain.extend(ssl.peer_certificate());
+    }
+    if let Some(certs) = ssl.peer_cert_chain() {
+        chain.extend(certs.iter().map(|c| c.to_owned()));
+    }
+    chain
+}
+
+/// Runs `callback` once the handshake of `stream` has completed.
+///
+/// Resumed sessions and peers which didn't present a certificate are not checked.
+pub(crate) async fn verify_after_handshake<S>(
+    callback: &Arc<VerifyCallback>,
+    stream: &SslStream<S>,
+) -> Result<(), Error> {
+    if stream.ssl().session_reused() {
+        return Ok(());
+    }
+
+    let chain = peer_chain(stream.ssl());
+    if chain.is_empty() {
+        return Ok(());
+    }
+    callback(chain).await.map_err(Error::Verify)
+}
+
+/// Suspends client handshakes during certificate verification with `SSL_set_retry_verify`, so
+/// the chain can be verified asynchronously before the handshake continues.
+#[cfg(ossl300)]
+pub(crate) mod retry {
+    use super::{peer_chain, VerifyCallback};
+    use crate::ex_data::ExIndex;
+    use crate::Error;
+    use foreign_types::ForeignTypeRef;
+    use openssl::error::ErrorStack;
+    use openssl::ssl::{ErrorCode, Ssl, SslConnectorBuilder, SslRef};
+    use openssl::x509::{X509StoreContext, X509StoreContextRef, X509VerifyResult, X509};
+    use openssl_sys::{SSL, SSL_CTX, X509_STORE_CTX};
+    use std::error;
+    use std::io;
+    use std::mem;
+    use std::os::raw::{c_int, c_void};
+    use std::sync::{Arc, Mutex};
+
+    // from `<openssl/ssl.h>`, which openssl-sys doesn't expose
+    pub(crate) const SSL_ERROR_WANT_RETRY_VERIFY: c_int = 12;
+
+    extern "C" {
+        fn SSL_CTX_set_cert_verify_callback(
+            ctx: *mut SSL_CTX,
+            cb: Option<unsafe extern "C" fn(*mut X509_STORE_CTX, *mut c_void) -> c_int>,
+            arg: *mut c_void,
+        );
+        fn SSL_set_retry_verify(ssl: *mut SSL) -> c_int;
+    }
+
+    enum State {
+        Start,
+        Paused(Vec<X509>),
+        Done(Result<(), Box<dyn error::Error + Send + Sync>>),
+        Rejected(Box<dyn error::Error + Send + Sync>),
+        Finished,
+    }
+
+    static INDEX: ExIndex<Ssl, Mutex<State>> = ExIndex::new();
+
+    pub(crate) fn install(builder: &mut SslConnectorBuilder) -> Result<(), ErrorStack> {
+        INDEX.index()?;
+        // SAFETY: the callback has the signature expected by OpenSSL and uses no argument.
+        unsafe {
+            SSL_CTX_set_cert_verify_callback(
+                builder.as_ptr(),
+                Some(cert_verify_callback),
+                std::ptr::null_mut(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Prepares `ssl` to suspend its handshake for verification.
+    pub(crate) fn init(ssl: &mut SslRef) -> Result<(), ErrorStack> {
+        ssl.set_ex_data(INDEX.index()?, Mutex::new(State::Start));
+        Ok(())
+    }
+
+    pub(crate) fn is_paused(e: &openssl::ssl::Error) -> bool {
+        e.code() == ErrorCode::from_raw(SSL_ERROR_WANT_RETRY_VERIFY)
+    }
+
+    /// Verifies the chain captured when the handshake of `ssl` was suspended.
+    pub(crate) async fn resume(callback: &Arc<VerifyCallback>, ssl: &SslRef) -> Result<(), Error> {
+        let state = match ssl.ex_data(INDEX.index()?) {
+            Some(state) => state,
+            None => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::Other,
+                    "the connection was not prepared for asynchronous verification",
+                )))
+            }
+        };
+        let chain = match mem::replace(&mut *state.lock().unwrap(), State::Start) {
+            State::Paused(chain) => chain,
+            _ => peer_chain(ssl),
+        };
+        let result = callback(chain).await;
+        *state.lock().unwrap() = State::Done(result);
+        Ok(())
+    }
+
+    /// Returns the error returned by the verification callback if it rejected the peer of `ssl`.
+    pub(crate) fn take_rejection(ssl: &SslRef) -> Option<Error> {
+        let state = ssl.ex_data(INDEX.get()?)?;
+        let mut state = state.lock().unwrap();
+        match mem::replace(&mut *state, State::Finished) {
+            State::Rejected(e) => Some(Error::Verify(e)),
+            other => {
+                *state = other;
+                None
+            }
+        }
+    }
+
+    unsafe extern "C" fn cert_verify_callback(ctx: *mut X509_STORE_CTX, _: *mut c_void) -> c_int {
+        let r = openssl_sys::X509_verify_cert(ctx);
+        if r <= 0 {
+            return r;
+        }
+
+        let ctx = X509StoreContextRef::from_ptr_mut(ctx);
+        let ssl = match X509StoreContext::ssl_idx()
+            .ok()
+            .and_then(|index| ctx.ex_data(index))
+        {
+            // detach the reference from `ctx` so that its error can be set
+            Some(ssl) => SslRef::from_ptr(ssl.as_ptr()),
+            None => return 0,
+        };
+        // fail closed for connections which weren't created by `SslStreamConnector`
+        let state = match INDEX.get().and_then(|index| ssl.ex_data(index)) {
+            Some(state) => state,
+            None => {
+                ctx.set_error(X509VerifyResult::APPLICATION_VERIFICATION);
+                return 0;
+            }
+        };
+        let mut state = match state.lock() {
+            Ok(state) => state,
+            Err(_) => return 0,
+        };
+        match mem::replace(&mut *state, State::Finished) {
+            State::Start => {
+                let chain = ctx
+                    .chain()
+                    .map(|chain| chain.iter().map(|c| c.to_owned()).collect())
+                    .unwrap_or_default();
+                *state = State::Paused(chain);
+                SSL_set_retry_verify(ssl.as_ptr())
+            }
+            State::Done(Ok(())) => 1,
+            State::Done(Err(e)) => {
+                *state = State::Rejected(e);
+                ctx.set_error(X509VerifyResult::APPLICATION_VERIFICATION);
+                0
+            }
+            // only the initial handshake is verified
+            _ => {
+                ctx.set_error(X509VerifyResult::APPLICATION_VERIFICATION);
+                0
+            }
+        }
+    }
+}
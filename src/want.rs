@@ -0,0 +1,40 @@
+use foreign_types::ForeignTypeRef;
+use openssl::ssl::{ErrorCode, SslRef};
+use std::os::raw::c_int;
+
+// The `SSL_want` states from `<openssl/ssl.h>`, which openssl-sys doesn't expose.
+const SSL_WRITING: c_int = 2;
+const SSL_READING: c_int = 3;
+const SSL_X509_LOOKUP: c_int = 4;
+#[cfg(ossl110)]
+const SSL_ASYNC_PAUSED: c_int = 5;
+#[cfg(ossl110)]
+const SSL_ASYNC_NO_JOBS: c_int = 6;
+#[cfg(ossl111)]
+const SSL_CLIENT_HELLO_CB: c_int = 7;
+#[cfg(ossl300)]
+const SSL_RETRY_VERIFY: c_int = 8;
+
+extern "C" {
+    // declared in `<openssl/ssl.h>`, but not by openssl-sys
+    fn SSL_want(ssl: *const openssl_sys::SSL) -> c_int;
+}
+
+/// Returns the `SSL_ERROR_WANT_*` code matching the operation OpenSSL is waiting on, or
+/// `ErrorCode::NONE` if it isn't waiting.
+pub(crate) fn want(ssl: &SslRef) -> ErrorCode {
+    match unsafe { SSL_want(ssl.as_ptr()) } {
+        SSL_WRITING => ErrorCode::WANT_WRITE,
+        SSL_READING => ErrorCode::WANT_READ,
+        SSL_X509_LOOKUP => ErrorCode::WANT_X509_LOOKUP,
+        #[cfg(ossl110)]
+        SSL_ASYNC_PAUSED => ErrorCode::from_raw(openssl_sys::SSL_ERROR_WANT_ASYNC),
+        #[cfg(ossl110)]
+        SSL_ASYNC_NO_JOBS => ErrorCode::from_raw(openssl_sys::SSL_ERROR_WANT_ASYNC_JOB),
+        #[cfg(ossl111)]
+        SSL_CLIENT_HELLO_CB => ErrorCode::WANT_CLIENT_HELLO_CB,
+        #[cfg(ossl300)]
+        SSL_RETRY_VERIFY => ErrorCode::from_raw(crate::verify::retry::SSL_ERROR_WANT_RETRY_VERIFY),
+        _ => ErrorCode::NONE,
+    }
+}